@@ -0,0 +1,121 @@
+//! Activity logs for roles and the site
+//!
+//! Every change to a role or its puzzles is recorded, along with who made it
+//! and which role they were acting as at the time, in both the site-wide
+//! activity log and the role's own.  Members of a role may read its log, and
+//! site administrators may read the site-wide one.
+//!
+//! Readers solving, rating, following, or commenting on puzzles are not
+//! changing the role, so none of that is recorded here.
+
+use std::time::SystemTime;
+
+use axum::{http::StatusCode, Extension, Json};
+use linkdoku_common::ActivityEntry;
+
+use crate::{
+    auth::{AdminUser, AuthUser, RoleMember},
+    dbconn::{Database, Identity},
+};
+
+/// How many entries an activity view shows
+const ACTIVITY_VIEW_LENGTH: usize = 200;
+
+/// Who made a change
+pub struct Actor {
+    identity: String,
+    active_role: Option<String>,
+}
+
+impl Actor {
+    /// A change made by an authenticated user
+    pub fn user(user: &AuthUser) -> Self {
+        Self {
+            identity: user.identity().uuid().to_string(),
+            active_role: user.active_role().map(String::from),
+        }
+    }
+
+    /// A change made on an identity's behalf while they were logging in
+    pub fn identity(identity: &Identity) -> Self {
+        Self {
+            identity: identity.uuid().to_string(),
+            active_role: None,
+        }
+    }
+}
+
+/// Summarise changes to fields as `field: old → new`, skipping any which
+/// didn't actually change
+pub fn diff(changes: &[(&str, &str, &str)]) -> String {
+    let shown = |value: &str| {
+        if value.is_empty() {
+            "(none)".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    changes
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| format!("{}: {} → {}", field, shown(old), shown(new)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Record a change to a role or one of its puzzles
+///
+/// Failing to record a change doesn't undo it, so failures are only logged.
+pub async fn record(
+    dbconn: &mut Database,
+    actor: &Actor,
+    role: &str,
+    action: &str,
+    target: &str,
+    summary: String,
+) {
+    let entry = ActivityEntry {
+        when: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        actor: actor.identity.clone(),
+        active_role: actor.active_role.clone(),
+        role: role.to_string(),
+        action: action.to_string(),
+        target: target.to_string(),
+        summary,
+    };
+    if let Err(e) = dbconn.log_activity(&entry).await {
+        tracing::error!("Unable to record activity {:?}: {:?}", entry, e);
+    }
+}
+
+pub async fn role_activity(
+    RoleMember { role, .. }: RoleMember,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<Json<Vec<ActivityEntry>>, StatusCode> {
+    match dbconn
+        .role_activity(role.uuid(), ACTIVITY_VIEW_LENGTH)
+        .await
+    {
+        Ok(entries) => Ok(Json::from(entries)),
+        Err(e) => {
+            tracing::error!("Failure reading activity for {}: {:?}", role.uuid(), e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn site_activity(
+    AdminUser(_): AdminUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<Json<Vec<ActivityEntry>>, StatusCode> {
+    match dbconn.site_activity(ACTIVITY_VIEW_LENGTH).await {
+        Ok(entries) => Ok(Json::from(entries)),
+        Err(e) => {
+            tracing::error!("Failure reading site activity: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Site administration
+//!
+//! The identities named in the configuration's `admins` may search every
+//! identity, role and puzzle on the site, force abusive puzzles back to being
+//! restricted, and suspend identities.  Suspended identities are logged out
+//! at their next request and cannot log back in, nor use their API tokens.
+//!
+//! Everything an administrator does is recorded in the admin audit log, which
+//! administrators may also read.
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use linkdoku_common::{
+    AdminActionResponse, AdminAuditEntry, AdminIdentity, Puzzle, RestrictPuzzleRequest, RoleData,
+    SuspendIdentityRequest, Visibility,
+};
+use serde::Deserialize;
+
+use crate::{
+    activity::{self, Actor},
+    auth::{self, AdminUser, AuthUser},
+    dbconn::{Database, DatabaseError, DatabaseResult},
+    state::AppState,
+};
+
+/// The most results a search returns
+const SEARCH_LIMIT: usize = 50;
+
+/// How much of the audit log we show
+const AUDIT_LOG_LENGTH: usize = 200;
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    #[serde(default)]
+    q: String,
+}
+
+impl SearchQuery {
+    /// Whether any of the given fields contain the query, ignoring case
+    fn matches(&self, fields: &[&str]) -> bool {
+        let query = self.q.trim().to_lowercase();
+        query.is_empty() || fields.iter().any(|f| f.to_lowercase().contains(&query))
+    }
+}
+
+fn search_failure(e: DatabaseError) -> StatusCode {
+    tracing::error!("Failure searching as administrator: {:?}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+async fn find_identities(
+    dbconn: &mut Database,
+    state: &AppState,
+    query: &SearchQuery,
+) -> DatabaseResult<Vec<AdminIdentity>> {
+    let mut ret = Vec::new();
+    for uuid in dbconn.all_identities().await? {
+        let identity = match dbconn.identity_by_uuid(&uuid).await? {
+            Some(identity) => identity,
+            None => continue,
+        };
+        let subjects = dbconn.identity_subjects(&uuid).await?;
+        let matched = {
+            let mut fields = vec![
+                identity.uuid(),
+                identity.display_name(),
+                identity.provider_name(),
+            ];
+            fields.extend(subjects.iter().map(String::as_str));
+            query.matches(&fields)
+        };
+        if !matched {
+            continue;
+        }
+        ret.push(AdminIdentity {
+            display_name: identity.display_name().to_string(),
+            provider_name: identity.provider_name().to_string(),
+            subjects,
+            roles: dbconn.identity_roles(&uuid).await?,
+            suspended: dbconn.identity_suspension(&uuid).await?,
+            admin: auth::is_admin(state, &uuid),
+            uuid,
+        });
+        if ret.len() >= SEARCH_LIMIT {
+            break;
+        }
+    }
+    Ok(ret)
+}
+
+async fn search_identities(
+    AdminUser(_): AdminUser,
+    Query(query): Query<SearchQuery>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Result<Json<Vec<AdminIdentity>>, StatusCode> {
+    find_identities(&mut dbconn, &state, &query)
+        .await
+        .map(Json::from)
+        .map_err(search_failure)
+}
+
+async fn find_roles(dbconn: &mut Database, query: &SearchQuery) -> DatabaseResult<Vec<RoleData>> {
+    let mut roles: Vec<_> = dbconn.all_roles().await?.into_iter().collect();
+    roles.sort();
+    let mut ret = Vec::new();
+    for (_, uuid) in roles {
+        let role = match dbconn.role_by_uuid_or_short_name(&uuid).await {
+            Ok(role) => role,
+            Err(DatabaseError::NotFound(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        if query.matches(&[role.uuid(), role.short_name(), role.display_name()]) {
+            ret.push(role.as_api_role());
+            if ret.len() >= SEARCH_LIMIT {
+                break;
+            }
+        }
+    }
+    Ok(ret)
+}
+
+async fn search_roles(
+    AdminUser(_): AdminUser,
+    Query(query): Query<SearchQuery>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<Json<Vec<RoleData>>, StatusCode> {
+    find_roles(&mut dbconn, &query)
+        .await
+        .map(Json::from)
+        .map_err(search_failure)
+}
+
+async fn find_puzzles(dbconn: &mut Database, query: &SearchQuery) -> DatabaseResult<Vec<Puzzle>> {
+    let mut puzzles: Vec<_> = dbconn.all_puzzles().await?.into_iter().collect();
+    puzzles.sort();
+    let mut ret = Vec::new();
+    for (_, uuid) in puzzles {
+        let puzzle = match dbconn.puzzle_by_uuid_or_short_name(&uuid).await {
+            Ok(puzzle) => puzzle,
+            Err(DatabaseError::NotFound(_)) => continue,
+            Err(e) => return Err(e),
+        };
+        if query.matches(&[
+            puzzle.uuid(),
+            puzzle.short_name(),
+            puzzle.display_name(),
+            puzzle.owner(),
+        ]) {
+            // Administrators see puzzles as their owners do
+            ret.push(puzzle.as_api_puzzle(true));
+            if ret.len() >= SEARCH_LIMIT {
+                break;
+            }
+        }
+    }
+    Ok(ret)
+}
+
+async fn search_puzzles(
+    AdminUser(_): AdminUser,
+    Query(query): Query<SearchQuery>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<Json<Vec<Puzzle>>, StatusCode> {
+    find_puzzles(&mut dbconn, &query)
+        .await
+        .map(Json::from)
+        .map_err(search_failure)
+}
+
+/// Record an administrator's action, failing to do so doesn't undo the action
+async fn audit(dbconn: &mut Database, admin: &AuthUser, action: &str, target: &str, detail: &str) {
+    if let Err(e) = dbconn
+        .log_admin_action(admin.identity().uuid(), action, target, detail)
+        .await
+    {
+        tracing::error!(
+            "Unable to audit {} of {} by {}: {:?}",
+            action,
+            target,
+            admin.identity().uuid(),
+            e
+        );
+    }
+}
+
+async fn restrict_puzzle(
+    AdminUser(admin): AdminUser,
+    Json(request): Json<RestrictPuzzleRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<AdminActionResponse> {
+    let mut puzzle = match dbconn.puzzle_by_uuid_or_short_name(&request.puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(DatabaseError::NotFound(_)) => return AdminActionResponse::NotFound.into(),
+        Err(e) => return AdminActionResponse::DatabaseFailure(e.to_string()).into(),
+    };
+    let old_visibility = puzzle.visibility();
+    if let Err(e) = dbconn
+        .set_puzzle_visibility(&mut puzzle, Visibility::Restricted)
+        .await
+    {
+        return AdminActionResponse::DatabaseFailure(e.to_string()).into();
+    }
+    activity::record(
+        &mut dbconn,
+        &Actor::user(&admin),
+        puzzle.owner(),
+        "puzzle-visibility",
+        puzzle.uuid(),
+        activity::diff(&[(
+            "visibility",
+            &format!("{:?}", old_visibility),
+            &format!("{:?}", Visibility::Restricted),
+        )]),
+    )
+    .await;
+    audit(
+        &mut dbconn,
+        &admin,
+        "restrict-puzzle",
+        puzzle.uuid(),
+        request.reason.trim(),
+    )
+    .await;
+    AdminActionResponse::Success.into()
+}
+
+async fn suspend_identity(
+    AdminUser(admin): AdminUser,
+    Json(request): Json<SuspendIdentityRequest>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<AdminActionResponse> {
+    let reason = request.reason.as_deref().map(str::trim);
+    if reason.is_some() && auth::is_admin(&state, &request.identity) {
+        return AdminActionResponse::CannotSuspendAdmin.into();
+    }
+    match dbconn.identity_by_uuid(&request.identity).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return AdminActionResponse::NotFound.into(),
+        Err(e) => return AdminActionResponse::DatabaseFailure(e.to_string()).into(),
+    }
+    if let Err(e) = dbconn
+        .set_identity_suspension(&request.identity, reason)
+        .await
+    {
+        return AdminActionResponse::DatabaseFailure(e.to_string()).into();
+    }
+    let action = match reason {
+        Some(_) => "suspend-identity",
+        None => "unsuspend-identity",
+    };
+    audit(
+        &mut dbconn,
+        &admin,
+        action,
+        &request.identity,
+        reason.unwrap_or_default(),
+    )
+    .await;
+    AdminActionResponse::Success.into()
+}
+
+async fn audit_log(
+    AdminUser(_): AdminUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<Json<Vec<AdminAuditEntry>>, StatusCode> {
+    match dbconn.admin_audit_log(AUDIT_LOG_LENGTH).await {
+        Ok(entries) => Ok(Json::from(entries)),
+        Err(e) => {
+            tracing::error!("Failure reading admin audit log: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/identities", get(search_identities))
+        .route("/roles", get(search_roles))
+        .route("/puzzles", get(search_puzzles))
+        .route("/puzzle/restrict", post(restrict_puzzle))
+        .route("/identity/suspend", post(suspend_identity))
+        .route("/audit", get(audit_log))
+        .route("/activity", get(activity::site_activity))
+}
@@ -0,0 +1,333 @@
+//! Atom feeds of published puzzles
+//!
+//! These are built by hand rather than with a feed crate, since Atom is a
+//! small format and we only ever need to produce it.
+
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use linkdoku_common::{url_scheme_allowed, PuzzleData, Visibility, SAFE_URL_SCHEMES};
+use pulldown_cmark::{html, BrokenLink, CowStr, Event, LinkType, Options, Parser, Tag};
+use serde_json::Value;
+
+use crate::{
+    dbconn::{self, Database},
+    state::AppState,
+};
+
+/// How many entries to put in a feed
+const FEED_LENGTH: usize = 20;
+
+const ATOM_CONTENT_TYPE: &str = "application/atom+xml; charset=utf-8";
+
+/// Escape text for inclusion in XML (or HTML) content or attributes
+pub(crate) fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Format seconds since the epoch as an RFC3339 timestamp in UTC
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // Civil from days, per Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem / 60) % 60,
+        rem % 60
+    )
+}
+
+/// Play links for a grid, in the same forms the frontend offers
+fn play_links(grid: &Value) -> Vec<(&'static str, &'static str, String)> {
+    let json_data = serde_json::to_string(grid).expect("Odd, JSON encoding failed?");
+    let data_str = lz_str::compress_to_base64(&json_data);
+    vec![
+        (
+            "fpuzzles",
+            "Play this on F-Puzzles",
+            format!("http://f-puzzles.com/?load={}", data_str),
+        ),
+        (
+            "sudokupad",
+            "Play this on Sudokupad",
+            format!("https://sudokupad.app/fpuzzles{}", data_str),
+        ),
+        (
+            "sudokupad-beta",
+            "Play this on Sudokupad (beta)",
+            format!("https://beta.sudokupad.app/fpuzzles{}", data_str),
+        ),
+    ]
+}
+
+/// Whether a link or image in a description may be kept, as the frontend
+/// decides when it renders descriptions
+fn tag_allowed(tag: &Tag) -> bool {
+    match tag {
+        Tag::Link(LinkType::Email, url, _) => {
+            url_scheme_allowed(&format!("mailto:{}", url), SAFE_URL_SCHEMES)
+        }
+        Tag::Link(_, url, _) | Tag::Image(_, url, _) => url_scheme_allowed(url, SAFE_URL_SCHEMES),
+        _ => true,
+    }
+}
+
+/// Render a puzzle's description into HTML for a feed entry
+///
+/// Linkdoku's special links are turned into play links where we can, and into
+/// links to the puzzle page otherwise.  Raw HTML in the description is dropped,
+/// as are links and images to URLs the frontend wouldn't allow, leaving their
+/// text behind.
+fn render_description<'a>(puzzle_url: &str, description: &'a str, data: &PuzzleData) -> String {
+    let links = match data {
+        PuzzleData::FPuzzles(grid) => play_links(grid),
+        _ => Vec::new(),
+    };
+
+    let mut callback = |link: BrokenLink<'a>| -> Option<(CowStr<'a>, CowStr<'a>)> {
+        let target = match &*link.reference {
+            "beta-sudokupad" => "sudokupad-beta",
+            other => other,
+        };
+        let url = links
+            .iter()
+            .find(|(name, _, _)| *name == target)
+            .map(|(_, _, url)| url.clone())
+            .unwrap_or_else(|| puzzle_url.to_string());
+        Some((CowStr::from(url), link.reference.clone()))
+    };
+
+    let parser = Parser::new_with_broken_link_callback(
+        description,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+        Some(&mut callback),
+    )
+    .filter(|event| match event {
+        Event::Html(_) => false,
+        Event::Start(tag) | Event::End(tag) => tag_allowed(tag),
+        _ => true,
+    });
+
+    let mut ret = String::new();
+    html::push_html(&mut ret, parser);
+
+    if !links.is_empty() {
+        ret.push_str("<p>");
+        for (_, label, url) in &links {
+            ret.push_str(&format!(
+                "<a href=\"{}\">{}</a><br />",
+                escape(url),
+                escape(label)
+            ));
+        }
+        ret.push_str("</p>");
+    }
+    ret
+}
+
+/// Render a single published puzzle as an Atom entry
+fn render_entry(base: &str, puzzle: &dbconn::Puzzle, published: u64) -> Option<String> {
+    let state = puzzle.as_api_puzzle(false).states.pop()?;
+    let puzzle_url = format!("{}/-/puzzle/{}", base, puzzle.short_name());
+    let permalink = format!("{}/-/puzzle/{}", base, puzzle.uuid());
+    let content = render_description(&puzzle_url, &state.description, &state.data);
+    Some(format!(
+        concat!(
+            "<entry>",
+            "<id>{id}</id>",
+            "<title>{title}</title>",
+            "<link rel=\"alternate\" type=\"text/html\" href=\"{link}\" />",
+            "<published>{published}</published>",
+            "<updated>{published}</updated>",
+            "<content type=\"html\">{content}</content>",
+            "</entry>\n"
+        ),
+        id = escape(&permalink),
+        title = escape(puzzle.display_name()),
+        link = escape(&puzzle_url),
+        published = rfc3339(published),
+        content = escape(&content),
+    ))
+}
+
+/// Wrap rendered entries into a complete Atom document
+fn render_feed(
+    base: &str,
+    self_path: &str,
+    title: &str,
+    alternate: &str,
+    entries: &[(String, u64)],
+) -> String {
+    let updated = entries.iter().map(|(_, when)| *when).max().unwrap_or(0);
+    let mut ret = String::new();
+    ret.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    ret.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    ret.push_str(&format!(
+        "<id>{}</id>\n",
+        escape(&format!("{}{}", base, self_path))
+    ));
+    ret.push_str(&format!("<title>{}</title>\n", escape(title)));
+    ret.push_str(&format!("<updated>{}</updated>\n", rfc3339(updated)));
+    ret.push_str(&format!(
+        "<link rel=\"self\" type=\"application/atom+xml\" href=\"{}\" />\n",
+        escape(&format!("{}{}", base, self_path))
+    ));
+    ret.push_str(&format!(
+        "<link rel=\"alternate\" type=\"text/html\" href=\"{}\" />\n",
+        escape(&format!("{}{}", base, alternate))
+    ));
+    ret.push_str("<generator>Linkdoku</generator>\n");
+    for (entry, _) in entries {
+        ret.push_str(entry);
+    }
+    ret.push_str("</feed>\n");
+    ret
+}
+
+/// Load and render entries for the given puzzles and their times of
+/// publication, skipping any which are no longer published
+async fn published_entries(
+    dbconn: &mut Database,
+    base: &str,
+    puzzles: Vec<(String, u64)>,
+) -> Vec<(String, u64)> {
+    let mut ret = Vec::new();
+    for (uuid, published) in puzzles {
+        let puzzle = match dbconn.puzzle_by_uuid_or_short_name(&uuid).await {
+            Ok(puzzle) => puzzle,
+            Err(_) => continue,
+        };
+        if puzzle.visibility() != Visibility::Published {
+            continue;
+        }
+        if let Some(entry) = render_entry(base, &puzzle, published) {
+            ret.push((entry, published));
+        }
+    }
+    ret
+}
+
+fn atom_response(body: String) -> Response {
+    ([(header::CONTENT_TYPE, ATOM_CONTENT_TYPE)], body).into_response()
+}
+
+pub async fn site_feed(
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Response {
+    let base = &state.base_url;
+    let puzzles = match dbconn.recently_published(FEED_LENGTH as isize).await {
+        Ok(puzzles) => puzzles,
+        Err(e) => {
+            tracing::error!("Unable to build site feed: {:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let entries = published_entries(&mut dbconn, base, puzzles).await;
+    atom_response(render_feed(
+        base,
+        "/api/feed.atom",
+        "Linkdoku - Recently published puzzles",
+        "/-/",
+        &entries,
+    ))
+}
+
+pub async fn role_feed(
+    Path(role): Path<String>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Response {
+    let base = &state.base_url;
+    let role = match dbconn.role_by_uuid_or_short_name(&role).await {
+        Ok(role) => role,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let puzzles = match dbconn.role_published(role.uuid(), FEED_LENGTH).await {
+        Ok(puzzles) => puzzles,
+        Err(e) => {
+            tracing::error!("Unable to build feed for role {}: {:?}", role.uuid(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let entries = published_entries(&mut dbconn, base, puzzles).await;
+    atom_response(render_feed(
+        base,
+        &format!("/api/role/feed/{}", role.short_name()),
+        &format!("Linkdoku - Puzzles by {}", role.display_name()),
+        &format!("/-/role/{}", role.short_name()),
+        &entries,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(description: &str) -> String {
+        render_description(
+            "https://example.com/-/puzzle/a-puzzle",
+            description,
+            &PuzzleData::Nothing,
+        )
+    }
+
+    #[test]
+    fn unsafe_urls_are_dropped() {
+        for description in [
+            "[click](javascript:alert(1))",
+            "[click](JaVaScRiPt:alert(1))",
+            "[click][evil]\n\n[evil]: javascript:alert(1)",
+            "![pic](data:image/svg+xml;base64,PHN2Zz48L3N2Zz4=)",
+            "[click](vbscript:msgbox)",
+            "<script>alert(1)</script>",
+        ] {
+            let html = render(description);
+            assert!(!html.contains("href="), "{:?} gave {}", description, html);
+            assert!(!html.contains("<img"), "{:?} gave {}", description, html);
+            assert!(!html.contains("<script"), "{:?} gave {}", description, html);
+        }
+        assert_eq!(render("[click](javascript:alert(1))"), "<p>click</p>\n");
+    }
+
+    #[test]
+    fn safe_urls_are_kept() {
+        assert_eq!(
+            render("[site](https://example.com/rules)"),
+            "<p><a href=\"https://example.com/rules\">site</a></p>\n"
+        );
+        assert_eq!(
+            render("[sudokupad]"),
+            "<p><a href=\"https://example.com/-/puzzle/a-puzzle\" title=\"sudokupad\">sudokupad</a></p>\n"
+        );
+        assert!(render("![grid](https://example.com/grid.png)")
+            .contains("<img src=\"https://example.com/grid.png\""));
+    }
+}
@@ -0,0 +1,377 @@
+//! Authentication of API requests
+//!
+//! Requests are authenticated either by the login cookie set up by the OIDC
+//! flow in [`crate::login`], or by a personal API token presented as an
+//! `Authorization: Bearer` header.  Tokens are scoped to a subset of their
+//! identity's roles, and read-only tokens are only accepted for GET requests.
+//!
+//! Handlers take [`Auth`] as an extractor and need not care which was used.
+//! Where a handler needs a logged in user, [`LoggedInUser`] and [`RoleMember`]
+//! reject anyone else with an appropriate status before the handler runs, as
+//! does [`AdminUser`] for the site administrators named in the configuration.
+//! Suspended identities are treated as logged out, and their tokens refused.
+
+use std::collections::HashMap;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, Path, RequestParts},
+    http::{header::AUTHORIZATION, HeaderMap, Method, StatusCode},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use linkdoku_common::{
+    ApiTokenInfo, CreateApiTokenRequest, CreateApiTokenResponse, RevokeApiTokenRequest,
+    RevokeApiTokenResponse,
+};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tower_cookies::Cookies;
+
+use crate::{
+    dbconn::{Database, Identity, Role},
+    login::login_flow_status,
+    state::AppState,
+};
+
+/// Prefix on API tokens, so that they are recognisable if they leak
+const TOKEN_PREFIX: &str = "ldk_";
+
+/// The longest name we permit for an API token
+const MAX_TOKEN_NAME_LENGTH: usize = 100;
+
+/// An authenticated user, however they authenticated
+pub struct AuthUser {
+    identity: Identity,
+    roles: Vec<String>,
+    active_role: Option<String>,
+    via_token: bool,
+}
+
+impl AuthUser {
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    /// Whether the user may act as the given role
+    ///
+    /// For token users this is limited to the roles the token was scoped to
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// The role the user has chosen to act as, token users have none
+    pub fn active_role(&self) -> Option<&str> {
+        self.active_role.as_deref()
+    }
+
+    /// Whether the user authenticated with an API token rather than a login
+    pub fn via_token(&self) -> bool {
+        self.via_token
+    }
+}
+
+/// The authentication state of a request
+pub struct Auth {
+    user: Option<AuthUser>,
+}
+
+impl Auth {
+    pub fn user(&self) -> Option<&AuthUser> {
+        self.user.as_ref()
+    }
+}
+
+/// The API token the request presents, if any
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+async fn token_user(
+    dbconn: &mut Database,
+    token: &str,
+) -> Result<(AuthUser, ApiTokenInfo), (StatusCode, &'static str)> {
+    const INVALID: (StatusCode, &str) = (StatusCode::UNAUTHORIZED, "Invalid API token");
+    const FAILURE: (StatusCode, &str) = (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Unable to check API token",
+    );
+    let (identity, info) = match dbconn.api_token(&hash_token(token)).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return Err(INVALID),
+        Err(e) => {
+            tracing::error!("Failure looking up API token: {:?}", e);
+            return Err(FAILURE);
+        }
+    };
+    let identity = match dbconn.identity_by_uuid(&identity).await {
+        Ok(Some(identity)) => identity,
+        Ok(None) => return Err(INVALID),
+        Err(e) => {
+            tracing::error!("Failure looking up identity for API token: {:?}", e);
+            return Err(FAILURE);
+        }
+    };
+    // Deletion removes the identity's tokens, but a lookup may race with it
+    match dbconn.identity_deleted(identity.uuid()).await {
+        Ok(false) => {}
+        Ok(true) => return Err(INVALID),
+        Err(e) => {
+            tracing::error!("Failure checking deletion for API token: {:?}", e);
+            return Err(FAILURE);
+        }
+    }
+    match dbconn.identity_suspension(identity.uuid()).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return Err((StatusCode::FORBIDDEN, "Identity suspended")),
+        Err(e) => {
+            tracing::error!("Failure checking suspension for API token: {:?}", e);
+            return Err(FAILURE);
+        }
+    }
+    // The identity may have lost roles since the token was created
+    let roles = match dbconn.identity_roles(identity.uuid()).await {
+        Ok(roles) => roles
+            .into_iter()
+            .filter(|r| info.roles.contains(r))
+            .collect(),
+        Err(e) => {
+            tracing::error!("Failure looking up roles for API token: {:?}", e);
+            return Err(FAILURE);
+        }
+    };
+    Ok((
+        AuthUser {
+            identity,
+            roles,
+            active_role: None,
+            via_token: true,
+        },
+        info,
+    ))
+}
+
+async fn database<B: Send>(
+    req: &mut RequestParts<B>,
+) -> Result<Database, (StatusCode, &'static str)> {
+    let Extension(dbconn) = Extension::<Database>::from_request(req)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "No database"))?;
+    Ok(dbconn)
+}
+
+async fn app_state<B: Send>(
+    req: &mut RequestParts<B>,
+) -> Result<AppState, (StatusCode, &'static str)> {
+    let Extension(state) = Extension::<AppState>::from_request(req)
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "No application state"))?;
+    Ok(state)
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Auth
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(req.headers()) {
+            let mut dbconn = database(req).await?;
+            let (user, info) = token_user(&mut dbconn, &token).await?;
+            if !info.write && !matches!(*req.method(), Method::GET | Method::HEAD) {
+                return Err((StatusCode::FORBIDDEN, "API token is read-only"));
+            }
+            return Ok(Auth { user: Some(user) });
+        }
+
+        let cookies = Cookies::from_request(req).await?;
+        let state = app_state(req).await?;
+        let mut dbconn = database(req).await?;
+        let flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
+        Ok(Auth {
+            user: flow.user().map(|user| AuthUser {
+                identity: user.identity().clone(),
+                roles: user.roles().to_vec(),
+                active_role: Some(user.active_role().to_string()),
+                via_token: false,
+            }),
+        })
+    }
+}
+
+/// A request from a logged in user, anyone else is rejected as unauthorized
+pub struct LoggedInUser(pub AuthUser);
+
+#[async_trait]
+impl<B> FromRequest<B> for LoggedInUser
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match Auth::from_request(req).await?.user {
+            Some(user) => Ok(LoggedInUser(user)),
+            None => Err((StatusCode::UNAUTHORIZED, "Not logged in")),
+        }
+    }
+}
+
+/// A request from a user who may act as the role named by the route's `:role`
+///
+/// Unknown roles are rejected as not found, and roles the user may not act as
+/// are forbidden.
+pub struct RoleMember {
+    pub user: AuthUser,
+    pub role: Role,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for RoleMember
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let LoggedInUser(user) = LoggedInUser::from_request(req).await?;
+        let Path(params) = Path::<HashMap<String, String>>::from_request(req)
+            .await
+            .map_err(|_| (StatusCode::BAD_REQUEST, "Bad path parameters"))?;
+        let role = params
+            .get("role")
+            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Route has no role"))?;
+        let mut dbconn = database(req).await?;
+        let role = dbconn
+            .role_by_uuid_or_short_name(role)
+            .await
+            .map_err(|_| (StatusCode::NOT_FOUND, "Unknown role"))?;
+        if !user.has_role(role.uuid()) {
+            return Err((StatusCode::FORBIDDEN, "Not a member of this role"));
+        }
+        Ok(RoleMember { user, role })
+    }
+}
+
+/// A request from a site administrator, as listed in the configuration
+///
+/// Administration may only be done with a real login, not with a token.
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl<B> FromRequest<B> for AdminUser
+where
+    B: Send,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let LoggedInUser(user) = LoggedInUser::from_request(req).await?;
+        if user.via_token() {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "API tokens cannot administer the site",
+            ));
+        }
+        let state = app_state(req).await?;
+        if !is_admin(&state, user.identity().uuid()) {
+            return Err((StatusCode::FORBIDDEN, "Not a site administrator"));
+        }
+        Ok(AdminUser(user))
+    }
+}
+
+/// Whether the given identity is a site administrator
+pub fn is_admin(state: &AppState, identity: &str) -> bool {
+    state.config.admins.iter().any(|admin| admin == identity)
+}
+
+// Tokens may not be used to manage tokens, only a real login will do
+
+async fn list_tokens(
+    LoggedInUser(user): LoggedInUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<Vec<ApiTokenInfo>>> {
+    if user.via_token() {
+        return Json::from(None);
+    }
+    match dbconn.api_tokens(user.identity().uuid()).await {
+        Ok(tokens) => Json::from(Some(tokens)),
+        Err(e) => {
+            tracing::error!("Failure listing API tokens: {:?}", e);
+            Json::from(None)
+        }
+    }
+}
+
+async fn create_token(
+    LoggedInUser(user): LoggedInUser,
+    Json(request): Json<CreateApiTokenRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<CreateApiTokenResponse> {
+    if user.via_token() {
+        return CreateApiTokenResponse::NotLoggedIn.into();
+    }
+    let name = request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_TOKEN_NAME_LENGTH {
+        return CreateApiTokenResponse::InvalidName.into();
+    }
+    if let Some(role) = request.roles.iter().find(|role| !user.has_role(role)) {
+        return CreateApiTokenResponse::UnknownRole(role.clone()).into();
+    }
+
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    let token = format!("{}{}", TOKEN_PREFIX, hex::encode(secret));
+
+    match dbconn
+        .create_api_token(
+            user.identity().uuid(),
+            &hash_token(&token),
+            name,
+            &request.roles,
+            request.write,
+        )
+        .await
+    {
+        Ok(info) => CreateApiTokenResponse::Success { token, info },
+        Err(e) => CreateApiTokenResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn revoke_token(
+    LoggedInUser(user): LoggedInUser,
+    Json(request): Json<RevokeApiTokenRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<RevokeApiTokenResponse> {
+    if user.via_token() {
+        return RevokeApiTokenResponse::NotLoggedIn.into();
+    }
+    match dbconn
+        .revoke_api_token(user.identity().uuid(), &request.id)
+        .await
+    {
+        Ok(true) => RevokeApiTokenResponse::Success,
+        Ok(false) => RevokeApiTokenResponse::UnknownToken,
+        Err(e) => RevokeApiTokenResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/list", get(list_tokens))
+        .route("/create", post(create_token))
+        .route("/revoke", post(revoke_token))
+}
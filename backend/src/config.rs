@@ -7,12 +7,139 @@ use config::{Config, ConfigError, Environment, File, FileFormat};
 use serde::Deserialize;
 use url::Url;
 
+/// Which ID token claims provide the details of a newly logged in identity
+///
+/// Each entry is a list of claim names to try in order, the first which the
+/// provider supplied wins.  The defaults suit most providers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClaimMapping {
+    pub display_name: Vec<String>,
+    pub email: Vec<String>,
+    pub preferred_username: Vec<String>,
+}
+
+impl Default for ClaimMapping {
+    fn default() -> Self {
+        Self {
+            display_name: vec!["name".to_string()],
+            email: vec!["email".to_string()],
+            preferred_username: vec!["preferred_username".to_string()],
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct OpenIDProvider {
     pub client_id: String,
     pub client_secret: String,
     pub discovery_doc: String,
     pub scopes: Vec<String>,
+    /// Shown on the login button, defaults to the provider's name
+    pub label: Option<String>,
+    /// Font Awesome classes for the login button's icon
+    pub icon: Option<String>,
+    /// Overrides the site-wide `redirect_url` for this provider
+    pub redirect_url: Option<String>,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+fn default_subject_claim() -> String {
+    "id".to_string()
+}
+
+/// A plain OAuth2 provider, such as GitHub or Discord, which doesn't offer
+/// OpenID Connect.  Instead of an ID token, the user's details come from the
+/// provider's userinfo endpoint.
+#[derive(Debug, Deserialize)]
+pub struct OAuth2Provider {
+    pub client_id: String,
+    pub client_secret: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub label: Option<String>,
+    pub icon: Option<String>,
+    pub redirect_url: Option<String>,
+    /// The userinfo field which uniquely identifies the user
+    #[serde(default = "default_subject_claim")]
+    pub subject_claim: String,
+    #[serde(default)]
+    pub claims: ClaimMapping,
+}
+
+/// A token bucket, which holds up to `burst` requests and refills at
+/// `per_minute` requests per minute
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+/// Rate limits for each group of API routes, a group with no limit is not
+/// limited at all
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    /// Starting logins, and linking further logins
+    pub login: Option<RateLimit>,
+    /// Anything which changes something, such as creating a puzzle
+    pub write: Option<RateLimit>,
+    /// Everything else
+    pub read: Option<RateLimit>,
+    /// Take the client's address from `X-Forwarded-For`, only set this if
+    /// the backend is behind a proxy which sets it
+    pub trust_forwarded_for: bool,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            login: Some(RateLimit {
+                burst: 10,
+                per_minute: 10,
+            }),
+            write: Some(RateLimit {
+                burst: 30,
+                per_minute: 30,
+            }),
+            read: Some(RateLimit {
+                burst: 300,
+                per_minute: 300,
+            }),
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+/// Security headers sent with everything the backend serves
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SecurityHeaders {
+    /// Replaces the built in content security policy entirely.  The built in
+    /// policy allows the frontend's own inline scripts by hash, a replacement
+    /// must allow them some other way.
+    pub content_security_policy: Option<String>,
+    /// Report content security policy violations rather than enforcing it
+    pub report_only: bool,
+    pub referrer_policy: String,
+    /// How long browsers should insist on HTTPS, in seconds.  This is only
+    /// sent when the site's public address is HTTPS, and zero disables it.
+    pub hsts_max_age: u64,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: None,
+            report_only: false,
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            hsts_max_age: 365 * 24 * 60 * 60,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,22 +150,52 @@ pub struct Configuration {
     pub redirect_url: String,
     pub cookie_secret: String,
     pub openid: HashMap<String, OpenIDProvider>,
+    #[serde(default)]
+    pub oauth2: HashMap<String, OAuth2Provider>,
+    /// Offer the `dev` login provider, which lets anyone log in as anyone.
+    /// Never enable this anywhere but a development or test system.
+    #[serde(default)]
+    pub dev_login: bool,
+    /// UUIDs of the identities which may administer the site
+    #[serde(default)]
+    pub admins: Vec<String>,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// Let webhooks be delivered to loopback and private addresses.  Never
+    /// enable this anywhere but a development or test system.
+    #[serde(default)]
+    pub allow_internal_webhooks: bool,
+    #[serde(default)]
+    pub security_headers: SecurityHeaders,
 }
 
 const BASE_ENV: &str = "dev";
 
+/// Names a further configuration file, loaded over the base one, for settings
+/// which must only ever apply to a development system
+const OVERLAY_VAR: &str = "LINKDOKU_OVERLAY";
+
+/// Enables the dev login, which can't be set through the usual environment
+/// variables since they take `_` to separate nested keys
+const DEV_LOGIN_VAR: &str = "LINKDOKU_DEV_LOGIN";
+
 pub fn load_configuration() -> Result<Configuration, ConfigError> {
-    let config = Config::builder()
-        .add_source(File::new(
-            &format!("linkdoku-config-{}.yaml", BASE_ENV),
-            FileFormat::Yaml,
-        ))
-        .add_source(
-            Environment::default()
-                .prefix("LINKDOKU")
-                .separator("_")
-                .try_parsing(true),
-        );
+    let mut config = Config::builder().add_source(File::new(
+        &format!("linkdoku-config-{}.yaml", BASE_ENV),
+        FileFormat::Yaml,
+    ));
+    if let Ok(overlay) = std::env::var(OVERLAY_VAR) {
+        config = config.add_source(File::new(&overlay, FileFormat::Yaml));
+    }
+    config = config.add_source(
+        Environment::default()
+            .prefix("LINKDOKU")
+            .separator("_")
+            .try_parsing(true),
+    );
+    if let Ok(dev_login) = std::env::var(DEV_LOGIN_VAR) {
+        config = config.set_override("dev_login", dev_login)?;
+    }
 
     config.build()?.try_deserialize()
 }
@@ -0,0 +1,77 @@
+//! Protection against cross-site request forgery
+//!
+//! Login state lives in a cookie, which browsers send along with requests no
+//! matter which site makes them.  So that other sites can't change things on
+//! a user's behalf, every API request which isn't a GET must carry the value
+//! of our `csrf` cookie in an `X-CSRF-Token` header.  Other sites can neither
+//! read our cookie nor send that header to us, so only our own frontend can.
+//!
+//! Requests authenticated with an API token don't rely on cookies at all, so
+//! they need no CSRF token.
+
+use axum::{
+    http::{header::AUTHORIZATION, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use cookie::{Cookie, SameSite};
+use rand::RngCore;
+use tower_cookies::Cookies;
+
+/// The cookie in which the frontend is given its CSRF token
+pub const CSRF_COOKIE: &str = "csrf";
+
+/// The header in which the frontend must send the CSRF token back
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Middleware for the API which issues CSRF tokens and checks them on
+/// anything which might change something
+pub async fn protect<B>(req: Request<B>, next: Next<B>) -> Response {
+    let cookies = match req.extensions().get::<Cookies>().cloned() {
+        Some(cookies) => cookies,
+        None => {
+            tracing::error!("No cookies available for CSRF protection");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let token = cookies
+        .get(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| !token.is_empty());
+
+    let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let via_token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("Bearer "))
+        .unwrap_or(false);
+    if !safe && !via_token {
+        let header = req
+            .headers()
+            .get(CSRF_HEADER)
+            .and_then(|value| value.to_str().ok());
+        if token.is_none() || header != token.as_deref() {
+            tracing::warn!(
+                "Refusing {} {} without a matching CSRF token",
+                req.method(),
+                req.uri().path()
+            );
+            return (StatusCode::FORBIDDEN, "Missing or incorrect CSRF token").into_response();
+        }
+    }
+
+    if token.is_none() {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        // The frontend has to read this one, so it can't be private or HTTP only
+        cookies.add(
+            Cookie::build(CSRF_COOKIE, hex::encode(secret))
+                .path("/")
+                .same_site(SameSite::Strict)
+                .finish(),
+        );
+    }
+
+    next.run(req).await
+}
@@ -3,13 +3,22 @@
 //!
 
 use std::{
+    collections::HashMap,
     error::Error,
     fmt::Display,
     time::{Duration, SystemTime},
 };
 
 use axum::Extension;
-use redis::{aio::ConnectionManager, Client, Cmd, RedisError, Script};
+use linkdoku_common::{
+    ActivityEntry, AdminAuditEntry, ApiTokenInfo, AvatarSource, CommunityRatings, FollowStatus,
+    PuzzleComment, PuzzleData, Rating, SolveStats, SolveTime, WebhookDelivery, WebhookSettings,
+};
+use redis::{
+    aio::ConnectionManager,
+    streams::{StreamId, StreamMaxlen, StreamRangeReply},
+    Client, Cmd, RedisError, Script,
+};
 
 use crate::config::Configuration;
 
@@ -58,6 +67,16 @@ pub async fn redis_layer(config: &Configuration) -> DatabaseResult<Extension<Dat
     Ok(Extension(Database { conn: conn_mgr }))
 }
 
+impl Database {
+    /// Check that the database is answering
+    pub async fn ping(&mut self) -> DatabaseResult<()> {
+        Ok(Cmd::new()
+            .arg("PING")
+            .query_async::<_, ()>(&mut self.conn)
+            .await?)
+    }
+}
+
 mod normalise;
 
 mod identity;
@@ -74,14 +93,17 @@ pub use puzzle::*;
 /// Identities are stored in Redis in the following ways:
 ///
 /// In all the below, `xxx` is an ID formed by hashing the subject identifier
+/// the identity first logged in with
 ///
 /// * `identity:xxx` is a hash of display_name etc.
 /// * `identity:xxx:roles` is the set of roles the identity has control of
+/// * `identity:xxx:subjects` is the set of login subjects linked to the identity
+/// * `identity:bysubject` maps every linked subject to its identity's ID
+/// * `identity:deleted` is the set of IDs of identities which have been deleted
 impl Database {
     /// Acquire an identity from the database if it is available, by its computed id
     ///
     /// If the identity does not exist, this will return `Ok(None)`
-    #[allow(dead_code)]
     pub async fn identity_by_uuid(&mut self, uuid: &str) -> DatabaseResult<Option<Identity>> {
         let kvs: Vec<String> = Cmd::hgetall(format!("identity:{}", uuid))
             .query_async(&mut self.conn)
@@ -93,20 +115,54 @@ impl Database {
         }
     }
 
-    /// Acquire an identity from the database if it is available, by its subject identifier
+    /// Find the UUID of the identity a login subject logs in as
+    ///
+    /// Subjects which have never logged in get the UUID derived from them,
+    /// unless that UUID belongs to an identity which the subject has since
+    /// been unlinked from, or which was deleted, in which case they get a
+    /// fresh one.
+    pub async fn identity_uuid_for_subject(&mut self, subj: &str) -> DatabaseResult<String> {
+        let linked: Option<String> = Cmd::hget("identity:bysubject", subj)
+            .query_async(&mut self.conn)
+            .await?;
+        if let Some(uuid) = linked {
+            return Ok(uuid);
+        }
+        let uuid = Identity::subject_uuid(subj);
+        let subjects: Vec<String> = Cmd::smembers(format!("identity:{}:subjects", uuid))
+            .query_async(&mut self.conn)
+            .await?;
+        let deleted: bool = Cmd::sismember("identity:deleted", &uuid)
+            .query_async(&mut self.conn)
+            .await?;
+        if !deleted && (subjects.is_empty() || subjects.iter().any(|s| s == subj)) {
+            Ok(uuid)
+        } else {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            Ok(Identity::subject_uuid(&format!("{}@{}", subj, now)))
+        }
+    }
+
+    /// Acquire an identity from the database if it is available, by a subject identifier
     ///
     /// If the identity does not exist, this will return `Ok(None)`
     #[allow(dead_code)]
     pub async fn identity_by_subject(&mut self, subj: &str) -> DatabaseResult<Option<Identity>> {
-        let uuid = format!("{:x}", md5::compute(subj));
+        let uuid = self.identity_uuid_for_subject(subj).await?;
         self.identity_by_uuid(&uuid).await
     }
 
     /// Create an identity if it does not exist in the database, and if it exists, return the
     /// role list for it.
+    ///
+    /// The subject the identity logged in with is linked to it if need be.
     pub async fn identity_upsert_and_roles(
         &mut self,
         identity: &Identity,
+        subj: &str,
     ) -> DatabaseResult<Vec<String>> {
         const UPSERT_SCRIPT: &str = include_str!("scripts/identity_upsert.lua");
         let script = Script::new(UPSERT_SCRIPT);
@@ -114,11 +170,281 @@ impl Database {
         invocation
             .key(format!("identity:{}", identity.uuid()))
             .key(format!("identity:{}:roles", identity.uuid()))
-            .arg(identity.display_name())
-            .arg(identity.gravatar_hash().unwrap_or(""));
+            .key(format!("identity:{}:subjects", identity.uuid()))
+            .key("identity:bysubject")
+            .arg(identity.provider_name())
+            .arg(identity.gravatar_hash.as_deref().unwrap_or(""))
+            .arg(identity.uuid())
+            .arg(subj);
         Ok(invocation.invoke_async(&mut self.conn).await?)
     }
 
+    /// Set the parts of an identity's profile which the user chooses
+    pub async fn set_identity_profile(
+        &mut self,
+        uuid: &str,
+        preferred_name: Option<&str>,
+        avatar: AvatarSource,
+    ) -> DatabaseResult<()> {
+        let key = format!("identity:{}", uuid);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match preferred_name {
+            Some(name) => pipe.hset(&key, "preferred_name", name),
+            None => pipe.hdel(&key, "preferred_name"),
+        };
+        pipe.hset(
+            &key,
+            "avatar",
+            match avatar {
+                AvatarSource::Gravatar => "gravatar",
+                AvatarSource::None => "none",
+            },
+        );
+        Ok(pipe.query_async::<_, ()>(&mut self.conn).await?)
+    }
+
+    /// Whether an identity has been deleted
+    pub async fn identity_deleted(&mut self, uuid: &str) -> DatabaseResult<bool> {
+        Ok(Cmd::sismember("identity:deleted", uuid)
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Delete an identity and everything which is only theirs
+    ///
+    /// The roles they own are disposed of as asked.  Deleted roles lose their
+    /// followers, and their puzzles are dropped from other roles' packs.  Their
+    /// solves, ratings, and comments remain, but no longer name them.  The
+    /// identity is recorded in `identity:deleted` so that logging in again
+    /// starts afresh rather than picking up roles which were left behind.
+    pub async fn delete_identity(
+        &mut self,
+        uuid: &str,
+        disposal: RoleDisposal<'_>,
+    ) -> DatabaseResult<()> {
+        let roles = self.identity_owned_roles(uuid).await?;
+        let subjects = self.identity_subjects(uuid).await?;
+        let tokens: Vec<String> = Cmd::smembers(format!("identity:{}:apitokens", uuid))
+            .query_async(&mut self.conn)
+            .await?;
+        let follows: Vec<String> = Cmd::smembers(format!("identity:{}:follows", uuid))
+            .query_async(&mut self.conn)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for subject in &subjects {
+            pipe.hdel("identity:bysubject", subject).ignore();
+        }
+        for token in &tokens {
+            pipe.del(format!("apitoken:{}", token)).ignore();
+        }
+        for role in &follows {
+            pipe.srem(format!("role:{}:followers", role), uuid).ignore();
+        }
+        let mut deleted_puzzles = Vec::new();
+        for role in &roles {
+            match disposal {
+                RoleDisposal::Orphan => {
+                    pipe.hset(format!("role:{}", role), "owner", "").ignore();
+                }
+                RoleDisposal::Reassign(owner) => {
+                    pipe.hset(format!("role:{}", role), "owner", owner)
+                        .ignore()
+                        .sadd(format!("identity:{}:roles", owner), role)
+                        .ignore();
+                }
+                RoleDisposal::Delete => {
+                    deleted_puzzles.extend(self.queue_role_deletion(&mut pipe, role).await?)
+                }
+            }
+        }
+        self.queue_pack_cleanup(&mut pipe, &deleted_puzzles).await?;
+        pipe.del(vec![
+            format!("identity:{}", uuid),
+            format!("identity:{}:roles", uuid),
+            format!("identity:{}:subjects", uuid),
+            format!("identity:{}:apitokens", uuid),
+            format!("identity:{}:follows", uuid),
+        ])
+        .ignore()
+        .sadd("identity:deleted", uuid)
+        .ignore();
+        Ok(pipe.query_async::<_, ()>(&mut self.conn).await?)
+    }
+
+    /// The roles an identity both has control of and owns
+    ///
+    /// Only these go with the identity when it is deleted.
+    pub async fn identity_owned_roles(&mut self, identity: &str) -> DatabaseResult<Vec<String>> {
+        let mut owned = Vec::new();
+        for role in self.identity_roles(identity).await? {
+            match self.role_by_uuid_or_short_name(&role).await {
+                Ok(data) if data.owner() == identity => owned.push(role),
+                Ok(_) | Err(DatabaseError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(owned)
+    }
+
+    /// Add the deletion of a role and all of its puzzles to a pipeline
+    ///
+    /// Anyone following the role stops doing so.  Returns the UUIDs of the
+    /// deleted puzzles, so that packs elsewhere can be cleaned up.
+    async fn queue_role_deletion(
+        &mut self,
+        pipe: &mut redis::Pipeline,
+        role: &str,
+    ) -> DatabaseResult<Vec<String>> {
+        let role = self.role_by_uuid_or_short_name(role).await?;
+        let followers: Vec<String> = Cmd::smembers(format!("role:{}:followers", role.uuid()))
+            .query_async(&mut self.conn)
+            .await?;
+        for follower in &followers {
+            pipe.srem(format!("identity:{}:follows", follower), role.uuid())
+                .ignore();
+        }
+        let mut deleted = Vec::new();
+        for (puzzle, _) in self.role_puzzles(role.uuid()).await? {
+            let puzzle = self.puzzle_by_uuid_or_short_name(&puzzle).await?;
+            let uuid = puzzle.uuid();
+            deleted.push(uuid.to_string());
+            for state in puzzle.states() {
+                if let PuzzleData::Pack(members) = &state.data {
+                    for member in members {
+                        pipe.srem(format!("puzzle:{}:packs", member), uuid).ignore();
+                    }
+                }
+            }
+            pipe.hdel("puzzle:byname", puzzle.short_name())
+                .ignore()
+                .zrem("puzzles:published", uuid)
+                .ignore()
+                .del(
+                    [
+                        "",
+                        ":solvers",
+                        ":solvetimes",
+                        ":comments",
+                        ":comments:hidden",
+                        ":packs",
+                        ":difficulty",
+                        ":difficulty:counts",
+                        ":quality",
+                        ":quality:totals",
+                    ]
+                    .iter()
+                    .map(|suffix| format!("puzzle:{}{}", uuid, suffix))
+                    .collect::<Vec<_>>(),
+                )
+                .ignore();
+        }
+        let uuid = role.uuid();
+        pipe.hdel("role:byname", role.short_name())
+            .ignore()
+            .del(
+                [
+                    "",
+                    ":puzzles",
+                    ":followers",
+                    ":webhook",
+                    ":webhook:log",
+                    ":activity",
+                ]
+                .iter()
+                .map(|suffix| format!("role:{}{}", uuid, suffix))
+                .collect::<Vec<_>>(),
+            )
+            .ignore();
+        Ok(deleted)
+    }
+
+    /// Add the removal of deleted puzzles from any surviving packs to a pipeline
+    async fn queue_pack_cleanup(
+        &mut self,
+        pipe: &mut redis::Pipeline,
+        deleted: &[String],
+    ) -> DatabaseResult<()> {
+        let mut packs: HashMap<String, (Puzzle, bool)> = HashMap::new();
+        for puzzle in deleted {
+            for pack in self.puzzle_packs(puzzle).await? {
+                if deleted.contains(&pack) {
+                    continue;
+                }
+                if !packs.contains_key(&pack) {
+                    match self.puzzle_by_uuid_or_short_name(&pack).await {
+                        Ok(data) => packs.insert(pack.clone(), (data, false)),
+                        Err(DatabaseError::NotFound(_)) => continue,
+                        Err(e) => return Err(e),
+                    };
+                }
+                if let Some((data, changed)) = packs.get_mut(&pack) {
+                    *changed |= data.remove_pack_member(puzzle);
+                }
+            }
+        }
+        for (uuid, (pack, _)) in packs.into_iter().filter(|(_, (_, changed))| *changed) {
+            pipe.hset(
+                format!("puzzle:{}", uuid),
+                "states",
+                Puzzle::compress_states(pack.states()),
+            )
+            .ignore();
+        }
+        Ok(())
+    }
+
+    /// The login subjects linked to an identity
+    pub async fn identity_subjects(&mut self, uuid: &str) -> DatabaseResult<Vec<String>> {
+        let mut subjects: Vec<String> = Cmd::smembers(format!("identity:{}:subjects", uuid))
+            .query_async(&mut self.conn)
+            .await?;
+        subjects.sort();
+        Ok(subjects)
+    }
+
+    /// Link another login subject to an identity
+    ///
+    /// Returns false if the subject already belongs to some other identity
+    pub async fn link_subject(&mut self, uuid: &str, subj: &str) -> DatabaseResult<bool> {
+        const LINK_SCRIPT: &str = include_str!("scripts/link_subject.lua");
+        let subject_uuid = Identity::subject_uuid(subj);
+        let script = Script::new(LINK_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key("identity:bysubject")
+            .key(format!("identity:{}:subjects", uuid))
+            .key(format!("identity:{}", subject_uuid))
+            .arg(subj)
+            .arg(uuid)
+            .arg(subject_uuid);
+        let linked: i32 = invocation.invoke_async(&mut self.conn).await?;
+        Ok(linked == 1)
+    }
+
+    /// Unlink a login subject from an identity, so long as it isn't the last
+    pub async fn unlink_subject(
+        &mut self,
+        uuid: &str,
+        subj: &str,
+    ) -> DatabaseResult<UnlinkOutcome> {
+        const UNLINK_SCRIPT: &str = include_str!("scripts/unlink_subject.lua");
+        let script = Script::new(UNLINK_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key("identity:bysubject")
+            .key(format!("identity:{}:subjects", uuid))
+            .arg(subj);
+        let outcome: i32 = invocation.invoke_async(&mut self.conn).await?;
+        Ok(match outcome {
+            1 => UnlinkOutcome::Unlinked,
+            0 => UnlinkOutcome::NotLinked,
+            _ => UnlinkOutcome::LastLogin,
+        })
+    }
+
     pub async fn role_by_uuid_or_short_name(
         &mut self,
         uuid_or_short_name: &str,
@@ -142,11 +468,23 @@ impl Database {
             Ok(Role::from_list(&uuid, kvs.into_iter()))
         }
     }
-    pub async fn create_default_role(&mut self, identity: &Identity) -> DatabaseResult<()> {
+    /// Create an identity's default role
+    ///
+    /// The role's short name is derived from the preferred username the login
+    /// provider gave us if there was one, otherwise from the display name.
+    pub async fn create_default_role(
+        &mut self,
+        identity: &Identity,
+        preferred_username: Option<&str>,
+    ) -> DatabaseResult<()> {
         let uuid = identity.get_default_role();
         let display_name = identity.display_name().to_string();
-        let short_name =
-            normalise::unique_short_name(self, identity.display_name(), "role").await?;
+        let short_name = normalise::unique_short_name(
+            self,
+            preferred_username.unwrap_or_else(|| identity.display_name()),
+            "role",
+        )
+        .await?;
         let owner = identity.uuid().to_string();
         let bio = format!("# {}\n\nTODO", identity.display_name());
 
@@ -190,16 +528,95 @@ impl Database {
             })
             .arg(puzzle.visibility_date().unwrap_or(""))
             .arg(Puzzle::compress_states(puzzle.states()))
-            .arg(
-                SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap_or_else(|_| Duration::from_secs(0))
-                    .as_secs(),
-            );
+            .arg(Self::now());
         invocation.invoke_async(&mut self.conn).await?;
         Ok(uuid)
     }
 
+    /// Change the visibility of a puzzle, keeping the published puzzle index up to date
+    ///
+    /// * `puzzles:published` is a sorted set of published puzzles, by time of publication
+    pub async fn set_puzzle_visibility(
+        &mut self,
+        puzzle: &mut Puzzle,
+        visibility: linkdoku_common::Visibility,
+    ) -> DatabaseResult<()> {
+        use linkdoku_common::Visibility;
+        let now = Self::now();
+        puzzle.set_visibility(visibility, now.to_string());
+
+        const SET_VISIBILITY_SCRIPT: &str = include_str!("scripts/set_visibility.lua");
+        let script = Script::new(SET_VISIBILITY_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("puzzle:{}", puzzle.uuid()))
+            .key("puzzles:published")
+            .arg(puzzle.uuid())
+            .arg(match visibility {
+                Visibility::Restricted => "restricted",
+                Visibility::Public => "public",
+                Visibility::Published => "published",
+            })
+            .arg(puzzle.visibility_date().unwrap_or(""))
+            .arg(Puzzle::compress_states(puzzle.states()))
+            .arg(now);
+        invocation.invoke_async::<_, ()>(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Retrieve the most recently published puzzles, newest first, along with
+    /// the time of their publication
+    pub async fn recently_published(&mut self, count: isize) -> DatabaseResult<Vec<(String, u64)>> {
+        Ok(Cmd::zrevrange_withscores("puzzles:published", 0, count - 1)
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Retrieve up to `count` of a role's published puzzles, most recently
+    /// published first, along with the time of their publication
+    ///
+    /// This is worked out in Redis in `role:{uuid}:published`, a scratch key
+    /// which never outlives the transaction that builds it.
+    pub async fn role_published(
+        &mut self,
+        role: &str,
+        count: usize,
+    ) -> DatabaseResult<Vec<(String, u64)>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let published = format!("role:{}:published", role);
+        // As for feeds, the role's puzzles are weighted to nothing so that
+        // the scores are the times of publication
+        let (puzzles,): (Vec<(String, u64)>,) = redis::pipe()
+            .atomic()
+            .cmd("ZINTERSTORE")
+            .arg(&published)
+            .arg(2)
+            .arg(format!("role:{}:puzzles", role))
+            .arg("puzzles:published")
+            .arg("WEIGHTS")
+            .arg(0)
+            .arg(1)
+            .ignore()
+            .zrevrange_withscores(&published, 0, count as isize - 1)
+            .del(&published)
+            .ignore()
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(puzzles)
+    }
+
+    /// Retrieve all the puzzles owned by a role, newest first, along with the time
+    /// they were created
+    pub async fn role_puzzles(&mut self, role: &str) -> DatabaseResult<Vec<(String, u64)>> {
+        Ok(
+            Cmd::zrevrange_withscores(format!("role:{}:puzzles", role), 0, -1)
+                .query_async(&mut self.conn)
+                .await?,
+        )
+    }
+
     pub async fn puzzle_by_uuid_or_short_name(
         &mut self,
         uuid_or_short_name: &str,
@@ -222,9 +639,988 @@ impl Database {
     }
 }
 
+/// Database functions related to following roles
+///
+/// Follows are stored in Redis in the following ways:
+///
+/// * `identity:{uuid}:follows` is the set of roles the identity follows
+/// * `role:{uuid}:followers` is the set of identities following the role
+/// * `role:{uuid}:puzzles` (maintained when puzzles are created) is used to build feeds
+/// * `identity:{uuid}:feed` is a scratch key used while building a feed, which
+///   never outlives the transaction that builds it
+impl Database {
+    /// Start or stop following a role
+    pub async fn set_following(
+        &mut self,
+        identity: &str,
+        role: &str,
+        follow: bool,
+    ) -> DatabaseResult<()> {
+        const SET_FOLLOWING_SCRIPT: &str = include_str!("scripts/set_following.lua");
+        let script = Script::new(SET_FOLLOWING_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("identity:{}:follows", identity))
+            .key(format!("role:{}:followers", role))
+            .arg(identity)
+            .arg(role)
+            .arg(if follow { "follow" } else { "unfollow" });
+        invocation.invoke_async::<_, ()>(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Retrieve the follower count of a role, and whether the identity (if any) follows it
+    pub async fn follow_status(
+        &mut self,
+        identity: Option<&str>,
+        role: &str,
+    ) -> DatabaseResult<FollowStatus> {
+        let followers = Cmd::scard(format!("role:{}:followers", role))
+            .query_async(&mut self.conn)
+            .await?;
+        let following = match identity {
+            Some(identity) => {
+                Cmd::sismember(format!("identity:{}:follows", identity), role)
+                    .query_async(&mut self.conn)
+                    .await?
+            }
+            None => false,
+        };
+        Ok(FollowStatus {
+            following,
+            followers,
+        })
+    }
+
+    /// Retrieve up to `count` published puzzles of the roles the identity
+    /// follows, most recently published first
+    ///
+    /// The result is pairs of puzzle UUID and the time it was published.  The
+    /// merging and filtering is done in Redis, so only the puzzles which make
+    /// it into the feed come back.
+    pub async fn followed_puzzles(
+        &mut self,
+        identity: &str,
+        count: usize,
+    ) -> DatabaseResult<Vec<(String, u64)>> {
+        let roles: Vec<String> = Cmd::smembers(format!("identity:{}:follows", identity))
+            .query_async(&mut self.conn)
+            .await?;
+        if roles.is_empty() || count == 0 {
+            return Ok(Vec::new());
+        }
+        let role_puzzles: Vec<String> = roles
+            .iter()
+            .map(|role| format!("role:{}:puzzles", role))
+            .collect();
+        let feed = format!("identity:{}:feed", identity);
+        // Intersecting with the published index, with the roles' puzzles
+        // weighted to nothing, keeps only published puzzles and scores them by
+        // when they were published
+        let (puzzles,): (Vec<(String, u64)>,) = redis::pipe()
+            .atomic()
+            .cmd("ZUNIONSTORE")
+            .arg(&feed)
+            .arg(role_puzzles.len())
+            .arg(&role_puzzles)
+            .ignore()
+            .cmd("ZINTERSTORE")
+            .arg(&feed)
+            .arg(2)
+            .arg(&feed)
+            .arg("puzzles:published")
+            .arg("WEIGHTS")
+            .arg(0)
+            .arg(1)
+            .ignore()
+            .zrevrange_withscores(&feed, 0, count as isize - 1)
+            .del(&feed)
+            .ignore()
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(puzzles)
+    }
+}
+
+/// Database functions related to puzzle packs
+///
+/// Pack membership is stored in Redis in the following ways:
+///
+/// * `puzzle:{uuid}:packs` is the set of packs which have ever listed the puzzle
+impl Database {
+    /// Record that a pack contains the given puzzles
+    pub async fn add_pack_members(&mut self, pack: &str, members: &[String]) -> DatabaseResult<()> {
+        for member in members {
+            Cmd::sadd(format!("puzzle:{}:packs", member), pack)
+                .query_async::<_, ()>(&mut self.conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the UUIDs of any packs which have listed the given puzzle
+    ///
+    /// Later states of a pack may have dropped the puzzle, so callers must check
+    pub async fn puzzle_packs(&mut self, puzzle: &str) -> DatabaseResult<Vec<String>> {
+        Ok(Cmd::smembers(format!("puzzle:{}:packs", puzzle))
+            .query_async(&mut self.conn)
+            .await?)
+    }
+}
+
+/// Database functions related to solving puzzles
+///
+/// Solves are stored in Redis in the following ways:
+///
+/// * `puzzle:{uuid}:solvers` is a sorted set of identities scored by when they solved it
+/// * `puzzle:{uuid}:solvetimes` is a sorted set of identities scored by their best solve time
+impl Database {
+    /// Record that an identity has solved a puzzle, optionally with a solve time in seconds
+    pub async fn mark_solved(
+        &mut self,
+        puzzle: &str,
+        identity: &str,
+        solve_time: Option<u64>,
+    ) -> DatabaseResult<usize> {
+        const MARK_SOLVED_SCRIPT: &str = include_str!("scripts/mark_solved.lua");
+        let script = Script::new(MARK_SOLVED_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("puzzle:{}:solvers", puzzle))
+            .key(format!("puzzle:{}:solvetimes", puzzle))
+            .arg(identity)
+            .arg(Self::now())
+            .arg(solve_time.map(|t| t.to_string()).unwrap_or_default());
+        Ok(invocation.invoke_async(&mut self.conn).await?)
+    }
+
+    /// Retrieve solve statistics for a puzzle, including up to `fastest` solve times
+    ///
+    /// If an identity is provided, the stats will say if that identity has solved the puzzle
+    pub async fn solve_stats(
+        &mut self,
+        puzzle: &str,
+        identity: Option<&str>,
+        fastest: isize,
+    ) -> DatabaseResult<SolveStats> {
+        let count: usize = Cmd::zcard(format!("puzzle:{}:solvers", puzzle))
+            .query_async(&mut self.conn)
+            .await?;
+        let times: Vec<(String, u64)> =
+            Cmd::zrange_withscores(format!("puzzle:{}:solvetimes", puzzle), 0, fastest - 1)
+                .query_async(&mut self.conn)
+                .await?;
+        let mut ret = SolveStats {
+            count,
+            ..Default::default()
+        };
+        for (solver, seconds) in times {
+            let name = self
+                .identity_by_uuid(&solver)
+                .await?
+                .map(|identity| identity.display_name().to_string());
+            ret.fastest.push(SolveTime {
+                name: name.unwrap_or(solver),
+                seconds,
+            });
+        }
+        if let Some(identity) = identity {
+            let solved_at: Option<u64> =
+                Cmd::zscore(format!("puzzle:{}:solvers", puzzle), identity)
+                    .query_async(&mut self.conn)
+                    .await?;
+            ret.solved = solved_at.is_some();
+        }
+        Ok(ret)
+    }
+}
+
+/// Database functions related to community ratings of puzzles
+///
+/// Ratings are stored in Redis in the following ways:
+///
+/// * `puzzle:{uuid}:difficulty` is a hash of identity to chosen difficulty rating
+/// * `puzzle:{uuid}:difficulty:counts` is a hash of difficulty rating to number of votes
+/// * `puzzle:{uuid}:quality` is a hash of identity to quality score
+/// * `puzzle:{uuid}:quality:totals` is a hash containing `count` and `total` of quality scores
+impl Database {
+    /// Record an identity's opinion of a puzzle, either part may be left unchanged
+    pub async fn rate_puzzle(
+        &mut self,
+        puzzle: &str,
+        identity: &str,
+        difficulty: Option<Rating>,
+        quality: Option<u8>,
+    ) -> DatabaseResult<()> {
+        const RATE_PUZZLE_SCRIPT: &str = include_str!("scripts/rate_puzzle.lua");
+        let script = Script::new(RATE_PUZZLE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("puzzle:{}:difficulty", puzzle))
+            .key(format!("puzzle:{}:difficulty:counts", puzzle))
+            .key(format!("puzzle:{}:quality", puzzle))
+            .key(format!("puzzle:{}:quality:totals", puzzle))
+            .arg(identity)
+            .arg(difficulty.map(Rating::value).unwrap_or(""))
+            .arg(quality.map(|q| q.to_string()).unwrap_or_default());
+        invocation.invoke_async::<_, ()>(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Retrieve the aggregated community ratings for a puzzle
+    ///
+    /// If an identity is provided, its own ratings are included
+    pub async fn community_ratings(
+        &mut self,
+        puzzle: &str,
+        identity: Option<&str>,
+    ) -> DatabaseResult<CommunityRatings> {
+        let counts: HashMap<String, usize> =
+            Cmd::hgetall(format!("puzzle:{}:difficulty:counts", puzzle))
+                .query_async(&mut self.conn)
+                .await?;
+        let totals: HashMap<String, u64> =
+            Cmd::hgetall(format!("puzzle:{}:quality:totals", puzzle))
+                .query_async(&mut self.conn)
+                .await?;
+        let mut ret = CommunityRatings {
+            difficulty: Rating::values()
+                .iter()
+                .map(|&r| (r, counts.get(r.value()).copied().unwrap_or(0)))
+                .collect(),
+            quality_count: totals.get("count").copied().unwrap_or(0) as usize,
+            quality_total: totals.get("total").copied().unwrap_or(0),
+            ..Default::default()
+        };
+        if let Some(identity) = identity {
+            let difficulty: Option<String> =
+                Cmd::hget(format!("puzzle:{}:difficulty", puzzle), identity)
+                    .query_async(&mut self.conn)
+                    .await?;
+            ret.my_difficulty = difficulty.as_deref().map(Rating::from_value);
+            ret.my_quality = Cmd::hget(format!("puzzle:{}:quality", puzzle), identity)
+                .query_async(&mut self.conn)
+                .await?;
+        }
+        Ok(ret)
+    }
+}
+
+/// Database functions related to comments on puzzles
+///
+/// Comments are stored in Redis in the following ways:
+///
+/// * `puzzle:{uuid}:comments` is a stream of comments, each entry has `author` (identity),
+///   `role` (the setter role if the author was replying as the setter), `body`, `hint`
+///   and `reply_to` fields
+/// * `puzzle:{uuid}:comments:hidden` is the set of comment IDs hidden by moderation
+impl Database {
+    /// Add a comment to a puzzle, returning the ID of the new comment
+    ///
+    /// Replies to replies are attached to the top of the thread, and replying
+    /// to a comment which does not exist results in [`DatabaseError::NotFound`].
+    /// Only the setter can see hidden threads, so anyone else replying to one
+    /// gets the same.
+    pub async fn add_comment(
+        &mut self,
+        puzzle: &str,
+        identity: &str,
+        setter_role: Option<&str>,
+        body: &str,
+        hint: bool,
+        reply_to: Option<&str>,
+    ) -> DatabaseResult<String> {
+        let key = format!("puzzle:{}:comments", puzzle);
+        let reply_to = match reply_to {
+            Some(parent) => {
+                let entry = self.comment_entry(&key, parent).await?;
+                let root = entry
+                    .get::<String>("reply_to")
+                    .filter(|r| !r.is_empty())
+                    .unwrap_or_else(|| entry.id.clone());
+                if setter_role.is_none() {
+                    let hidden_key = format!("puzzle:{}:comments:hidden", puzzle);
+                    for id in [&entry.id, &root] {
+                        let hidden: bool = Cmd::sismember(&hidden_key, id)
+                            .query_async(&mut self.conn)
+                            .await?;
+                        if hidden {
+                            return Err(DatabaseError::NotFound(format!("{} {}", key, parent)));
+                        }
+                    }
+                }
+                Some(root)
+            }
+            None => None,
+        };
+        Ok(Cmd::xadd(
+            &key,
+            "*",
+            &[
+                ("author", identity),
+                ("role", setter_role.unwrap_or("")),
+                ("body", body),
+                ("hint", if hint { "1" } else { "0" }),
+                ("reply_to", reply_to.as_deref().unwrap_or("")),
+            ],
+        )
+        .query_async(&mut self.conn)
+        .await?)
+    }
+
+    /// Retrieve all the comments on a puzzle, in the order they were posted
+    ///
+    /// Hidden comments are included, and flagged as such
+    pub async fn puzzle_comments(&mut self, puzzle: &str) -> DatabaseResult<Vec<PuzzleComment>> {
+        let entries: StreamRangeReply = Cmd::xrange_all(format!("puzzle:{}:comments", puzzle))
+            .query_async(&mut self.conn)
+            .await?;
+        let hidden: Vec<String> = Cmd::smembers(format!("puzzle:{}:comments:hidden", puzzle))
+            .query_async(&mut self.conn)
+            .await?;
+        let mut names: HashMap<String, String> = HashMap::new();
+        let mut ret = Vec::with_capacity(entries.ids.len());
+        for entry in entries.ids {
+            let role = entry.get::<String>("role").unwrap_or_default();
+            let (author_key, author) = if role.is_empty() {
+                let author = entry.get::<String>("author").unwrap_or_default();
+                (format!("identity:{}", author), author)
+            } else {
+                (format!("role:{}", role), role.clone())
+            };
+            let name = match names.get(&author_key) {
+                Some(name) => name.clone(),
+                None => {
+                    let name: Option<String> = if role.is_empty() {
+                        self.identity_by_uuid(&author)
+                            .await?
+                            .map(|identity| identity.display_name().to_string())
+                    } else {
+                        Cmd::hget(&author_key, "display_name")
+                            .query_async(&mut self.conn)
+                            .await?
+                    };
+                    let name = name.unwrap_or(author);
+                    names.insert(author_key, name.clone());
+                    name
+                }
+            };
+            ret.push(PuzzleComment {
+                hidden: hidden.contains(&entry.id),
+                posted: Self::stream_id_time(&entry.id),
+                author: name,
+                setter: !role.is_empty(),
+                body: entry.get("body").unwrap_or_default(),
+                hint: entry.get::<String>("hint").as_deref() == Some("1"),
+                reply_to: entry.get::<String>("reply_to").filter(|r| !r.is_empty()),
+                id: entry.id,
+            });
+        }
+        Ok(ret)
+    }
+
+    /// Hide or reveal a comment on a puzzle
+    pub async fn set_comment_hidden(
+        &mut self,
+        puzzle: &str,
+        comment: &str,
+        hidden: bool,
+    ) -> DatabaseResult<()> {
+        self.comment_entry(&format!("puzzle:{}:comments", puzzle), comment)
+            .await?;
+        let hidden_key = format!("puzzle:{}:comments:hidden", puzzle);
+        if hidden {
+            Cmd::sadd(hidden_key, comment)
+                .query_async::<_, ()>(&mut self.conn)
+                .await?;
+        } else {
+            Cmd::srem(hidden_key, comment)
+                .query_async::<_, ()>(&mut self.conn)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Delete a comment from a puzzle, along with any replies to it
+    pub async fn delete_comment(&mut self, puzzle: &str, comment: &str) -> DatabaseResult<()> {
+        let key = format!("puzzle:{}:comments", puzzle);
+        self.comment_entry(&key, comment).await?;
+        let entries: StreamRangeReply = Cmd::xrange_all(&key).query_async(&mut self.conn).await?;
+        let doomed: Vec<String> = entries
+            .ids
+            .into_iter()
+            .filter(|entry| {
+                entry.id == comment || entry.get::<String>("reply_to").as_deref() == Some(comment)
+            })
+            .map(|entry| entry.id)
+            .collect();
+        Cmd::xdel(&key, &doomed)
+            .query_async::<_, ()>(&mut self.conn)
+            .await?;
+        Cmd::srem(format!("puzzle:{}:comments:hidden", puzzle), &doomed)
+            .query_async::<_, ()>(&mut self.conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Fetch a single comment from a puzzle's comment stream
+    async fn comment_entry(&mut self, key: &str, comment: &str) -> DatabaseResult<StreamId> {
+        let not_found = || DatabaseError::NotFound(format!("{} {}", key, comment));
+        if !Self::smells_like_stream_id(comment) {
+            return Err(not_found());
+        }
+        let mut entries: StreamRangeReply = Cmd::xrange(key, comment, comment)
+            .query_async(&mut self.conn)
+            .await?;
+        entries.ids.pop().ok_or_else(not_found)
+    }
+}
+
+/// Personal API tokens
+///
+/// * `apitoken:{hash}` is a hash of `identity`, `name`, `roles`, `write` and
+///   `created`, keyed by the SHA-256 of the token, with roles space separated
+/// * `identity:{uuid}:apitokens` is the set of token hashes an identity owns
+impl Database {
+    /// Retrieve the roles an identity has control of
+    pub async fn identity_roles(&mut self, identity: &str) -> DatabaseResult<Vec<String>> {
+        Ok(Cmd::smembers(format!("identity:{}:roles", identity))
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Store a new API token for an identity, returning its details
+    pub async fn create_api_token(
+        &mut self,
+        identity: &str,
+        hash: &str,
+        name: &str,
+        roles: &[String],
+        write: bool,
+    ) -> DatabaseResult<ApiTokenInfo> {
+        let created = Self::now();
+        const CREATE_TOKEN_SCRIPT: &str = include_str!("scripts/create_api_token.lua");
+        let script = Script::new(CREATE_TOKEN_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("apitoken:{}", hash))
+            .key(format!("identity:{}:apitokens", identity))
+            .arg(hash)
+            .arg(identity)
+            .arg(name)
+            .arg(roles.join(" "))
+            .arg(if write { "1" } else { "0" })
+            .arg(created);
+        invocation.invoke_async::<_, ()>(&mut self.conn).await?;
+        Ok(ApiTokenInfo {
+            id: hash.to_string(),
+            name: name.to_string(),
+            roles: roles.to_vec(),
+            write,
+            created,
+        })
+    }
+
+    /// Look up an API token by its hash, returning the identity it belongs to
+    /// along with its details
+    pub async fn api_token(
+        &mut self,
+        hash: &str,
+    ) -> DatabaseResult<Option<(String, ApiTokenInfo)>> {
+        let kvs: HashMap<String, String> = Cmd::hgetall(format!("apitoken:{}", hash))
+            .query_async(&mut self.conn)
+            .await?;
+        let identity = match kvs.get("identity") {
+            Some(identity) => identity.clone(),
+            None => return Ok(None),
+        };
+        Ok(Some((
+            identity,
+            ApiTokenInfo {
+                id: hash.to_string(),
+                name: kvs.get("name").cloned().unwrap_or_default(),
+                roles: kvs
+                    .get("roles")
+                    .map(|r| r.split_whitespace().map(String::from).collect())
+                    .unwrap_or_default(),
+                write: kvs.get("write").map(String::as_str) == Some("1"),
+                created: kvs.get("created").and_then(|c| c.parse().ok()).unwrap_or(0),
+            },
+        )))
+    }
+
+    /// List the API tokens belonging to an identity, oldest first
+    pub async fn api_tokens(&mut self, identity: &str) -> DatabaseResult<Vec<ApiTokenInfo>> {
+        let hashes: Vec<String> = Cmd::smembers(format!("identity:{}:apitokens", identity))
+            .query_async(&mut self.conn)
+            .await?;
+        let mut ret = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some((_, info)) = self.api_token(&hash).await? {
+                ret.push(info);
+            }
+        }
+        ret.sort_by_key(|info| info.created);
+        Ok(ret)
+    }
+
+    /// Revoke one of an identity's API tokens, returning whether it existed
+    pub async fn revoke_api_token(&mut self, identity: &str, hash: &str) -> DatabaseResult<bool> {
+        const REVOKE_TOKEN_SCRIPT: &str = include_str!("scripts/revoke_api_token.lua");
+        let script = Script::new(REVOKE_TOKEN_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("apitoken:{}", hash))
+            .key(format!("identity:{}:apitokens", identity))
+            .arg(hash);
+        let revoked: i64 = invocation.invoke_async(&mut self.conn).await?;
+        Ok(revoked == 1)
+    }
+}
+
+/// Webhooks
+///
+/// * `role:{uuid}:webhook` is a hash with `url` and `secret` fields, absent if
+///   the role has no webhook configured
+/// * `role:{uuid}:webhook:log` is a capped stream of delivery attempts, with
+///   `puzzle`, `when`, `attempts`, `status` and `error` fields
+impl Database {
+    /// How many deliveries we keep in a role's webhook log
+    const WEBHOOK_LOG_LENGTH: usize = 100;
+
+    /// Retrieve the webhook settings for a role, if it has any
+    pub async fn role_webhook(&mut self, role: &str) -> DatabaseResult<Option<WebhookSettings>> {
+        let (url, secret): (Option<String>, Option<String>) =
+            Cmd::hget(format!("role:{}:webhook", role), &["url", "secret"])
+                .query_async(&mut self.conn)
+                .await?;
+        Ok(url.map(|url| WebhookSettings {
+            url,
+            secret: secret.filter(|s| !s.is_empty()),
+        }))
+    }
+
+    /// Set, or with `None` remove, the webhook for a role
+    pub async fn set_role_webhook(
+        &mut self,
+        role: &str,
+        webhook: Option<&WebhookSettings>,
+    ) -> DatabaseResult<()> {
+        let key = format!("role:{}:webhook", role);
+        match webhook {
+            Some(webhook) => {
+                Cmd::hset_multiple(
+                    key,
+                    &[
+                        ("url", webhook.url.as_str()),
+                        ("secret", webhook.secret.as_deref().unwrap_or("")),
+                    ],
+                )
+                .query_async::<_, ()>(&mut self.conn)
+                .await?
+            }
+            None => Cmd::del(key).query_async::<_, ()>(&mut self.conn).await?,
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of a webhook delivery for a role
+    pub async fn log_webhook_delivery(
+        &mut self,
+        role: &str,
+        delivery: &WebhookDelivery,
+    ) -> DatabaseResult<()> {
+        Cmd::xadd_maxlen(
+            format!("role:{}:webhook:log", role),
+            StreamMaxlen::Approx(Self::WEBHOOK_LOG_LENGTH),
+            "*",
+            &[
+                ("puzzle", delivery.puzzle.clone()),
+                ("when", delivery.when.to_string()),
+                ("attempts", delivery.attempts.to_string()),
+                (
+                    "status",
+                    delivery.status.map(|s| s.to_string()).unwrap_or_default(),
+                ),
+                ("error", delivery.error.clone().unwrap_or_default()),
+            ],
+        )
+        .query_async::<_, ()>(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieve the most recent webhook deliveries for a role, newest first
+    pub async fn webhook_deliveries(
+        &mut self,
+        role: &str,
+        count: usize,
+    ) -> DatabaseResult<Vec<WebhookDelivery>> {
+        let entries: StreamRangeReply =
+            Cmd::xrevrange_count(format!("role:{}:webhook:log", role), "+", "-", count)
+                .query_async(&mut self.conn)
+                .await?;
+        Ok(entries
+            .ids
+            .into_iter()
+            .map(|entry| WebhookDelivery {
+                puzzle: entry.get("puzzle").unwrap_or_default(),
+                when: entry
+                    .get::<String>("when")
+                    .and_then(|w| w.parse().ok())
+                    .unwrap_or_else(|| Self::stream_id_time(&entry.id)),
+                attempts: entry
+                    .get::<String>("attempts")
+                    .and_then(|a| a.parse().ok())
+                    .unwrap_or(0),
+                status: entry.get::<String>("status").and_then(|s| s.parse().ok()),
+                error: entry.get::<String>("error").filter(|e| !e.is_empty()),
+            })
+            .collect())
+    }
+}
+
+/// Activity
+///
+/// * `activity` is a capped stream of changes made to roles and puzzles, with
+///   `actor`, `active_role`, `role`, `action`, `target` and `summary` fields
+/// * `role:{uuid}:activity` is a capped stream of the same, for one role
+impl Database {
+    /// How many changes we keep in the site-wide activity log
+    const ACTIVITY_LOG_LENGTH: usize = 10000;
+    /// How many changes we keep in a role's activity log
+    const ROLE_ACTIVITY_LOG_LENGTH: usize = 1000;
+
+    /// Record a change in the activity logs
+    pub async fn log_activity(&mut self, entry: &ActivityEntry) -> DatabaseResult<()> {
+        const LOG_ACTIVITY_SCRIPT: &str = include_str!("scripts/log_activity.lua");
+        let script = Script::new(LOG_ACTIVITY_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key("activity")
+            .key(format!("role:{}", entry.role))
+            .key(format!("role:{}:activity", entry.role))
+            .arg(Self::ACTIVITY_LOG_LENGTH)
+            .arg(Self::ROLE_ACTIVITY_LOG_LENGTH);
+        for (field, value) in [
+            ("when", entry.when.to_string()),
+            ("actor", entry.actor.clone()),
+            ("active_role", entry.active_role.clone().unwrap_or_default()),
+            ("role", entry.role.clone()),
+            ("action", entry.action.clone()),
+            ("target", entry.target.clone()),
+            ("summary", entry.summary.clone()),
+        ] {
+            invocation.arg(field).arg(value);
+        }
+        invocation.invoke_async::<_, ()>(&mut self.conn).await?;
+        Ok(())
+    }
+
+    /// Retrieve the most recent changes to a role and its puzzles, newest first
+    pub async fn role_activity(
+        &mut self,
+        role: &str,
+        count: usize,
+    ) -> DatabaseResult<Vec<ActivityEntry>> {
+        self.activity_log(format!("role:{}:activity", role), count)
+            .await
+    }
+
+    /// Retrieve the most recent changes across the site, newest first
+    pub async fn site_activity(&mut self, count: usize) -> DatabaseResult<Vec<ActivityEntry>> {
+        self.activity_log("activity".to_string(), count).await
+    }
+
+    async fn activity_log(
+        &mut self,
+        key: String,
+        count: usize,
+    ) -> DatabaseResult<Vec<ActivityEntry>> {
+        let entries: StreamRangeReply = Cmd::xrevrange_count(key, "+", "-", count)
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(entries
+            .ids
+            .into_iter()
+            .map(|entry| ActivityEntry {
+                when: entry
+                    .get::<String>("when")
+                    .and_then(|w| w.parse().ok())
+                    .unwrap_or_else(|| Self::stream_id_time(&entry.id)),
+                actor: entry.get("actor").unwrap_or_default(),
+                active_role: entry.get::<String>("active_role").filter(|r| !r.is_empty()),
+                role: entry.get("role").unwrap_or_default(),
+                action: entry.get("action").unwrap_or_default(),
+                target: entry.get("target").unwrap_or_default(),
+                summary: entry.get("summary").unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
+/// Rate limiting
+///
+/// * `ratelimit:{group}:{client}` is a hash holding the `tokens` left in a
+///   client's bucket for a group of routes, and when it was `updated`.  It
+///   expires once the bucket would be full again.
+impl Database {
+    /// Take a token from a rate limiting bucket, if there is one to take
+    ///
+    /// If the bucket is empty, returns how long until it won't be.
+    pub async fn take_rate_limit_token(
+        &mut self,
+        bucket: &str,
+        capacity: u32,
+        refill: Duration,
+    ) -> DatabaseResult<Option<Duration>> {
+        let now_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+            .as_millis() as u64;
+        self.take_rate_limit_token_at(bucket, capacity, refill, now_ms)
+            .await
+    }
+
+    /// Take a token from a rate limiting bucket as of the given time
+    async fn take_rate_limit_token_at(
+        &mut self,
+        bucket: &str,
+        capacity: u32,
+        refill: Duration,
+        now_ms: u64,
+    ) -> DatabaseResult<Option<Duration>> {
+        const RATE_LIMIT_SCRIPT: &str = include_str!("scripts/rate_limit.lua");
+        let script = Script::new(RATE_LIMIT_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("ratelimit:{}", bucket))
+            .arg(capacity)
+            .arg(refill.as_millis().max(1) as u64)
+            .arg(now_ms);
+        let wait: u64 = invocation.invoke_async(&mut self.conn).await?;
+        Ok((wait > 0).then(|| Duration::from_millis(wait)))
+    }
+}
+
+/// Moderation
+///
+/// * `identity:suspended` is a hash mapping the UUIDs of suspended identities
+///   to the reason they were suspended
+/// * `admin:audit` is a capped stream of the actions site administrators have
+///   taken, with `admin`, `action`, `target` and `detail` fields
+impl Database {
+    /// How many entries we keep in the admin audit log
+    const ADMIN_AUDIT_LENGTH: usize = 10000;
+
+    /// Why an identity is suspended, if it is
+    pub async fn identity_suspension(&mut self, uuid: &str) -> DatabaseResult<Option<String>> {
+        Ok(Cmd::hget("identity:suspended", uuid)
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Suspend an identity for the given reason, or lift its suspension
+    pub async fn set_identity_suspension(
+        &mut self,
+        uuid: &str,
+        reason: Option<&str>,
+    ) -> DatabaseResult<()> {
+        match reason {
+            Some(reason) => {
+                Cmd::hset("identity:suspended", uuid, reason)
+                    .query_async::<_, ()>(&mut self.conn)
+                    .await?
+            }
+            None => {
+                Cmd::hdel("identity:suspended", uuid)
+                    .query_async::<_, ()>(&mut self.conn)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// The UUIDs of every identity
+    ///
+    /// There is no index of identities, so this scans the keyspace.  It is
+    /// only for the use of site administrators.
+    pub async fn all_identities(&mut self) -> DatabaseResult<Vec<String>> {
+        let mut iter = Cmd::scan_match("identity:*")
+            .iter_async::<String>(&mut self.conn)
+            .await?;
+        let mut ret = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            if let Some(uuid) = key.strip_prefix("identity:") {
+                if Self::smells_like_uuid(uuid) {
+                    ret.push(uuid.to_string());
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+
+    /// Every role's UUID, by short name
+    pub async fn all_roles(&mut self) -> DatabaseResult<HashMap<String, String>> {
+        Ok(Cmd::hgetall("role:byname")
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Every puzzle's UUID, by short name
+    pub async fn all_puzzles(&mut self) -> DatabaseResult<HashMap<String, String>> {
+        Ok(Cmd::hgetall("puzzle:byname")
+            .query_async(&mut self.conn)
+            .await?)
+    }
+
+    /// Record something a site administrator did
+    pub async fn log_admin_action(
+        &mut self,
+        admin: &str,
+        action: &str,
+        target: &str,
+        detail: &str,
+    ) -> DatabaseResult<()> {
+        Cmd::xadd_maxlen(
+            "admin:audit",
+            StreamMaxlen::Approx(Self::ADMIN_AUDIT_LENGTH),
+            "*",
+            &[
+                ("admin", admin),
+                ("action", action),
+                ("target", target),
+                ("detail", detail),
+            ],
+        )
+        .query_async::<_, ()>(&mut self.conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Retrieve the most recent actions of site administrators, newest first
+    pub async fn admin_audit_log(&mut self, count: usize) -> DatabaseResult<Vec<AdminAuditEntry>> {
+        let entries: StreamRangeReply = Cmd::xrevrange_count("admin:audit", "+", "-", count)
+            .query_async(&mut self.conn)
+            .await?;
+        Ok(entries
+            .ids
+            .into_iter()
+            .map(|entry| AdminAuditEntry {
+                when: Self::stream_id_time(&entry.id),
+                admin: entry.get("admin").unwrap_or_default(),
+                action: entry.get("action").unwrap_or_default(),
+                target: entry.get("target").unwrap_or_default(),
+                detail: entry.get("detail").unwrap_or_default(),
+            })
+            .collect())
+    }
+}
+
 // Utility functions
 impl Database {
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::from_secs(0))
+            .as_secs()
+    }
+
+    fn smells_like_stream_id(maybe_id: &str) -> bool {
+        maybe_id
+            .split_once('-')
+            .map(|(ms, seq)| {
+                [ms, seq]
+                    .iter()
+                    .all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Stream IDs start with the time in milliseconds at which the entry was added
+    fn stream_id_time(id: &str) -> u64 {
+        id.split('-')
+            .next()
+            .and_then(|ms| ms.parse::<u64>().ok())
+            .unwrap_or(0)
+            / 1000
+    }
+
     fn smells_like_uuid(maybe_uuid: &str) -> bool {
         (maybe_uuid.len() == 32) && maybe_uuid.bytes().all(|b| b"0123456789abcdef".contains(&b))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Connect to the Redis named by `LINKDOKU_TEST_REDIS`, if there is one
+    ///
+    /// These tests scribble on the database, so never point this at anything
+    /// you care about.
+    async fn test_database() -> Option<Database> {
+        let url = std::env::var("LINKDOKU_TEST_REDIS").ok()?;
+        let client = Client::open(url).expect("Bad LINKDOKU_TEST_REDIS");
+        let conn = ConnectionManager::new(client)
+            .await
+            .expect("Unable to connect to LINKDOKU_TEST_REDIS");
+        Some(Database { conn })
+    }
+
+    #[tokio::test]
+    async fn rate_limit_bucket() {
+        let mut db = match test_database().await {
+            Some(db) => db,
+            None => {
+                eprintln!("LINKDOKU_TEST_REDIS not set, skipping");
+                return;
+            }
+        };
+        let bucket = format!("test:{}", std::process::id());
+        let key = format!("ratelimit:{}", bucket);
+        Cmd::del(&key)
+            .query_async::<_, ()>(&mut db.conn)
+            .await
+            .unwrap();
+        let refill = Duration::from_secs(1);
+        let start = 1_000_000;
+        let take = |offset: u64| {
+            let mut db = db.clone();
+            let bucket = bucket.clone();
+            async move {
+                db.take_rate_limit_token_at(&bucket, 3, refill, start + offset)
+                    .await
+                    .unwrap()
+                    .map(|wait| wait.as_millis())
+            }
+        };
+
+        // A new client gets a full bucket to burst through
+        for _ in 0..3 {
+            assert_eq!(take(0).await, None);
+        }
+        // And is then told how long until the next token
+        assert_eq!(take(0).await, Some(1000));
+        assert_eq!(take(250).await, Some(750));
+        // Which comes back one at a time
+        assert_eq!(take(1000).await, None);
+        assert_eq!(take(1000).await, Some(1000));
+        // The clock running backwards doesn't hand out tokens
+        assert_eq!(take(0).await, Some(1000));
+        // A long wait refills the bucket, but only up to its capacity
+        for _ in 0..3 {
+            assert_eq!(take(60_000).await, None);
+        }
+        assert_eq!(take(60_000).await, Some(1000));
+
+        // The empty bucket is forgotten once it would have filled again
+        let ttl: i64 = Cmd::pttl(&key).query_async(&mut db.conn).await.unwrap();
+        assert!(ttl > 0 && ttl <= 4000, "unexpected TTL {}", ttl);
+        Cmd::del(&key)
+            .query_async::<_, ()>(&mut db.conn)
+            .await
+            .unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use linkdoku_common::{AvatarSource, Profile};
 use serde::{Deserialize, Serialize};
 
 /// Identities are stored in the `identity` prefix in Redis
@@ -9,28 +10,47 @@ use serde::{Deserialize, Serialize};
 ///
 /// Redis keys:
 ///
-/// * `identity:{uuid}` - hash containing display_name and gravatar_hash
+/// * `identity:{uuid}` - hash containing display_name, gravatar_hash, and the
+///   user's own preferred_name and avatar choice
+/// * `identity:deleted` - Set containing UUIDs of identities which were deleted
+/// * `identity:suspended` - hash mapping UUIDs of suspended identities to the reason
 /// * `identity:{uuid}:roles` - Set containing UUIDs of roles this identity can access
+/// * `identity:{uuid}:subjects` - Set containing the login subjects linked to this identity
+/// * `identity:bysubject` - hash mapping login subjects to identity UUIDs
 ///
-#[derive(Serialize, Deserialize, Debug)]
+/// An identity's UUID is derived from the subject it first logged in with,
+/// but further subjects may be linked to it later, so always go via the
+/// `identity:bysubject` index to find the identity for a subject.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Identity {
     pub(crate) uuid: String,
     pub(crate) display_name: String,
     pub(crate) gravatar_hash: Option<String>,
+    #[serde(default)]
+    pub(crate) preferred_name: Option<String>,
+    #[serde(default)]
+    pub(crate) avatar: AvatarSource,
 }
 
 impl Identity {
+    /// The UUID an identity first logging in with the given subject has
+    pub fn subject_uuid(subj: &str) -> String {
+        format!("{:x}", md5::compute(subj))
+    }
+
     /// Create a new identity based on the given display name and
-    /// email address.  This will create a new UUID for the identity
-    /// as well, based on the subject identifier
-    pub fn new(subj: &str, display_name: &str, email: Option<&str>) -> Identity {
-        let uuid = format!("{:x}", md5::compute(subj));
+    /// email address.  The UUID should come from looking up the subject
+    /// the identity logged in with.
+    pub fn new(uuid: String, display_name: &str, email: Option<&str>) -> Identity {
         let display_name = display_name.to_string();
         let gravatar_hash = email.map(|s| format!("{:x}", md5::compute(s)));
         Identity {
             uuid,
             display_name,
             gravatar_hash,
+            preferred_name: None,
+            avatar: AvatarSource::default(),
         }
     }
 
@@ -39,14 +59,32 @@ impl Identity {
         &self.uuid
     }
 
-    /// This identity's display_name
+    /// This identity's display_name, which is their preferred name if they set one
     pub fn display_name(&self) -> &str {
+        self.preferred_name.as_deref().unwrap_or(&self.display_name)
+    }
+
+    /// The display name the login provider gave us
+    pub fn provider_name(&self) -> &str {
         &self.display_name
     }
 
-    /// This identity's gravatar hash
+    /// This identity's gravatar hash, if they want a gravatar
     pub fn gravatar_hash(&self) -> Option<&str> {
-        self.gravatar_hash.as_deref()
+        match self.avatar {
+            AvatarSource::Gravatar => self.gravatar_hash.as_deref(),
+            AvatarSource::None => None,
+        }
+    }
+
+    /// The identity's profile, as they see it
+    pub fn as_profile(&self) -> Profile {
+        Profile {
+            provider_name: self.display_name.clone(),
+            preferred_name: self.preferred_name.clone(),
+            avatar: self.avatar,
+            has_gravatar: self.gravatar_hash.is_some(),
+        }
     }
 
     /// Internal conversion from redis key/value list
@@ -55,12 +93,21 @@ impl Identity {
             uuid: uuid.to_string(),
             display_name: String::new(),
             gravatar_hash: None,
+            preferred_name: None,
+            avatar: AvatarSource::default(),
         };
         while let Some(key) = kvs.next() {
             if let Some(value) = kvs.next() {
                 match key.as_str() {
                     "display_name" => ret.display_name = value,
                     "gravatar_hash" => ret.gravatar_hash = Some(value),
+                    "preferred_name" => ret.preferred_name = Some(value),
+                    "avatar" => {
+                        ret.avatar = match value.as_str() {
+                            "none" => AvatarSource::None,
+                            _ => AvatarSource::Gravatar,
+                        }
+                    }
                     _ => tracing::warn!("Unknown kv pair decoding Identity: {}={}", key, value),
                 }
             }
@@ -76,3 +123,23 @@ impl Identity {
         )
     }
 }
+
+/// The outcome of trying to unlink a login subject from an identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnlinkOutcome {
+    Unlinked,
+    NotLinked,
+    /// The subject is the only way to log in as the identity
+    LastLogin,
+}
+
+/// What becomes of an identity's roles when it is deleted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleDisposal<'a> {
+    /// The roles are left without an owner
+    Orphan,
+    /// The roles are handed to the given identity
+    Reassign(&'a str),
+    /// The roles and all their puzzles are deleted
+    Delete,
+}
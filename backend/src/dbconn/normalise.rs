@@ -8,6 +8,9 @@ const RESERVED_SHORT_NAMES: &[&str] = &[
     "api", "-", "linkdoku", "r", "p", "role", "puzzle", "create", "delete", "rename",
 ];
 
+/// How many candidate short names we check for at once
+const PROBE_BATCH: usize = 16;
+
 /// Normalise a short name name, and ensure it is unique.
 /// Note: this is no guarantee of uniqueness by the time you get to the server later, but it's
 /// a good way to ensure nothing unusual happens.
@@ -26,18 +29,27 @@ pub async fn unique_short_name(
     if RESERVED_SHORT_NAMES.iter().any(|&s| s == short_name) {
         short_name.push('_');
     }
-    // Finally we set a counter at zero, and we try and find a unique role name...
-    let mut full_short_name = short_name.clone();
-    let mut counter = 0;
+    // Finally we look for a unique name, probing candidates in batches so that
+    // popular names don't cost a round trip per suffix tried
     let group_key = format!("{}:byname", group);
+    let mut counter = 0;
     loop {
-        let found: bool = Cmd::hexists(&group_key, &full_short_name)
+        let candidates: Vec<String> = (counter..counter + PROBE_BATCH)
+            .map(|n| match n {
+                0 => short_name.clone(),
+                n => format!("{}_{}", short_name, n - 1),
+            })
+            .collect();
+        let found: Vec<Option<String>> = Cmd::hget(&group_key, &candidates)
             .query_async(&mut database.conn)
             .await?;
-        if !found {
-            break Ok(full_short_name);
+        if let Some((candidate, _)) = candidates
+            .into_iter()
+            .zip(found)
+            .find(|(_, found)| found.is_none())
+        {
+            break Ok(candidate);
         }
-        full_short_name = format!("{}_{}", short_name, counter);
-        counter += 1;
+        counter += PROBE_BATCH;
     }
 }
@@ -2,7 +2,7 @@
 
 use std::io::Cursor;
 
-use linkdoku_common::{PuzzleState, Visibility};
+use linkdoku_common::{PuzzleData, PuzzleState, Visibility};
 use serde::{Deserialize, Serialize};
 
 /// Puzzles are the core data which most users of Linkdoku care about
@@ -14,6 +14,9 @@ use serde::{Deserialize, Serialize};
 ///
 /// * `puzzle:{uuid}` hash containing core puzzle data
 /// * `puzzle:byname` hash containing normalised short-name to puzzle UUID mapping
+/// * `puzzle:{uuid}:solvers` sorted set of identities who solved the puzzle, by time of solve
+/// * `puzzle:{uuid}:solvetimes` sorted set of identities by their fastest solve time
+/// * `puzzle:{uuid}:packs` set of packs which list the puzzle
 ///
 /// Note: a large amount of the puzzle data is actually a compressed serialised JSON object
 #[derive(Debug, Serialize, Deserialize)]
@@ -63,6 +66,29 @@ impl Puzzle {
         self.states.as_ref()
     }
 
+    /// Change the visibility of the puzzle and all of its states
+    pub(crate) fn set_visibility(&mut self, visibility: Visibility, when: String) {
+        self.visibility = visibility;
+        self.visibility_date = Some(when.clone());
+        for state in &mut self.states {
+            state.visibility = visibility;
+            state.visibility_changed = Some(when.clone());
+        }
+    }
+
+    /// Drop a puzzle from every state of this pack, returning whether it was listed
+    pub(crate) fn remove_pack_member(&mut self, member: &str) -> bool {
+        let mut removed = false;
+        for state in &mut self.states {
+            if let PuzzleData::Pack(members) = &mut state.data {
+                let before = members.len();
+                members.retain(|m| m != member);
+                removed |= members.len() != before;
+            }
+        }
+        removed
+    }
+
     /// Load a puzzle from the database
     pub(crate) fn from_list(uuid: &str, mut kvs: impl Iterator<Item = String>) -> Self {
         let mut ret = Self {
@@ -131,6 +157,7 @@ impl Puzzle {
             visibility: self.visibility,
             visibility_changed: self.visibility_date().map(String::from),
             states: Vec::new(),
+            solves: Default::default(),
         };
         for state in &self.states {
             match state.visibility {
@@ -153,6 +180,7 @@ impl From<linkdoku_common::Puzzle> for Puzzle {
             uuid,
             visibility_changed,
             states,
+            ..
         } = input;
         Self {
             uuid,
@@ -47,6 +47,17 @@ impl Role {
         &self.bio
     }
 
+    /// Convert to the form the API hands out
+    pub fn as_api_role(&self) -> linkdoku_common::RoleData {
+        linkdoku_common::RoleData {
+            uuid: self.uuid.clone(),
+            owner: self.owner.clone(),
+            short_name: self.short_name.clone(),
+            display_name: self.display_name.clone(),
+            bio: self.bio.clone(),
+        }
+    }
+
     /// Load a role from the database
     pub(crate) fn from_list(uuid: &str, mut kvs: impl Iterator<Item = String>) -> Role {
         let mut ret = Role {
@@ -0,0 +1,221 @@
+//! Security headers for everything we serve
+//!
+//! Puzzle descriptions are markdown written by users, and link off to other
+//! puzzle sites, so the frontend is served with a content security policy
+//! which only lets it run our own code.  The wasm bundle needs
+//! `'wasm-unsafe-eval'` to be instantiated, and trunk boots it with an inline
+//! script which we allow by hash.  The hashes are worked out from `index.html`
+//! once, and again whenever it changes, so that rebuilds of the frontend are
+//! picked up.  Bulma is served locally, but the
+//! Font Awesome kit is a script which loads its fonts and styles from its own
+//! CDN, and Yew sets inline `style` attributes, so styles may be inline.
+//! Images may come from anywhere over HTTPS since puzzles and avatars link to
+//! them.
+//!
+//! Everything also gets `X-Content-Type-Options`, a `Referrer-Policy` so that
+//! puzzle sites we link to don't learn which page linked them, and HSTS when
+//! the site is served over HTTPS.
+
+use std::{path::Path, sync::Mutex, time::SystemTime};
+
+use axum::{
+    http::{
+        header::{
+            CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY_REPORT_ONLY, CONTENT_TYPE,
+            REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+        },
+        HeaderValue, Request,
+    },
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+
+use crate::state::AppState;
+
+/// Where the Font Awesome kit loads from, and where it loads fonts and styles from
+const FONTAWESOME_KIT: &str = "https://kit.fontawesome.com";
+const FONTAWESOME_CDN: &str = "https://ka-f.fontawesome.com";
+
+/// CSP hashes of the inline scripts in a page
+fn inline_script_hashes(html: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<script") {
+        rest = &rest[start..];
+        let open = match rest.find('>') {
+            Some(end) => &rest[..end],
+            None => break,
+        };
+        rest = &rest[open.len() + 1..];
+        let body = match rest.find("</script>") {
+            Some(end) => &rest[..end],
+            None => break,
+        };
+        if !open.contains("src=") && !body.is_empty() {
+            ret.push(format!(
+                "'sha256-{}'",
+                base64::encode(Sha256::digest(body.as_bytes()))
+            ));
+        }
+        rest = &rest[body.len()..];
+    }
+    ret
+}
+
+/// The built in content security policy for the frontend, allowing the
+/// inline scripts with the given hashes
+fn default_policy(hashes: &[String]) -> String {
+    [
+        "default-src 'self'".to_string(),
+        format!(
+            "script-src 'self' 'wasm-unsafe-eval' {} {}",
+            FONTAWESOME_KIT,
+            hashes.join(" ")
+        ),
+        format!("style-src 'self' 'unsafe-inline' {}", FONTAWESOME_CDN),
+        format!("font-src 'self' data: {}", FONTAWESOME_CDN),
+        "img-src 'self' data: https:".to_string(),
+        format!("connect-src 'self' {}", FONTAWESOME_CDN),
+        "object-src 'none'".to_string(),
+        "base-uri 'self'".to_string(),
+        "form-action 'self'".to_string(),
+        "frame-ancestors 'none'".to_string(),
+    ]
+    .join("; ")
+}
+
+/// The built in content security policy, as of when `index.html` last changed
+#[derive(Default)]
+pub struct PolicyCache {
+    cached: Mutex<Option<(SystemTime, String)>>,
+}
+
+impl PolicyCache {
+    /// The policy for the given `index.html`, only reading it if it has
+    /// changed since we last did
+    async fn policy(&self, index_html: &Path) -> String {
+        let modified = match tokio::fs::metadata(index_html)
+            .await
+            .and_then(|meta| meta.modified())
+        {
+            Ok(modified) => modified,
+            Err(e) => {
+                tracing::error!("Unable to check {}: {:?}", index_html.display(), e);
+                return default_policy(&[]);
+            }
+        };
+        if let Some((when, policy)) = &*self.cached.lock().unwrap() {
+            if *when == modified {
+                return policy.clone();
+            }
+        }
+        let policy = match tokio::fs::read_to_string(index_html).await {
+            Ok(html) => default_policy(&inline_script_hashes(&html)),
+            Err(e) => {
+                tracing::error!("Unable to read {}: {:?}", index_html.display(), e);
+                return default_policy(&[]);
+            }
+        };
+        *self.cached.lock().unwrap() = Some((modified, policy.clone()));
+        policy
+    }
+}
+
+/// Middleware which adds security headers to every response
+pub async fn security_headers<B>(req: Request<B>, next: Next<B>) -> Response {
+    let state = req.extensions().get::<AppState>().cloned();
+    let mut response = next.run(req).await;
+    let state = match state {
+        Some(state) => state,
+        None => return response,
+    };
+    let config = &state.config.security_headers;
+
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/html"))
+        .unwrap_or(false);
+    let policy = if is_html {
+        Some(match &config.content_security_policy {
+            Some(policy) => policy.clone(),
+            None => state.csp_cache.policy(&state.index_html()).await,
+        })
+    } else {
+        None
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    match HeaderValue::from_str(&config.referrer_policy) {
+        Ok(value) => {
+            headers.insert(REFERRER_POLICY, value);
+        }
+        Err(e) => tracing::error!("Bad referrer policy {:?}: {}", config.referrer_policy, e),
+    }
+    if config.hsts_max_age > 0 && state.base_url.starts_with("https://") {
+        let hsts = format!("max-age={}", config.hsts_max_age);
+        if let Ok(value) = HeaderValue::from_str(&hsts) {
+            headers.insert(STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+    if let Some(policy) = policy {
+        let name = if config.report_only {
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            CONTENT_SECURITY_POLICY
+        };
+        match HeaderValue::from_str(&policy) {
+            Ok(value) => {
+                headers.insert(name, value);
+            }
+            Err(e) => tracing::error!("Bad content security policy {:?}: {}", policy, e),
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_inline_scripts() {
+        let html = r#"<html><head>
+<script src="https://kit.fontawesome.com/kit.js" crossorigin="anonymous"></script>
+<script type="module">alert(1)</script>
+<script></script>
+<script>
+      import init from '/-/app.js';
+      init();
+    </script>
+</head></html>"#;
+        assert_eq!(
+            inline_script_hashes(html),
+            vec![
+                "'sha256-bhHHL3z2vDgxUt0W3dWQOrprscmda2Y5pLsLg4GF+pI='".to_string(),
+                "'sha256-g7bd5UMDUUeE4XBcNi0v0OEtlu4UfkzRRGI0wmcD1hc='".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_inline_scripts() {
+        assert!(inline_script_hashes("<html><body>Hello</body></html>").is_empty());
+        assert!(inline_script_hashes(r#"<script src="/-/app.js"></script>"#).is_empty());
+        // An unterminated script can't be hashed
+        assert!(inline_script_hashes("<script>alert(1)").is_empty());
+    }
+
+    #[test]
+    fn policy_allows_hashes() {
+        let policy = default_policy(&["'sha256-abc'".to_string()]);
+        assert!(policy.contains(
+            "script-src 'self' 'wasm-unsafe-eval' https://kit.fontawesome.com 'sha256-abc'"
+        ));
+        assert!(policy.contains("object-src 'none'"));
+    }
+}
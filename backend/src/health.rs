@@ -0,0 +1,33 @@
+//! Health reporting, for monitoring and load balancers
+//!
+//! The site is only considered unhealthy if the database is unreachable,
+//! since nothing works without it.  Login providers which are down are
+//! reported, but the rest of the site carries on without them.
+
+use axum::{http::StatusCode, Extension, Json};
+use linkdoku_common::HealthReport;
+
+use crate::{dbconn::Database, login, state::AppState};
+
+pub async fn health(
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> (StatusCode, Json<HealthReport>) {
+    let database = match dbconn.ping().await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("Health check could not reach the database: {:?}", e);
+            false
+        }
+    };
+    let report = HealthReport {
+        database,
+        providers: login::providers_health(&state.providers),
+    };
+    let status = if database {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json::from(report))
+}
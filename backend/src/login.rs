@@ -8,81 +8,573 @@ OpenID Connect / OAuth login flow is fairly standardised.
 To achieve it we have to create and store nonces in cookies
 and similar things though.
 
-For now our design assumes the google OIDC provider and
-we don't support others.  We might change that later.
+Any number of OIDC providers may be configured.  Each is discovered at
+startup, with failures retried in the background and metadata refreshed
+periodically thereafter, and the claims in the ID tokens it issues are mapped onto our
+identities according to its configuration.
+
+Providers such as GitHub and Discord only offer plain OAuth2, so for
+those we are configured with their endpoints directly, and once we have
+an access token we fetch the user's details from their userinfo endpoint
+and map its fields as if they were claims.
+
+Either way, subjects are namespaced by the provider's name, so the same
+subject at two providers yields two distinct identities, unless the user
+links the second to their identity while logged in with the first.
+
+For development and testing, the configuration may enable a `dev`
+provider.  Rather than sending the user elsewhere, its flow sends them to
+our own dev login page where they can say who they'd like to be, and that
+page comes back with their choice in place of an authorization code.  From
+there on logins proceed exactly as for any other provider.
 
 */
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
 use axum::{
     extract::{Path, Query},
-    http::StatusCode,
-    routing::get,
+    routing::{get, post},
     Extension, Json, Router,
 };
 use cookie::SameSite;
-use lazy_static::lazy_static;
-use linkdoku_common::{BackendLoginStatus, LoginFlowResult, LoginFlowStart};
+use linkdoku_common::{
+    BackendLoginStatus, ChooseRoleRequest, DevLogin, LinkedLogin, LoginFlowResult, LoginFlowStart,
+    LoginProvider, ProviderHealth, UnlinkLoginRequest, UnlinkLoginResponse,
+};
+use oauth2::basic::BasicClient;
 use openidconnect::{
     core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata},
     reqwest::async_http_client,
     url::Url,
-    AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, PkceCodeChallenge,
-    PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce,
+    OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+    TokenUrl,
 };
+use reqwest::header::{ACCEPT, USER_AGENT};
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use serde_json::Value;
 use tower_cookies::{Cookie, Cookies, Key};
 use tracing::instrument;
 
 use crate::{
-    config::Configuration,
-    dbconn::{Database, Identity},
+    activity::{self, Actor},
+    auth::LoggedInUser,
+    config::{ClaimMapping, Configuration},
+    dbconn::{Database, DatabaseError, Identity, UnlinkOutcome},
+    state::AppState,
 };
 
-struct ProviderSetup {
+/// How we talk to a login provider
+enum ProviderKind {
+    /// OpenID Connect, where the user's details come in a signed ID token
+    OpenID {
+        issuer: IssuerUrl,
+        discovery: RwLock<Discovery>,
+    },
+    /// Plain OAuth2, where we fetch the user's details with the access token
+    OAuth2 {
+        auth_url: AuthUrl,
+        token_url: TokenUrl,
+        userinfo_url: String,
+        subject_claim: String,
+    },
+    /// Development logins, where the user says who they are on our own page
+    Dev { login_page: Url },
+}
+
+/// The name of the development login provider
+const DEV_PROVIDER: &str = "dev";
+
+/// How long we wait before retrying a failed discovery, this doubles with
+/// each consecutive failure up to [`MAX_RETRY_DELAY`]
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// How often we refresh discovered metadata, which picks up rotated signing keys
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// What we know of an OIDC provider's metadata
+///
+/// Metadata which fails to refresh is kept, since a provider's signing keys
+/// rarely change and stale keys are better than none.
+#[derive(Default)]
+struct Discovery {
+    metadata: Option<CoreProviderMetadata>,
+    /// When we last successfully discovered the provider
+    last_success: Option<u64>,
+    /// How many attempts have failed since then
+    failures: u32,
+    last_error: Option<String>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub struct ProviderSetup {
+    label: String,
+    icon: Option<String>,
     client_id: String,
     client_secret: String,
-    provider_metadata: CoreProviderMetadata,
+    kind: ProviderKind,
     scopes: Vec<Scope>,
+    redirect_url: String,
+    claims: ClaimMapping,
 }
 
-async fn load_providers(providers: &mut HashMap<String, ProviderSetup>, config: &Configuration) {
-    for (name, oidp) in config.openid.iter() {
-        tracing::info!("Loading OIDC metdata for {} from config", name);
-        let provider_metadata = CoreProviderMetadata::discover_async(
-            IssuerUrl::new(oidp.discovery_doc.clone()).expect("Unable to grok discovery_doc url"),
-            async_http_client,
+impl ProviderSetup {
+    fn redirect_url(&self) -> RedirectUrl {
+        RedirectUrl::new(self.redirect_url.clone()).expect("Unable to grok redirect_url")
+    }
+
+    fn oidc_client(&self, provider_metadata: &CoreProviderMetadata) -> CoreClient {
+        CoreClient::from_provider_metadata(
+            provider_metadata.clone(),
+            ClientId::new(self.client_id.clone()),
+            Some(ClientSecret::new(self.client_secret.clone())),
+        )
+        .set_redirect_uri(self.redirect_url())
+    }
+
+    fn oauth2_client(&self, auth_url: &AuthUrl, token_url: &TokenUrl) -> BasicClient {
+        BasicClient::new(
+            ClientId::new(self.client_id.clone()),
+            Some(ClientSecret::new(self.client_secret.clone())),
+            auth_url.clone(),
+            Some(token_url.clone()),
         )
-        .await;
-        if let Ok(provider_metadata) = provider_metadata {
-            tracing::info!("Loaded openid connect provider {}", name);
-            let client_id = oidp.client_id.clone();
-            let client_secret = oidp.client_secret.clone();
-            let scopes = oidp
-                .scopes
-                .iter()
-                .map(String::clone)
-                .map(Scope::new)
-                .collect();
-            providers.insert(
-                name.to_lowercase(),
-                ProviderSetup {
-                    client_id,
-                    client_secret,
-                    provider_metadata,
-                    scopes,
+        .set_redirect_uri(self.redirect_url())
+    }
+
+    fn metadata(&self) -> Option<CoreProviderMetadata> {
+        match &self.kind {
+            ProviderKind::OpenID { discovery, .. } => discovery
+                .read()
+                .expect("Discovery lock poisoned")
+                .metadata
+                .clone(),
+            _ => None,
+        }
+    }
+
+    /// Whether users can currently log in with this provider
+    ///
+    /// Only OIDC providers can be unavailable, if we have yet to discover them
+    fn is_live(&self) -> bool {
+        match &self.kind {
+            ProviderKind::OpenID { discovery, .. } => discovery
+                .read()
+                .expect("Discovery lock poisoned")
+                .metadata
+                .is_some(),
+            _ => true,
+        }
+    }
+
+    /// (Re)discover an OIDC provider's metadata, including its signing keys
+    ///
+    /// Returns how many attempts have failed in a row, so zero on success
+    async fn discover(&self, name: &str) -> u32 {
+        let (issuer, discovery) = match &self.kind {
+            ProviderKind::OpenID { issuer, discovery } => (issuer, discovery),
+            _ => return 0,
+        };
+        tracing::info!("Loading OIDC metdata for {}", name);
+        let result = CoreProviderMetadata::discover_async(issuer.clone(), async_http_client).await;
+        let mut discovery = discovery.write().expect("Discovery lock poisoned");
+        match result {
+            Ok(metadata) => {
+                tracing::info!("Loaded openid connect provider {}", name);
+                discovery.metadata = Some(metadata);
+                discovery.last_success = Some(now());
+                discovery.failures = 0;
+                discovery.last_error = None;
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Unable to discover openid connect provider {}: {:?}",
+                    name,
+                    e
+                );
+                discovery.failures += 1;
+                discovery.last_error = Some(e.to_string());
+            }
+        }
+        discovery.failures
+    }
+
+    fn health(&self, name: &str) -> ProviderHealth {
+        let mut health = ProviderHealth {
+            name: name.to_string(),
+            live: true,
+            ..Default::default()
+        };
+        if let ProviderKind::OpenID { discovery, .. } = &self.kind {
+            let discovery = discovery.read().expect("Discovery lock poisoned");
+            health.live = discovery.metadata.is_some();
+            health.last_discovered = discovery.last_success;
+            health.failures = discovery.failures;
+            health.last_error = discovery.last_error.clone();
+        }
+        health
+    }
+
+    fn as_login_provider(&self, name: &str) -> LoginProvider {
+        LoginProvider {
+            name: name.to_string(),
+            label: self.label.clone(),
+            icon: self.icon.clone(),
+        }
+    }
+
+    /// Begin a login flow, the user is to be sent to the returned setup's URL
+    ///
+    /// Returns `None` if the provider has yet to be discovered.
+    fn start_flow(&self, provider: String, link: bool) -> Option<LoginFlowSetup> {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf_token, nonce) = match &self.kind {
+            ProviderKind::OpenID { .. } => {
+                let client = self.oidc_client(&self.metadata()?);
+                let mut actor = client.authorize_url(
+                    CoreAuthenticationFlow::AuthorizationCode,
+                    CsrfToken::new_random,
+                    Nonce::new_random,
+                );
+                for scope in self.scopes.iter() {
+                    actor = actor.add_scope(scope.clone());
+                }
+                let (url, csrf_token, nonce) = actor.set_pkce_challenge(pkce_challenge).url();
+                (url, csrf_token, Some(nonce))
+            }
+            ProviderKind::OAuth2 {
+                auth_url,
+                token_url,
+                ..
+            } => {
+                let client = self.oauth2_client(auth_url, token_url);
+                let (url, csrf_token) = client
+                    .authorize_url(CsrfToken::new_random)
+                    .add_scopes(self.scopes.iter().cloned())
+                    .set_pkce_challenge(pkce_challenge)
+                    .url();
+                (url, csrf_token, None)
+            }
+            ProviderKind::Dev { login_page } => {
+                let csrf_token = CsrfToken::new_random();
+                let mut url = login_page.clone();
+                url.query_pairs_mut()
+                    .append_pair("state", csrf_token.secret());
+                (url, csrf_token, None)
+            }
+        };
+        Some(LoginFlowSetup {
+            provider,
+            pkce_verifier,
+            url,
+            csrf_token,
+            nonce,
+            link,
+        })
+    }
+
+    /// Exchange an authorization code for the details of the user
+    async fn exchange_code(
+        &self,
+        state: &AppState,
+        setup: &LoginFlowSetup,
+        code: String,
+    ) -> Result<LoginDetails, String> {
+        let code = AuthorizationCode::new(code);
+        let pkce_verifier = PkceCodeVerifier::new(setup.pkce_verifier.secret().clone());
+        let exchange_failed = |e: String| {
+            tracing::error!("Failed exchanging codes: {}", e);
+            "code-exchange-failed".to_string()
+        };
+        tracing::info!("Trying to transact code: {}", code.secret());
+        match &self.kind {
+            ProviderKind::OpenID { .. } => {
+                let provider_metadata = self
+                    .metadata()
+                    .ok_or_else(|| "provider-unavailable".to_string())?;
+                let client = self.oidc_client(&provider_metadata);
+                let token_response = client
+                    .exchange_code(code)
+                    .set_pkce_verifier(pkce_verifier)
+                    .request_async(async_http_client)
+                    .await
+                    .map_err(|e| exchange_failed(format!("{:?}", e)))?;
+                let id_token = token_response.id_token().ok_or_else(|| {
+                    tracing::error!("Failed to get id_token");
+                    "no-id-token".to_string()
+                })?;
+                let nonce = setup
+                    .nonce
+                    .as_ref()
+                    .ok_or_else(|| "bad-state".to_string())?;
+                let claims = id_token
+                    .claims(&client.id_token_verifier(), nonce)
+                    .map_err(|e| {
+                        tracing::error!("Failed to verify id_token: {:?}", e);
+                        "bad-id-token".to_string()
+                    })?;
+                // Claims are mapped by name, so work from their JSON form
+                let claim_values = serde_json::to_value(claims).unwrap_or_default();
+                Ok(LoginDetails::from_claims(
+                    &setup.provider,
+                    claims.subject().as_str(),
+                    &self.claims,
+                    &claim_values,
+                ))
+            }
+            ProviderKind::OAuth2 {
+                auth_url,
+                token_url,
+                userinfo_url,
+                subject_claim,
+            } => {
+                let client = self.oauth2_client(auth_url, token_url);
+                let token_response = client
+                    .exchange_code(code)
+                    .set_pkce_verifier(pkce_verifier)
+                    .request_async(async_http_client)
+                    .await
+                    .map_err(|e| exchange_failed(format!("{:?}", e)))?;
+                let userinfo = fetch_userinfo(
+                    &state.http_client,
+                    userinfo_url,
+                    token_response.access_token().secret(),
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed fetching userinfo: {}", e);
+                    "userinfo-failed".to_string()
+                })?;
+                let subject = find_claim(&userinfo, std::slice::from_ref(subject_claim))
+                    .ok_or_else(|| {
+                        tracing::error!("Userinfo has no {} field", subject_claim);
+                        "no-subject".to_string()
+                    })?;
+                Ok(LoginDetails::from_claims(
+                    &setup.provider,
+                    &subject,
+                    &self.claims,
+                    &userinfo,
+                ))
+            }
+            ProviderKind::Dev { .. } => {
+                // The dev login page sends us who the user wants to be in place of a code
+                let login: DevLogin = serde_json::from_str(code.secret()).map_err(|e| {
+                    tracing::error!("Bad dev login: {:?}", e);
+                    "bad-code".to_string()
+                })?;
+                let subject = login.subject.trim();
+                if subject.is_empty() {
+                    return Err("no-subject".to_string());
+                }
+                let given = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+                Ok(LoginDetails {
+                    subject: format!("{}:{}", setup.provider, subject),
+                    display_name: given(login.display_name),
+                    email: given(login.email),
+                    preferred_username: Some(subject.to_string()),
+                })
+            }
+        }
+    }
+}
+
+/// Fetch the user's details from a plain OAuth2 provider
+async fn fetch_userinfo(
+    client: &reqwest::Client,
+    userinfo_url: &str,
+    access_token: &str,
+) -> Result<Value, String> {
+    let body = client
+        .get(userinfo_url)
+        .bearer_auth(access_token)
+        .header(ACCEPT, "application/json")
+        // GitHub refuses requests without a user agent
+        .header(USER_AGENT, "Linkdoku")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| e.to_string())?
+        .bytes()
+        .await
+        .map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+/// The default label for a provider is its name, capitalised
+fn default_label(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[instrument(skip(config))]
+pub async fn load_providers(config: &Configuration) -> HashMap<String, ProviderSetup> {
+    tracing::info!("Loading OIDC providers...");
+    let mut providers = HashMap::new();
+    for (name, oidp) in config.openid.iter() {
+        let scopes = oidp
+            .scopes
+            .iter()
+            .map(String::clone)
+            .map(Scope::new)
+            .collect();
+        providers.insert(
+            name.to_lowercase(),
+            ProviderSetup {
+                label: oidp.label.clone().unwrap_or_else(|| default_label(name)),
+                icon: oidp.icon.clone(),
+                client_id: oidp.client_id.clone(),
+                client_secret: oidp.client_secret.clone(),
+                kind: ProviderKind::OpenID {
+                    issuer: IssuerUrl::new(oidp.discovery_doc.clone())
+                        .expect("Unable to grok discovery_doc url"),
+                    discovery: RwLock::default(),
+                },
+                scopes,
+                redirect_url: oidp
+                    .redirect_url
+                    .clone()
+                    .unwrap_or_else(|| config.redirect_url.clone()),
+                claims: oidp.claims.clone(),
+            },
+        );
+    }
+    for (name, oauth) in config.oauth2.iter() {
+        if providers.contains_key(&name.to_lowercase()) {
+            tracing::error!("OAuth2 provider {} clashes with an OIDC provider", name);
+            continue;
+        }
+        tracing::info!("Loaded oauth2 provider {}", name);
+        let scopes = oauth
+            .scopes
+            .iter()
+            .map(String::clone)
+            .map(Scope::new)
+            .collect();
+        providers.insert(
+            name.to_lowercase(),
+            ProviderSetup {
+                label: oauth.label.clone().unwrap_or_else(|| default_label(name)),
+                icon: oauth.icon.clone(),
+                client_id: oauth.client_id.clone(),
+                client_secret: oauth.client_secret.clone(),
+                kind: ProviderKind::OAuth2 {
+                    auth_url: AuthUrl::new(oauth.auth_url.clone())
+                        .expect("Unable to grok auth_url"),
+                    token_url: TokenUrl::new(oauth.token_url.clone())
+                        .expect("Unable to grok token_url"),
+                    userinfo_url: oauth.userinfo_url.clone(),
+                    subject_claim: oauth.subject_claim.clone(),
                 },
-            );
+                scopes,
+                redirect_url: oauth
+                    .redirect_url
+                    .clone()
+                    .unwrap_or_else(|| config.redirect_url.clone()),
+                claims: oauth.claims.clone(),
+            },
+        );
+    }
+    if config.dev_login {
+        tracing::warn!("The dev login provider is enabled, anyone can log in as anyone");
+        let mut login_page = Url::parse(&config.redirect_url).expect("Unable to grok redirect_url");
+        login_page.set_path("/-/dev-login");
+        login_page.set_query(None);
+        providers.insert(
+            DEV_PROVIDER.to_string(),
+            ProviderSetup {
+                label: "Developer".to_string(),
+                icon: Some("fa-solid fa-code".to_string()),
+                client_id: String::new(),
+                client_secret: String::new(),
+                kind: ProviderKind::Dev { login_page },
+                scopes: Vec::new(),
+                redirect_url: config.redirect_url.clone(),
+                claims: ClaimMapping::default(),
+            },
+        );
+    }
+    // Providers which fail discovery now will be retried by `maintain_providers`
+    for (name, provider) in providers.iter() {
+        provider.discover(name).await;
+    }
+    tracing::info!(
+        "Loaded {} providers, {} live",
+        providers.len(),
+        providers.values().filter(|p| p.is_live()).count()
+    );
+    providers
+}
+
+/// Keep OIDC providers' metadata fresh for as long as the server runs
+///
+/// Providers which failed discovery are retried with backoff, and the rest
+/// are refreshed periodically so that we pick up new signing keys.
+pub async fn maintain_providers(providers: Arc<HashMap<String, ProviderSetup>>) {
+    for (name, provider) in providers.iter() {
+        if matches!(provider.kind, ProviderKind::OpenID { .. }) {
+            tokio::spawn(maintain_provider(providers.clone(), name.clone()));
         }
     }
 }
 
-lazy_static! {
-    static ref REDIRECT_URL: Mutex<String> = Mutex::new(String::new());
-    static ref PROVIDERS: Mutex<HashMap<String, ProviderSetup>> = Mutex::new(HashMap::new());
-    static ref LOGIN_KEY: Mutex<Key> = Mutex::new(Key::generate());
+async fn maintain_provider(providers: Arc<HashMap<String, ProviderSetup>>, name: String) {
+    let provider = &providers[&name];
+    let mut failures = provider.health(&name).failures;
+    loop {
+        let delay = if failures == 0 {
+            REFRESH_INTERVAL
+        } else {
+            (RETRY_DELAY * 2u32.pow((failures - 1).min(10))).min(MAX_RETRY_DELAY)
+        };
+        tokio::time::sleep(delay).await;
+        failures = provider.discover(&name).await;
+    }
+}
+
+/// Look up the first of the named claims which the provider gave us
+fn find_claim(claims: &Value, names: &[String]) -> Option<String> {
+    names.iter().find_map(|name| match claims.get(name)? {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    })
+}
+
+/// What a provider told us about the user who just logged in
+struct LoginDetails {
+    /// The namespaced subject, `provider:subject`
+    subject: String,
+    display_name: Option<String>,
+    email: Option<String>,
+    preferred_username: Option<String>,
+}
+
+impl LoginDetails {
+    fn from_claims(provider: &str, subject: &str, mapping: &ClaimMapping, claims: &Value) -> Self {
+        Self {
+            subject: format!("{}:{}", provider, subject),
+            display_name: find_claim(claims, &mapping.display_name),
+            email: find_claim(claims, &mapping.email),
+            preferred_username: find_claim(claims, &mapping.preferred_username),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -91,7 +583,12 @@ struct LoginFlowSetup {
     pkce_verifier: PkceCodeVerifier,
     url: Url,
     csrf_token: CsrfToken,
-    nonce: Nonce,
+    /// Only OIDC flows have a nonce
+    #[serde(default)]
+    nonce: Option<Nonce>,
+    /// Whether this flow links another login to the logged in identity
+    #[serde(default)]
+    link: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -102,9 +599,21 @@ pub struct LoginFlowUserData {
 }
 
 impl LoginFlowUserData {
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
     pub fn has_role(&self, role: &str) -> bool {
         self.cached_roles.iter().any(|r| r == role)
     }
+
+    pub fn roles(&self) -> &[String] {
+        &self.cached_roles
+    }
+
+    pub fn active_role(&self) -> &str {
+        &self.active_role
+    }
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -119,10 +628,11 @@ impl LoginFlowStatus {
     }
 }
 
-pub async fn login_flow_status(cookies: &Cookies) -> LoginFlowStatus {
+/// The login state in the request's cookie, taken at face value
+fn read_login_flow_status(cookies: &Cookies, key: &Key) -> LoginFlowStatus {
     serde_json::from_str(
         &cookies
-            .private(&*LOGIN_KEY.lock().await)
+            .private(key)
             .get("login")
             .map(|c| c.value().to_owned())
             .unwrap_or_default(),
@@ -130,8 +640,54 @@ pub async fn login_flow_status(cookies: &Cookies) -> LoginFlowStatus {
     .unwrap_or_default()
 }
 
-async fn set_login_flow_status(cookies: &Cookies, login: &LoginFlowStatus) {
-    cookies.private(&*LOGIN_KEY.lock().await).add(
+/// The UUID of the identity logged in with the request's cookie, if any
+///
+/// This is taken at face value, so it's only good for things like rate
+/// limiting, not for deciding what the request may do.
+pub fn cookie_identity(cookies: &Cookies, key: &Key) -> Option<String> {
+    read_login_flow_status(cookies, key)
+        .user
+        .map(|user| user.identity.uuid)
+}
+
+/// The login state of the request
+///
+/// A logged in identity may have been deleted or suspended since it logged
+/// in, perhaps from another session, in which case it is logged out here and
+/// now.
+pub async fn login_flow_status(
+    cookies: &Cookies,
+    key: &Key,
+    dbconn: &mut Database,
+) -> LoginFlowStatus {
+    let mut flow = read_login_flow_status(cookies, key);
+    if let Some(user) = flow.user.as_ref() {
+        let uuid = user.identity.uuid().to_string();
+        let reason = match dbconn.identity_deleted(&uuid).await {
+            Ok(true) => Ok(Some("deleted".to_string())),
+            Ok(false) => dbconn
+                .identity_suspension(&uuid)
+                .await
+                .map(|reason| reason.map(|reason| format!("suspended: {}", reason))),
+            Err(e) => Err(e),
+        };
+        match reason {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                tracing::info!("Logging out identity {}, {}", uuid, reason);
+                flow.user = None;
+                set_login_flow_status(cookies, key, &flow);
+            }
+            Err(e) => {
+                tracing::error!("Unable to check status of {}: {:?}", uuid, e);
+            }
+        }
+    }
+    flow
+}
+
+fn set_login_flow_status(cookies: &Cookies, key: &Key, login: &LoginFlowStatus) {
+    cookies.private(key).add(
         Cookie::build(
             "login",
             serde_json::to_string(login).expect("Unable to serialise login"),
@@ -142,60 +698,74 @@ async fn set_login_flow_status(cookies: &Cookies, login: &LoginFlowStatus) {
     );
 }
 
-async fn start_auth(Path(provider): Path<String>, cookies: Cookies) -> Json<LoginFlowStart> {
-    let mut flow = login_flow_status(&cookies).await;
-    // First up, if we're already logged in, just redirect the user to the root of the app
-    if flow.user.is_some() {
-        return Json::from(LoginFlowStart::Idle);
-    }
+/// Set up a login flow with the given provider, unless one is already underway
+async fn begin_flow(
+    cookies: &Cookies,
+    state: &AppState,
+    mut flow: LoginFlowStatus,
+    provider: String,
+    link: bool,
+) -> LoginFlowStart {
     if let Some(setup) = flow.flow.as_ref() {
-        if setup.provider == provider {
+        if setup.provider == provider && setup.link == link {
             // We already have a login flow in progress, so redirect the user again
-            return Json::from(LoginFlowStart::Redirect(setup.url.to_string()));
+            return LoginFlowStart::Redirect(setup.url.to_string());
         }
     }
-    if let Some(provider_data) = PROVIDERS.lock().await.get(&provider) {
-        // Either no flow in progress, or user is trying a different flow for whatever reason
-        let client = CoreClient::from_provider_metadata(
-            provider_data.provider_metadata.clone(),
-            ClientId::new(provider_data.client_id.clone()),
-            Some(ClientSecret::new(provider_data.client_secret.clone())),
-        )
-        .set_redirect_uri(RedirectUrl::new(REDIRECT_URL.lock().await.clone()).unwrap());
+    let provider_data = match state.providers.get(&provider) {
+        Some(provider_data) => provider_data,
+        None => {
+            // Selected provider was not available, let's go again
+            return LoginFlowStart::Error(format!("Provider: {} not known", provider));
+        }
+    };
+    // If we've not managed to discover the provider yet, have another go now
+    // rather than waiting for the next retry
+    if !provider_data.is_live() {
+        provider_data.discover(&provider).await;
+    }
+    // Either no flow in progress, or user is trying a different flow for whatever reason
+    match provider_data.start_flow(provider.clone(), link) {
+        Some(setup) => {
+            let url = setup.url.clone();
+            flow.flow = Some(setup);
 
-        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
-        let (url, csrf_token, nonce) = {
-            let mut actor = client.authorize_url(
-                CoreAuthenticationFlow::AuthorizationCode,
-                CsrfToken::new_random,
-                Nonce::new_random,
-            );
-            for scope in provider_data.scopes.iter() {
-                actor = actor.add_scope(scope.clone());
-            }
-            actor.set_pkce_challenge(pkce_challenge).url()
-        };
+            tracing::info!("Set up flow: {:?}", flow.flow);
 
-        flow.flow = Some(LoginFlowSetup {
-            provider,
-            pkce_verifier,
-            url: url.clone(),
-            csrf_token,
-            nonce,
-        });
+            set_login_flow_status(cookies, &state.login_key, &flow);
 
-        tracing::info!("Set up flow: {:?}", flow.flow);
+            LoginFlowStart::Redirect(url.to_string())
+        }
+        None => LoginFlowStart::Error(format!("Provider: {} is unavailable", provider)),
+    }
+}
 
-        set_login_flow_status(&cookies, &flow).await;
+async fn start_auth(
+    Path(provider): Path<String>,
+    cookies: Cookies,
+    Extension(state): Extension<AppState>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<LoginFlowStart> {
+    let flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
+    // First up, if we're already logged in, just redirect the user to the root of the app
+    if flow.user.is_some() {
+        return Json::from(LoginFlowStart::Idle);
+    }
+    Json::from(begin_flow(&cookies, &state, flow, provider, false).await)
+}
 
-        Json::from(LoginFlowStart::Redirect(url.to_string()))
-    } else {
-        // Selected provider was not available, let's go again
-        Json::from(LoginFlowStart::Error(format!(
-            "Provider: {} not known",
-            provider
-        )))
+/// Start a flow which links another login to the logged in identity
+async fn start_link(
+    Path(provider): Path<String>,
+    cookies: Cookies,
+    Extension(state): Extension<AppState>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<LoginFlowStart> {
+    let flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
+    if flow.user.is_none() {
+        return Json::from(LoginFlowStart::Error("Not logged in".to_string()));
     }
+    Json::from(begin_flow(&cookies, &state, flow, provider, true).await)
 }
 
 #[derive(Deserialize)]
@@ -205,141 +775,236 @@ struct LoginContinueQuery {
     error: Option<String>,
 }
 
+/// Check the user came back from the flow we set up, and exchange the code
+/// the provider sent them back with for their details
+async fn continue_flow(
+    state: &AppState,
+    setup: &LoginFlowSetup,
+    params: LoginContinueQuery,
+) -> Result<LoginDetails, String> {
+    if params.state.as_ref() != Some(setup.csrf_token.secret()) {
+        return Err("bad-state".to_string());
+    }
+    if let Some(error) = params.error {
+        tracing::error!("Error in flow: {}", error);
+        return Err(error);
+    }
+    let code = params.code.ok_or_else(|| "no-code".to_string())?;
+    let provider_data = state
+        .providers
+        .get(&setup.provider)
+        .ok_or_else(|| "bad-provider".to_string())?;
+    provider_data.exchange_code(state, setup, code).await
+}
+
+/// Log in the identity described by the details, creating it if need be
+async fn complete_login(
+    dbconn: &mut Database,
+    flow: &mut LoginFlowStatus,
+    details: LoginDetails,
+) -> Result<(), String> {
+    let database_error = |e: DatabaseError| {
+        tracing::error!("Failed upserting identity: {:?}", e);
+        "database-error".to_string()
+    };
+    let uuid = dbconn
+        .identity_uuid_for_subject(&details.subject)
+        .await
+        .map_err(database_error)?;
+    if let Some(reason) = dbconn
+        .identity_suspension(&uuid)
+        .await
+        .map_err(database_error)?
+    {
+        tracing::info!("Refusing login to suspended identity {}: {}", uuid, reason);
+        return Err("suspended".to_string());
+    }
+    let identity = Identity::new(
+        uuid,
+        details.display_name.as_ref().unwrap_or(&details.subject),
+        details.email.as_deref(),
+    );
+    let mut roles = dbconn
+        .identity_upsert_and_roles(&identity, &details.subject)
+        .await
+        .map_err(database_error)?;
+    // The stored identity may have a preferred name and so on
+    let identity = dbconn
+        .identity_by_uuid(identity.uuid())
+        .await
+        .map_err(database_error)?
+        .unwrap_or(identity);
+    let default_role = identity.get_default_role();
+    if !roles.iter().any(|v| v == &default_role) {
+        dbconn
+            .create_default_role(&identity, details.preferred_username.as_deref())
+            .await
+            .map_err(|e| {
+                tracing::error!(
+                    "Failed creating default role for identity {:?}: {:?}",
+                    identity,
+                    e
+                );
+                "database-error".to_string()
+            })?;
+        if let Ok(role) = dbconn.role_by_uuid_or_short_name(&default_role).await {
+            activity::record(
+                dbconn,
+                &Actor::identity(&identity),
+                role.uuid(),
+                "role-create",
+                role.uuid(),
+                activity::diff(&[
+                    ("short_name", "", role.short_name()),
+                    ("display_name", "", role.display_name()),
+                ]),
+            )
+            .await;
+        }
+        roles.push(default_role.clone());
+    }
+    flow.user = Some(LoginFlowUserData {
+        identity,
+        cached_roles: roles,
+        active_role: default_role,
+    });
+    Ok(())
+}
+
+/// Link the subject described by the details to the logged in identity
+async fn link_login(
+    dbconn: &mut Database,
+    flow: &LoginFlowStatus,
+    details: LoginDetails,
+) -> Result<(), String> {
+    let user = flow
+        .user
+        .as_ref()
+        .ok_or_else(|| "not-logged-in".to_string())?;
+    match dbconn
+        .link_subject(user.identity.uuid(), &details.subject)
+        .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("already-linked".to_string()),
+        Err(e) => {
+            tracing::error!("Failed linking {}: {:?}", details.subject, e);
+            Err("database-error".to_string())
+        }
+    }
+}
+
 async fn handle_login_continue(
     cookies: Cookies,
     Query(params): Query<LoginContinueQuery>,
     Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
 ) -> Json<LoginFlowResult> {
-    let mut flow = login_flow_status(&cookies).await;
-    // First up, if we're already logged in, just redirect the user to the root of the app
-    if flow.user.is_some() {
-        return Json::from(LoginFlowResult { error: None });
+    let mut flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
+    let linking = flow.flow.as_ref().map(|setup| setup.link).unwrap_or(false);
+    // First up, if we're already logged in, just redirect the user to the root of the app,
+    // unless they're linking another login
+    if flow.user.is_some() && !linking {
+        return Json::from(LoginFlowResult {
+            error: None,
+            linked: false,
+        });
     }
-    if let Some(setup) = flow.flow.as_ref() {
-        // Flow is in progress, so let's check the state first
-        if params.state.as_ref() != Some(setup.csrf_token.secret()) {
-            // State value is bad, so clean up and BAD_REQUEST
-            flow.flow = None;
-            set_login_flow_status(&cookies, &flow).await;
+    // If no login is in progress, redirect user to root
+    let setup = match flow.flow.take() {
+        Some(setup) => setup,
+        None => {
             return Json::from(LoginFlowResult {
-                error: Some("bad-state".to_string()),
-            });
-        }
-        if let Some(error) = params.error {
-            tracing::error!("Error in flow: {}", error);
-            flow.flow = None;
-            set_login_flow_status(&cookies, &flow).await;
-            return Json::from(LoginFlowResult { error: Some(error) });
+                error: None,
+                linked: false,
+            })
         }
-        let code = params.code.as_deref().unwrap();
-        tracing::info!("Trying to transact code: {}", code);
-        if let Some(provider_data) = PROVIDERS.lock().await.get(&setup.provider) {
-            let client = CoreClient::from_provider_metadata(
-                provider_data.provider_metadata.clone(),
-                ClientId::new(provider_data.client_id.clone()),
-                Some(ClientSecret::new(provider_data.client_secret.clone())),
-            )
-            .set_redirect_uri(RedirectUrl::new(REDIRECT_URL.lock().await.clone()).unwrap());
-            match client
-                .exchange_code(AuthorizationCode::new(code.to_string()))
-                .set_pkce_verifier(PkceCodeVerifier::new(setup.pkce_verifier.secret().clone()))
-                .request_async(async_http_client)
-                .await
-            {
-                Ok(token_response) => {
-                    let id_token = match token_response.id_token() {
-                        Some(token) => token,
-                        None => {
-                            tracing::error!("Failed to get id_token");
-                            flow.flow = None;
-                            set_login_flow_status(&cookies, &flow).await;
-                            return Json::from(LoginFlowResult {
-                                error: Some("no-id-token".to_string()),
-                            });
-                        }
-                    };
-                    let claims = match id_token.claims(&client.id_token_verifier(), &setup.nonce) {
-                        Ok(claims) => claims,
-                        Err(e) => {
-                            tracing::error!("Failed to verify id_token: {:?}", e);
-                            flow.flow = None;
-                            set_login_flow_status(&cookies, &flow).await;
-                            return Json::from(LoginFlowResult {
-                                error: Some("bad-id-token".to_string()),
-                            });
-                        }
-                    };
-                    // Okay, at this point we *are* logged in, so let's prepare our data
-                    let subject = format!("{}:{}", setup.provider, claims.subject().as_str());
-                    let name = claims
-                        .name()
-                        .and_then(|n| n.get(None).map(|n| n.to_string()));
-                    let email = claims.email().map(|e| e.to_string());
-                    flow.flow = None;
-                    let identity = Identity::new(
-                        &subject,
-                        name.as_ref().unwrap_or(&subject),
-                        email.as_deref(),
-                    );
-                    match dbconn.identity_upsert_and_roles(&identity).await {
-                        Ok(mut roles) => {
-                            let default_role = identity.get_default_role();
-                            if !roles.iter().any(|v| v == &default_role) {
-                                if let Err(e) = dbconn.create_default_role(&identity).await {
-                                    tracing::error!(
-                                        "Failed creating default role for identity {:?}: {:?}",
-                                        identity,
-                                        e
-                                    );
-                                    set_login_flow_status(&cookies, &flow).await;
-                                    return Json::from(LoginFlowResult {
-                                        error: Some("databse-error".to_string()),
-                                    });
-                                }
-                                roles.push(default_role.clone());
-                            }
-                            flow.user = Some(LoginFlowUserData {
-                                identity,
-                                cached_roles: roles,
-                                active_role: default_role,
-                            });
-                            set_login_flow_status(&cookies, &flow).await;
-                            Json::from(LoginFlowResult { error: None })
-                        }
-                        Err(e) => {
-                            tracing::error!("Failed upserting identity: {:?}", e);
-                            set_login_flow_status(&cookies, &flow).await;
-                            Json::from(LoginFlowResult {
-                                error: Some("database-error".to_string()),
-                            })
-                        }
+    };
+    // Whatever happens, the flow in progress is now finished with
+    let result = match continue_flow(&state, &setup, params).await {
+        Ok(details) if linking => link_login(&mut dbconn, &flow, details).await,
+        Ok(details) => complete_login(&mut dbconn, &mut flow, details).await,
+        Err(e) => Err(e),
+    };
+    set_login_flow_status(&cookies, &state.login_key, &flow);
+    Json::from(LoginFlowResult {
+        error: result.err(),
+        linked: linking,
+    })
+}
+
+/// The logins linked to the user, tokens may not manage logins
+async fn handle_linked_logins(
+    LoggedInUser(user): LoggedInUser,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<Option<Vec<LinkedLogin>>> {
+    if user.via_token() {
+        return Json::from(None);
+    }
+    match dbconn.identity_subjects(user.identity().uuid()).await {
+        Ok(subjects) => Json::from(Some(
+            subjects
+                .into_iter()
+                .map(|subject| {
+                    let provider = subject.split(':').next().unwrap_or_default().to_string();
+                    let label = state
+                        .providers
+                        .get(&provider)
+                        .map(|setup| setup.label.clone())
+                        .unwrap_or_else(|| default_label(&provider));
+                    LinkedLogin {
+                        subject,
+                        provider,
+                        label,
                     }
-                }
-                Err(e) => {
-                    // Failed to exchange the token, return something
-                    tracing::error!("Failed exchanging codes: {:?}", e);
-                    flow.flow = None;
-                    set_login_flow_status(&cookies, &flow).await;
-                    Json::from(LoginFlowResult {
-                        error: Some("code-exchange-failed".to_string()),
-                    })
-                }
-            }
-        } else {
-            flow.flow = None;
-            set_login_flow_status(&cookies, &flow).await;
-            Json::from(LoginFlowResult {
-                error: Some("bad-provider".to_string()),
-            })
+                })
+                .collect(),
+        )),
+        Err(e) => {
+            tracing::error!("Failure listing linked logins: {:?}", e);
+            Json::from(None)
         }
-    } else {
-        // No login in progress, redirect user to root
-        Json::from(LoginFlowResult { error: None })
     }
 }
 
-async fn handle_login_status(cookies: Cookies) -> Json<BackendLoginStatus> {
-    let flow = login_flow_status(&cookies).await;
+async fn handle_unlink_login(
+    LoggedInUser(user): LoggedInUser,
+    Json(request): Json<UnlinkLoginRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<UnlinkLoginResponse> {
+    if user.via_token() {
+        return UnlinkLoginResponse::NotLoggedIn.into();
+    }
+    match dbconn
+        .unlink_subject(user.identity().uuid(), &request.subject)
+        .await
+    {
+        Ok(UnlinkOutcome::Unlinked) => UnlinkLoginResponse::Success,
+        Ok(UnlinkOutcome::NotLinked) => UnlinkLoginResponse::NotLinked,
+        Ok(UnlinkOutcome::LastLogin) => UnlinkLoginResponse::LastLogin,
+        Err(e) => UnlinkLoginResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn handle_login_providers(Extension(state): Extension<AppState>) -> Json<Vec<LoginProvider>> {
+    let mut providers: Vec<_> = state
+        .providers
+        .iter()
+        .filter(|(_, setup)| setup.is_live())
+        .map(|(name, setup)| setup.as_login_provider(name))
+        .collect();
+    providers.sort_by(|a, b| a.label.cmp(&b.label));
+    Json::from(providers)
+}
+
+async fn handle_login_status(
+    cookies: Cookies,
+    Extension(state): Extension<AppState>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<BackendLoginStatus> {
+    let flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
     if let Some(data) = flow.user {
         Json::from(BackendLoginStatus::LoggedIn {
             name: data.identity.display_name().to_string(),
@@ -352,28 +1017,69 @@ async fn handle_login_status(cookies: Cookies) -> Json<BackendLoginStatus> {
     }
 }
 
-async fn handle_clear_login(cookies: Cookies) -> StatusCode {
-    let mut flow = login_flow_status(&cookies).await;
+/// Choose which of their roles the user is acting as, so that changes they
+/// make are recorded as made by that role
+async fn handle_choose_role(
+    cookies: Cookies,
+    Extension(state): Extension<AppState>,
+    Extension(mut dbconn): Extension<Database>,
+    Json(request): Json<ChooseRoleRequest>,
+) -> Json<bool> {
+    let mut flow = login_flow_status(&cookies, &state.login_key, &mut dbconn).await;
+    match flow.user.as_mut() {
+        Some(user) if user.has_role(&request.role) => {
+            user.active_role = request.role;
+            set_login_flow_status(&cookies, &state.login_key, &flow);
+            true
+        }
+        _ => false,
+    }
+    .into()
+}
+
+/// Log the user out, abandoning any flow in progress
+pub fn clear_login(cookies: &Cookies, key: &Key) {
+    let mut flow = read_login_flow_status(cookies, key);
     flow.flow = None;
     flow.user = None;
-    set_login_flow_status(&cookies, &flow).await;
-    StatusCode::NO_CONTENT
+    set_login_flow_status(cookies, key, &flow);
 }
 
-#[instrument(skip(config))]
-pub async fn setup(config: &Configuration) {
-    let mut providers = PROVIDERS.lock().await;
-    tracing::info!("Loading OIDC providers...");
-    load_providers(&mut providers, config).await;
-    tracing::info!("Loaded {} providers", providers.len());
-    *(REDIRECT_URL.lock().await) = config.redirect_url.clone();
-    *(LOGIN_KEY.lock().await) = Key::derive_from(config.cookie_secret.as_bytes());
+/// Replace the identity cached in the login cookie, after it has changed
+pub fn refresh_login_identity(cookies: &Cookies, key: &Key, identity: Identity) {
+    let mut flow = read_login_flow_status(cookies, key);
+    if let Some(user) = flow.user.as_mut() {
+        if user.identity.uuid() == identity.uuid() {
+            user.identity = identity;
+            set_login_flow_status(cookies, key, &flow);
+        }
+    }
+}
+
+async fn handle_clear_login(cookies: Cookies, Extension(state): Extension<AppState>) -> Json<()> {
+    clear_login(&cookies, &state.login_key);
+    Json::from(())
 }
 
 pub fn router() -> Router {
     Router::new()
         .route("/continue", get(handle_login_continue))
         .route("/start/:provider", get(start_auth))
+        .route("/link/:provider", get(start_link))
+        .route("/linked", get(handle_linked_logins))
+        .route("/unlink", post(handle_unlink_login))
+        .route("/providers", get(handle_login_providers))
         .route("/status", get(handle_login_status))
-        .route("/clear", get(handle_clear_login))
+        .route("/role", post(handle_choose_role))
+        .route("/clear", post(handle_clear_login))
+}
+
+/// The health of every configured login provider
+pub fn providers_health(providers: &HashMap<String, ProviderSetup>) -> Vec<ProviderHealth> {
+    let mut health: Vec<_> = providers
+        .iter()
+        .map(|(name, provider)| provider.health(name))
+        .collect();
+    health.sort_by(|a, b| a.name.cmp(&b.name));
+    health
 }
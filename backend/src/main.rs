@@ -1,8 +1,11 @@
+use std::net::SocketAddr;
+
 use axum::{
     http::StatusCode,
+    middleware,
     response::Redirect,
     routing::{get, get_service},
-    Router,
+    Extension, Router,
 };
 
 use tower_cookies::CookieManagerLayer;
@@ -17,11 +20,26 @@ async fn handle_root() -> Redirect {
     Redirect::to("/-/")
 }
 
+mod activity;
+mod admin;
+mod atom;
+mod auth;
 mod config;
+mod csrf;
 mod dbconn;
+mod headers;
+mod health;
 mod login;
+mod preview;
+mod profile;
 mod puzzle;
+mod ratelimit;
 mod role;
+mod state;
+mod webhook;
+
+use dbconn::Database;
+use state::AppState;
 
 #[tokio::main]
 async fn main() {
@@ -29,14 +47,28 @@ async fn main() {
 
     println!("{:#?}", config);
 
-    let index_html = {
-        let mut p = config.resources.clone();
-        p.push("index.html");
-        p
-    };
+    tracing_subscriber::fmt::init();
+
+    let dbconn = dbconn::redis_layer(&config)
+        .await
+        .expect("Unable to establish Redis connection");
+    let port = config.port;
+    let state = AppState::new(config).await;
+    tokio::spawn(login::maintain_providers(state.providers.clone()));
+
+    // run it with hyper on localhost:3000
+    axum::Server::bind(&format!("0.0.0.0:{}", port).parse().unwrap())
+        .serve(app(state, dbconn).into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
+}
+
+/// Build the application's router around the given state and database
+fn app(state: AppState, dbconn: Extension<Database>) -> Router {
+    let index_html = state.index_html();
 
     let frontend_service = get_service(
-        ServeDir::new(&config.resources)
+        ServeDir::new(&state.config.resources)
             .append_index_html_on_directories(true)
             .fallback(ServeFile::new(&index_html)),
     )
@@ -45,12 +77,15 @@ async fn main() {
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Unhandled internal error: {}", error),
         )
-    });
+    })
+    .layer(middleware::from_fn(preview::link_previews));
 
-    let app = Router::new()
+    Router::new()
         .nest("/api/", api_router())
         .nest("/-/", frontend_service)
         .route("/", get(handle_root))
+        .layer(middleware::from_fn(headers::security_headers))
+        .layer(Extension(state))
         .layer(CookieManagerLayer::new())
         .layer(
             TraceLayer::new_for_http()
@@ -61,21 +96,7 @@ async fn main() {
                         .latency_unit(LatencyUnit::Millis),
                 ),
         )
-        .layer(
-            dbconn::redis_layer(&config)
-                .await
-                .expect("Unable to establish Redis connection"),
-        );
-
-    tracing_subscriber::fmt::init();
-
-    login::setup(&config).await;
-
-    // run it with hyper on localhost:3000
-    axum::Server::bind(&format!("0.0.0.0:{}", config.port).parse().unwrap())
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .layer(dbconn)
 }
 
 fn api_router() -> Router {
@@ -83,4 +104,11 @@ fn api_router() -> Router {
         .nest("/login", login::router())
         .nest("/role", role::router())
         .nest("/puzzle", puzzle::router())
+        .nest("/tokens", auth::router())
+        .nest("/profile", profile::router())
+        .nest("/admin", admin::router())
+        .route("/feed.atom", get(atom::site_feed))
+        .route("/health", get(health::health))
+        .layer(middleware::from_fn(csrf::protect))
+        .layer(middleware::from_fn(ratelimit::rate_limit))
 }
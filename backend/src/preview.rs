@@ -0,0 +1,172 @@
+//! Link previews for puzzle and role pages
+//!
+//! The frontend is a single page app, so every page is served as the same
+//! `index.html`.  Sites which unfurl links (chat services, social media, etc)
+//! do not run our wasm, so for public puzzles and roles we inject OpenGraph
+//! and Twitter card meta tags into the page before serving it.  The SPA then
+//! boots exactly as it would otherwise.
+
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use linkdoku_common::{PuzzleData, Visibility};
+use pulldown_cmark::{Event, Parser};
+
+use crate::{atom::escape, dbconn::Database, state::AppState};
+
+/// How long a description excerpt may be, in characters
+const EXCERPT_LENGTH: usize = 200;
+
+/// Reduce some markdown to a short plain text excerpt
+fn excerpt(markdown: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= EXCERPT_LENGTH {
+        text
+    } else {
+        let mut ret: String = text.chars().take(EXCERPT_LENGTH - 1).collect();
+        ret.push('…');
+        ret
+    }
+}
+
+/// The details we put into the meta tags of a page
+struct Preview {
+    title: String,
+    description: String,
+    url: String,
+    image: Option<String>,
+}
+
+impl Preview {
+    fn render(&self) -> String {
+        let mut tags = vec![
+            ("property", "og:site_name", "Linkdoku".to_string()),
+            ("property", "og:type", "website".to_string()),
+            ("property", "og:title", self.title.clone()),
+            ("property", "og:description", self.description.clone()),
+            ("property", "og:url", self.url.clone()),
+            ("name", "twitter:title", self.title.clone()),
+            ("name", "twitter:description", self.description.clone()),
+        ];
+        match &self.image {
+            Some(image) => {
+                tags.push(("property", "og:image", image.clone()));
+                tags.push(("name", "twitter:image", image.clone()));
+                tags.push(("name", "twitter:card", "summary_large_image".to_string()));
+            }
+            None => tags.push(("name", "twitter:card", "summary".to_string())),
+        }
+        let mut ret = format!(
+            "<meta name=\"description\" content=\"{}\" />\n",
+            escape(&self.description)
+        );
+        for (attr, name, content) in tags {
+            ret.push_str(&format!(
+                "<meta {}=\"{}\" content=\"{}\" />\n",
+                attr,
+                name,
+                escape(&content)
+            ));
+        }
+        ret
+    }
+}
+
+/// The grid thumbnail for a puzzle, where we have one
+fn grid_image(data: &PuzzleData) -> Option<String> {
+    match data {
+        PuzzleData::FPuzzles(grid) => {
+            let json_data = serde_json::to_string(grid).expect("Odd, JSON encoding failed?");
+            Some(format!(
+                "https://api.sudokupad.com/thumbnail/fpuzzles{}_512x512.svg",
+                lz_str::compress_to_base64(&json_data)
+            ))
+        }
+        _ => None,
+    }
+}
+
+async fn puzzle_preview(dbconn: &mut Database, base: &str, puzzle: &str) -> Option<Preview> {
+    let puzzle = dbconn.puzzle_by_uuid_or_short_name(puzzle).await.ok()?;
+    if puzzle.visibility() == Visibility::Restricted {
+        return None;
+    }
+    let state = puzzle.as_api_puzzle(false).states.pop()?;
+    let description = match excerpt(&state.description) {
+        text if text.is_empty() => format!(
+            "A puzzle on Linkdoku, rated {}",
+            state.setter_rating.title()
+        ),
+        text => text,
+    };
+    Some(Preview {
+        title: puzzle.display_name().to_string(),
+        description,
+        url: format!("{}/-/puzzle/{}", base, puzzle.short_name()),
+        image: grid_image(&state.data),
+    })
+}
+
+async fn role_preview(dbconn: &mut Database, base: &str, role: &str) -> Option<Preview> {
+    let role = dbconn.role_by_uuid_or_short_name(role).await.ok()?;
+    let description = match excerpt(role.bio()) {
+        text if text.is_empty() => format!("Puzzles by {} on Linkdoku", role.display_name()),
+        text => text,
+    };
+    Some(Preview {
+        title: role.display_name().to_string(),
+        description,
+        url: format!("{}/-/role/{}", base, role.short_name()),
+        image: None,
+    })
+}
+
+/// Work out the preview for a frontend path, if it is a page we preview
+async fn preview_for(dbconn: &mut Database, base: &str, path: &str) -> Option<Preview> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let (kind, name) = (segments.next()?, segments.next()?);
+    if name.is_empty() || segments.next().is_some() {
+        return None;
+    }
+    match kind {
+        "puzzle" => puzzle_preview(dbconn, base, name).await,
+        "role" => role_preview(dbconn, base, name).await,
+        _ => None,
+    }
+}
+
+/// Middleware for the frontend service which serves previewable pages
+///
+/// Anything we can't (or shouldn't) preview is passed on untouched.
+pub async fn link_previews<B>(req: Request<B>, next: Next<B>) -> Response {
+    let dbconn = req.extensions().get::<Database>().cloned();
+    let state = req.extensions().get::<AppState>().cloned();
+    let path = req.uri().path().to_string();
+    if let (Some(mut dbconn), Some(state)) = (dbconn, state) {
+        if let Some(preview) = preview_for(&mut dbconn, &state.base_url, &path).await {
+            let index_html = state.index_html();
+            match tokio::fs::read_to_string(&index_html).await {
+                Ok(page) if page.contains("</head>") => {
+                    let page = page.replacen("</head>", &format!("{}</head>", preview.render()), 1);
+                    return Html(page).into_response();
+                }
+                Ok(_) => tracing::warn!(
+                    "No </head> in {}, unable to inject previews",
+                    index_html.display()
+                ),
+                Err(e) => tracing::error!("Unable to read {}: {:?}", index_html.display(), e),
+            }
+        }
+    }
+    next.run(req).await
+}
@@ -0,0 +1,194 @@
+//! Identity profiles
+//!
+//! Users may choose the name they are known by and whether they have an
+//! avatar, take away everything we hold about them, and delete their
+//! account entirely.  As with API tokens, none of this may be done with a
+//! token, only a real login will do.
+
+use axum::{
+    http::{header::CONTENT_DISPOSITION, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use linkdoku_common::{
+    DeleteAccountRequest, DeleteAccountResponse, DeletionPolicy, Profile, ProfileExport,
+    UpdateProfileRequest, UpdateProfileResponse,
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    activity::{self, Actor},
+    auth::LoggedInUser,
+    dbconn::{Database, DatabaseResult, RoleDisposal},
+    login,
+    state::AppState,
+};
+
+/// The longest preferred name we permit
+const MAX_NAME_LENGTH: usize = 100;
+
+async fn get_profile(
+    LoggedInUser(user): LoggedInUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<Profile>> {
+    if user.via_token() {
+        return Json::from(None);
+    }
+    match dbconn.identity_by_uuid(user.identity().uuid()).await {
+        Ok(identity) => Json::from(identity.map(|identity| identity.as_profile())),
+        Err(e) => {
+            tracing::error!("Failure loading profile: {:?}", e);
+            Json::from(None)
+        }
+    }
+}
+
+async fn update_profile(
+    LoggedInUser(user): LoggedInUser,
+    cookies: Cookies,
+    Json(request): Json<UpdateProfileRequest>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<UpdateProfileResponse> {
+    if user.via_token() {
+        return UpdateProfileResponse::NotLoggedIn.into();
+    }
+    let preferred_name = request
+        .preferred_name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty());
+    if preferred_name.map(|name| name.chars().count()).unwrap_or(0) > MAX_NAME_LENGTH {
+        return UpdateProfileResponse::InvalidName.into();
+    }
+    let uuid = user.identity().uuid();
+    if let Err(e) = dbconn
+        .set_identity_profile(uuid, preferred_name, request.avatar)
+        .await
+    {
+        return UpdateProfileResponse::DatabaseFailure(e.to_string()).into();
+    }
+    match dbconn.identity_by_uuid(uuid).await {
+        Ok(Some(identity)) => {
+            let profile = identity.as_profile();
+            login::refresh_login_identity(&cookies, &state.login_key, identity);
+            UpdateProfileResponse::Success(profile)
+        }
+        Ok(None) => UpdateProfileResponse::NotLoggedIn,
+        Err(e) => UpdateProfileResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn gather_export(dbconn: &mut Database, uuid: &str) -> DatabaseResult<ProfileExport> {
+    let mut export = ProfileExport {
+        uuid: uuid.to_string(),
+        ..Default::default()
+    };
+    if let Some(identity) = dbconn.identity_by_uuid(uuid).await? {
+        export.profile = identity.as_profile();
+    }
+    export.logins = dbconn.identity_subjects(uuid).await?;
+    for role in dbconn.identity_roles(uuid).await? {
+        let role = dbconn.role_by_uuid_or_short_name(&role).await?;
+        for (puzzle, _) in dbconn.role_puzzles(role.uuid()).await? {
+            let puzzle = dbconn.puzzle_by_uuid_or_short_name(&puzzle).await?;
+            export.puzzles.push(puzzle.as_api_puzzle(true));
+        }
+        export.roles.push(role.as_api_role());
+    }
+    export.api_tokens = dbconn.api_tokens(uuid).await?;
+    Ok(export)
+}
+
+async fn export_profile(
+    LoggedInUser(user): LoggedInUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if user.via_token() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match gather_export(&mut dbconn, user.identity().uuid()).await {
+        Ok(export) => Ok((
+            [(
+                CONTENT_DISPOSITION,
+                "attachment; filename=\"linkdoku-export.json\"",
+            )],
+            Json::from(export),
+        )),
+        Err(e) => {
+            tracing::error!("Failure exporting profile: {:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn delete_account(
+    LoggedInUser(user): LoggedInUser,
+    cookies: Cookies,
+    Json(request): Json<DeleteAccountRequest>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<DeleteAccountResponse> {
+    if user.via_token() {
+        return DeleteAccountResponse::NotLoggedIn.into();
+    }
+    let uuid = user.identity().uuid();
+    let new_owner = match &request.policy {
+        DeletionPolicy::Reassign(role) => match dbconn.role_by_uuid_or_short_name(role).await {
+            Ok(role) if role.owner().is_empty() => {
+                return DeleteAccountResponse::UnknownRole.into()
+            }
+            Ok(role) if role.owner() == uuid => {
+                return DeleteAccountResponse::CannotReassignToSelf.into()
+            }
+            Ok(role) => Some(role.owner().to_string()),
+            Err(_) => return DeleteAccountResponse::UnknownRole.into(),
+        },
+        _ => None,
+    };
+    let disposal = match (&request.policy, &new_owner) {
+        (DeletionPolicy::Reassign(_), Some(owner)) => RoleDisposal::Reassign(owner),
+        (DeletionPolicy::Delete, _) => RoleDisposal::Delete,
+        _ => RoleDisposal::Orphan,
+    };
+    let roles = match dbconn.identity_owned_roles(uuid).await {
+        Ok(roles) => roles,
+        Err(e) => return DeleteAccountResponse::DatabaseFailure(e.to_string()).into(),
+    };
+    match dbconn.delete_identity(uuid, disposal).await {
+        Ok(()) => {
+            tracing::info!("Deleted identity {}", uuid);
+            let (action, new_owner) = match disposal {
+                RoleDisposal::Orphan => ("role-orphan", ""),
+                RoleDisposal::Reassign(owner) => ("role-reassign", owner),
+                RoleDisposal::Delete => ("role-delete", ""),
+            };
+            let actor = Actor::user(&user);
+            for role in &roles {
+                activity::record(
+                    &mut dbconn,
+                    &actor,
+                    role,
+                    action,
+                    role,
+                    activity::diff(&[("owner", uuid, new_owner)]),
+                )
+                .await;
+            }
+            login::clear_login(&cookies, &state.login_key);
+            DeleteAccountResponse::Success
+        }
+        Err(e) => DeleteAccountResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/get", get(get_profile))
+        .route("/update", post(update_profile))
+        .route("/export", get(export_profile))
+        .route("/delete", post(delete_account))
+}
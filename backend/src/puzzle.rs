@@ -1,29 +1,101 @@
+use std::time::{Duration, Instant};
+
 use axum::{
     extract::Path,
     routing::{get, post},
     Extension, Json, Router,
 };
-use linkdoku_common::{CreatePuzzleResponse, Puzzle as APIPuzzle, Visibility};
-use tower_cookies::Cookies;
+use linkdoku_common::{
+    AddCommentRequest, AddCommentResponse, CommentModeration, CommunityRatings,
+    CreatePuzzleRequest, CreatePuzzleResponse, MarkSolvedRequest, MarkSolvedResponse,
+    ModerateCommentRequest, ModerateCommentResponse, PackMember, PackPosition, Puzzle as APIPuzzle,
+    PuzzleComments, PuzzleData, PuzzleState, RatePuzzleRequest, RatePuzzleResponse,
+    SetVisibilityRequest, SetVisibilityResponse, SolutionCount, Visibility, MAX_COMMENT_LENGTH,
+    MAX_QUALITY,
+};
+use linkdoku_solver::{solve_fpuzzles_until, Solutions, DEFAULT_NODE_LIMIT};
+use serde_json::Value;
 
 use crate::{
-    dbconn::{self, Database},
-    login::login_flow_status,
+    activity::{self, Actor},
+    auth::{Auth, LoggedInUser},
+    dbconn::{self, Database, DatabaseError},
+    state::AppState,
+    webhook,
 };
 
+/// How hard the solver may work when a setter checks a grid while editing
+const CHECK_NODE_LIMIT: usize = 100_000;
+
+/// How long a solve may take, including waiting for its turn
+const SOLVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many solves may run at once, each of which occupies a blocking thread
+pub const MAX_CONCURRENT_SOLVES: usize = 2;
+
+/// Run the solver over a grid, returning the count along with the solution if unique
+///
+/// The solver can take a while on hard grids, so it runs on the blocking pool,
+/// at most [`MAX_CONCURRENT_SOLVES`] at a time, and bounded both by the node
+/// limit and by [`SOLVER_TIMEOUT`], which the solver keeps to itself so that
+/// nothing is left running once we've given up on it.
+async fn count_solutions(
+    state: &AppState,
+    data: Value,
+    node_limit: usize,
+) -> (SolutionCount, Option<Vec<u8>>) {
+    let deadline = Instant::now() + SOLVER_TIMEOUT;
+    let permit = match tokio::time::timeout_at(
+        deadline.into(),
+        state.solver_permits.clone().acquire_owned(),
+    )
+    .await
+    {
+        Ok(Ok(permit)) => permit,
+        Ok(Err(e)) => {
+            tracing::error!("Solver permits unavailable: {:?}", e);
+            return (SolutionCount::Unknown("solver failure".to_string()), None);
+        }
+        Err(_) => {
+            tracing::warn!("Solver busy for {:?}", SOLVER_TIMEOUT);
+            return (SolutionCount::Unknown("solver busy".to_string()), None);
+        }
+    };
+    let task = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        solve_fpuzzles_until(&data, node_limit, deadline)
+    });
+    match task.await {
+        Ok(Ok(Solutions::None)) => (SolutionCount::Zero, None),
+        Ok(Ok(Solutions::Unique(solution))) => (SolutionCount::One, Some(solution)),
+        Ok(Ok(Solutions::Multiple)) => (SolutionCount::Many, None),
+        Ok(Err(e)) => (SolutionCount::Unknown(e.to_string()), None),
+        Err(e) => {
+            tracing::error!("Solver task failed: {:?}", e);
+            (SolutionCount::Unknown("solver failure".to_string()), None)
+        }
+    }
+}
+
+async fn check_puzzle_state(
+    LoggedInUser(_): LoggedInUser,
+    Json(puzzle_state): Json<PuzzleState>,
+    Extension(state): Extension<AppState>,
+) -> Json<Option<SolutionCount>> {
+    match puzzle_state.data {
+        PuzzleData::FPuzzles(data) => Some(count_solutions(&state, data, CHECK_NODE_LIMIT).await.0),
+        _ => None,
+    }
+    .into()
+}
+
 async fn create_puzzle(
-    cookies: Cookies,
-    Json(puzzle): Json<APIPuzzle>,
+    LoggedInUser(user): LoggedInUser,
+    Json(request): Json<CreatePuzzleRequest>,
     Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
 ) -> Json<CreatePuzzleResponse> {
-    let flow = login_flow_status(&cookies).await;
-    let user = match flow.user() {
-        Some(x) => x,
-        None => {
-            // User isn't logged in, cannot possibly create puzzles
-            return CreatePuzzleResponse::NotLoggedIn.into();
-        }
-    };
+    let mut puzzle = request.puzzle;
 
     // Must not have supplied a UUID
     if !puzzle.uuid.is_empty() {
@@ -49,19 +121,69 @@ async fn create_puzzle(
         return CreatePuzzleResponse::InvalidVisiblityData.into();
     }
 
+    // If the state is a pack, every member must exist and be visible to the
+    // creator, and we store them by UUID so that renames don't break the pack
+    let mut pack_members = Vec::new();
+    if let PuzzleData::Pack(members) = &mut puzzle.states[0].data {
+        for member in members.iter_mut() {
+            let member_data = match dbconn.puzzle_by_uuid_or_short_name(member.trim()).await {
+                Ok(member_data) => member_data,
+                Err(_) => return CreatePuzzleResponse::InvalidPackMember(member.clone()).into(),
+            };
+            if !can_see_puzzle(&member_data, user.has_role(member_data.owner())) {
+                return CreatePuzzleResponse::InvalidPackMember(member.clone()).into();
+            }
+            *member = member_data.uuid().to_string();
+        }
+        pack_members = members.clone();
+    }
+
+    // If the state has a grid, check how many solutions it has, and if the
+    // setter asked us to and did not supply the unique solution, store it
+    let solutions = if let PuzzleData::FPuzzles(data) = &mut puzzle.states[0].data {
+        let (count, solution) = count_solutions(&state, data.clone(), DEFAULT_NODE_LIMIT).await;
+        if let (true, Some(solution), Some(obj)) =
+            (request.store_solution, solution, data.as_object_mut())
+        {
+            obj.entry("solution").or_insert_with(|| solution.into());
+        }
+        Some(count)
+    } else {
+        None
+    };
+
     // Let's try and transform the puzzle into a database puzzle
     let puzzle = dbconn::Puzzle::from(puzzle);
 
     // At this point it's safe to create the puzzle...
-    match dbconn.create_puzzle(&puzzle).await {
-        Ok(uuid) => CreatePuzzleResponse::Success(uuid),
-        Err(e) => CreatePuzzleResponse::DatabaseFailure(e.to_string()),
+    let uuid = match dbconn.create_puzzle(&puzzle).await {
+        Ok(uuid) => uuid,
+        Err(e) => return CreatePuzzleResponse::DatabaseFailure(e.to_string()).into(),
+    };
+
+    if let Err(e) = dbconn.add_pack_members(&uuid, &pack_members).await {
+        tracing::error!("Unable to record pack members for {}: {:?}", uuid, e);
     }
-    .into()
+
+    activity::record(
+        &mut dbconn,
+        &Actor::user(&user),
+        puzzle.owner(),
+        "puzzle-create",
+        &uuid,
+        activity::diff(&[
+            ("display_name", "", puzzle.display_name()),
+            ("visibility", "", &format!("{:?}", puzzle.visibility())),
+            ("members", "", &pack_members.join(" ")),
+        ]),
+    )
+    .await;
+
+    CreatePuzzleResponse::Success(uuid, solutions).into()
 }
 
 pub async fn retrieve_puzzle(
-    cookies: Cookies,
+    auth: Auth,
     Path(puzzle): Path<String>,
     Extension(mut dbconn): Extension<Database>,
 ) -> Json<Option<APIPuzzle>> {
@@ -72,12 +194,9 @@ pub async fn retrieve_puzzle(
 
     tracing::info!("Fetched puzzle {}", puzzle);
 
-    let is_logged_in_owner = {
-        let flow = login_flow_status(&cookies).await;
-        match flow.user() {
-            Some(x) => x.has_role(puzzle_data.owner()),
-            None => false,
-        }
+    let is_logged_in_owner = match auth.user() {
+        Some(x) => x.has_role(puzzle_data.owner()),
+        None => false,
     };
 
     tracing::info!(
@@ -85,22 +204,452 @@ pub async fn retrieve_puzzle(
         is_logged_in_owner
     );
 
-    let can_see_puzzle = match puzzle_data.visibility() {
+    if !can_see_puzzle(&puzzle_data, is_logged_in_owner) {
+        tracing::info!("Calling user? cannot see puzzle");
+        return None.into();
+    }
+
+    let mut ret = puzzle_data.as_api_puzzle(is_logged_in_owner);
+
+    match dbconn
+        .solve_stats(
+            puzzle_data.uuid(),
+            auth.user().map(|u| u.identity().uuid()),
+            FASTEST_SOLVES,
+        )
+        .await
+    {
+        Ok(stats) => ret.solves = stats,
+        Err(e) => tracing::error!("Unable to retrieve solve stats for {}: {:?}", puzzle, e),
+    }
+
+    Some(ret).into()
+}
+
+/// How many of the fastest solve times we report with a puzzle
+const FASTEST_SOLVES: isize = 10;
+
+fn can_see_puzzle(puzzle: &dbconn::Puzzle, is_logged_in_owner: bool) -> bool {
+    match puzzle.visibility() {
         Visibility::Restricted => is_logged_in_owner,
         Visibility::Public => true,
         Visibility::Published => true,
+    }
+}
+
+async fn mark_solved(
+    LoggedInUser(user): LoggedInUser,
+    Path(puzzle): Path<String>,
+    Json(request): Json<MarkSolvedRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<MarkSolvedResponse> {
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return MarkSolvedResponse::UnknownPuzzle.into(),
     };
 
-    if !can_see_puzzle {
-        tracing::info!("Calling user? cannot see puzzle");
+    if !can_see_puzzle(&puzzle_data, user.has_role(puzzle_data.owner())) {
+        return MarkSolvedResponse::UnknownPuzzle.into();
+    }
+
+    match dbconn
+        .mark_solved(
+            puzzle_data.uuid(),
+            user.identity().uuid(),
+            request.solve_time,
+        )
+        .await
+    {
+        Ok(_) => MarkSolvedResponse::Success,
+        Err(e) => MarkSolvedResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn set_visibility(
+    LoggedInUser(user): LoggedInUser,
+    Path(puzzle): Path<String>,
+    Json(request): Json<SetVisibilityRequest>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<SetVisibilityResponse> {
+    let mut puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return SetVisibilityResponse::UnknownPuzzle.into(),
+    };
+
+    let is_owner = user.has_role(puzzle_data.owner());
+    if !can_see_puzzle(&puzzle_data, is_owner) {
+        return SetVisibilityResponse::UnknownPuzzle.into();
+    }
+    if !is_owner {
+        return SetVisibilityResponse::PermissionDenied.into();
+    }
+
+    let old_visibility = puzzle_data.visibility();
+    let newly_published =
+        request.visibility == Visibility::Published && old_visibility != Visibility::Published;
+
+    // Publishing a grid checks it again, so that the setter is warned if it
+    // isn't unique, but doesn't stop them since the solver may be wrong
+    let solutions = if newly_published {
+        match puzzle_data.as_api_puzzle(true).states.pop().map(|s| s.data) {
+            Some(PuzzleData::FPuzzles(data)) => {
+                Some(count_solutions(&state, data, DEFAULT_NODE_LIMIT).await.0)
+            }
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    match dbconn
+        .set_puzzle_visibility(&mut puzzle_data, request.visibility)
+        .await
+    {
+        Ok(()) => {
+            activity::record(
+                &mut dbconn,
+                &Actor::user(&user),
+                puzzle_data.owner(),
+                "puzzle-visibility",
+                puzzle_data.uuid(),
+                activity::diff(&[(
+                    "visibility",
+                    &format!("{:?}", old_visibility),
+                    &format!("{:?}", request.visibility),
+                )]),
+            )
+            .await;
+            if newly_published {
+                tokio::spawn(webhook::notify_published(state, dbconn, puzzle_data));
+            }
+            SetVisibilityResponse::Success(solutions)
+        }
+        Err(e) => SetVisibilityResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+/// Summarise a puzzle for pack navigation, if the caller is allowed to see it
+async fn pack_member(
+    dbconn: &mut Database,
+    auth: &Auth,
+    puzzle: &str,
+) -> Option<(PackMember, dbconn::Puzzle)> {
+    let puzzle_data = dbconn.puzzle_by_uuid_or_short_name(puzzle).await.ok()?;
+    let is_logged_in_owner = auth
+        .user()
+        .map(|u| u.has_role(puzzle_data.owner()))
+        .unwrap_or(false);
+    if !can_see_puzzle(&puzzle_data, is_logged_in_owner) {
+        return None;
+    }
+    Some((
+        PackMember {
+            uuid: puzzle_data.uuid().to_string(),
+            short_name: puzzle_data.short_name().to_string(),
+            display_name: puzzle_data.display_name().to_string(),
+        },
+        puzzle_data,
+    ))
+}
+
+async fn puzzle_packs(
+    auth: Auth,
+    Path(puzzle): Path<String>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Vec<PackPosition>> {
+    let puzzle = match pack_member(&mut dbconn, &auth, &puzzle).await {
+        Some((member, _)) => member,
+        None => return Vec::new().into(),
+    };
+
+    let packs = match dbconn.puzzle_packs(&puzzle.uuid).await {
+        Ok(packs) => packs,
+        Err(e) => {
+            tracing::error!("Unable to retrieve packs for {}: {:?}", puzzle.uuid, e);
+            return Vec::new().into();
+        }
+    };
+
+    let mut ret = Vec::new();
+    for pack in packs {
+        let (pack, pack_data) = match pack_member(&mut dbconn, &auth, &pack).await {
+            Some(pack) => pack,
+            None => continue,
+        };
+        let is_logged_in_owner = auth
+            .user()
+            .map(|u| u.has_role(pack_data.owner()))
+            .unwrap_or(false);
+        // Navigation follows the newest state of the pack the caller can see
+        let members = match pack_data
+            .as_api_puzzle(is_logged_in_owner)
+            .states
+            .pop()
+            .map(|state| state.data)
+        {
+            Some(PuzzleData::Pack(members)) => members,
+            _ => continue,
+        };
+        if !members.contains(&puzzle.uuid) {
+            continue;
+        }
+        let mut visible = Vec::new();
+        for member in members {
+            if let Some((member, _)) = pack_member(&mut dbconn, &auth, &member).await {
+                visible.push(member);
+            }
+        }
+        if let Some(idx) = visible.iter().position(|m| m.uuid == puzzle.uuid) {
+            ret.push(PackPosition {
+                pack,
+                position: idx + 1,
+                count: visible.len(),
+                previous: idx.checked_sub(1).map(|n| visible[n].clone()),
+                next: visible.get(idx + 1).cloned(),
+            });
+        }
+    }
+
+    ret.into()
+}
+
+async fn community_ratings(
+    auth: Auth,
+    Path(puzzle): Path<String>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<CommunityRatings>> {
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return None.into(),
+    };
+
+    let is_logged_in_owner = auth
+        .user()
+        .map(|u| u.has_role(puzzle_data.owner()))
+        .unwrap_or(false);
+    if !can_see_puzzle(&puzzle_data, is_logged_in_owner) {
         return None.into();
     }
 
-    Some(puzzle_data.as_api_puzzle(is_logged_in_owner)).into()
+    match dbconn
+        .community_ratings(puzzle_data.uuid(), auth.user().map(|u| u.identity().uuid()))
+        .await
+    {
+        Ok(ratings) => Some(ratings),
+        Err(e) => {
+            tracing::error!("Unable to retrieve ratings for {}: {:?}", puzzle, e);
+            None
+        }
+    }
+    .into()
+}
+
+async fn rate_puzzle(
+    LoggedInUser(user): LoggedInUser,
+    Path(puzzle): Path<String>,
+    Json(request): Json<RatePuzzleRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<RatePuzzleResponse> {
+    if matches!(request.quality, Some(q) if q == 0 || q > MAX_QUALITY) {
+        return RatePuzzleResponse::InvalidQuality.into();
+    }
+
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return RatePuzzleResponse::UnknownPuzzle.into(),
+    };
+
+    if !can_see_puzzle(&puzzle_data, user.has_role(puzzle_data.owner())) {
+        return RatePuzzleResponse::UnknownPuzzle.into();
+    }
+
+    let identity = user.identity().uuid();
+    if let Err(e) = dbconn
+        .rate_puzzle(
+            puzzle_data.uuid(),
+            identity,
+            request.difficulty,
+            request.quality,
+        )
+        .await
+    {
+        return RatePuzzleResponse::DatabaseFailure(e.to_string()).into();
+    }
+
+    match dbconn
+        .community_ratings(puzzle_data.uuid(), Some(identity))
+        .await
+    {
+        Ok(ratings) => RatePuzzleResponse::Success(ratings),
+        Err(e) => RatePuzzleResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn puzzle_comments(
+    auth: Auth,
+    Path(puzzle): Path<String>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<PuzzleComments>> {
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return None.into(),
+    };
+
+    let is_logged_in_owner = auth
+        .user()
+        .map(|u| u.has_role(puzzle_data.owner()))
+        .unwrap_or(false);
+    if !can_see_puzzle(&puzzle_data, is_logged_in_owner) {
+        return None.into();
+    }
+
+    let mut comments = match dbconn.puzzle_comments(puzzle_data.uuid()).await {
+        Ok(comments) => comments,
+        Err(e) => {
+            tracing::error!("Unable to retrieve comments for {}: {:?}", puzzle, e);
+            return None.into();
+        }
+    };
+
+    if !is_logged_in_owner {
+        // Only the setter gets to see hidden comments, and replies to them are
+        // hidden along with them
+        let hidden: Vec<String> = comments
+            .iter()
+            .filter(|c| c.hidden)
+            .map(|c| c.id.clone())
+            .collect();
+        comments.retain(|c| {
+            !c.hidden
+                && c.reply_to
+                    .as_ref()
+                    .map(|r| !hidden.contains(r))
+                    .unwrap_or(true)
+        });
+    }
+
+    Some(PuzzleComments {
+        comments,
+        can_moderate: is_logged_in_owner,
+    })
+    .into()
+}
+
+async fn add_comment(
+    LoggedInUser(user): LoggedInUser,
+    Path(puzzle): Path<String>,
+    Json(request): Json<AddCommentRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<AddCommentResponse> {
+    let body = request.body.trim();
+    if body.is_empty() || body.chars().count() > MAX_COMMENT_LENGTH {
+        return AddCommentResponse::InvalidBody.into();
+    }
+
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return AddCommentResponse::UnknownPuzzle.into(),
+    };
+
+    let is_owner = user.has_role(puzzle_data.owner());
+    if !can_see_puzzle(&puzzle_data, is_owner) {
+        return AddCommentResponse::UnknownPuzzle.into();
+    }
+
+    match dbconn
+        .add_comment(
+            puzzle_data.uuid(),
+            user.identity().uuid(),
+            is_owner.then(|| puzzle_data.owner()),
+            body,
+            request.hint,
+            request.reply_to.as_deref(),
+        )
+        .await
+    {
+        Ok(id) => AddCommentResponse::Success(id),
+        Err(DatabaseError::NotFound(_)) => AddCommentResponse::UnknownComment,
+        Err(e) => AddCommentResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+async fn moderate_comment(
+    LoggedInUser(user): LoggedInUser,
+    Path(puzzle): Path<String>,
+    Json(request): Json<ModerateCommentRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<ModerateCommentResponse> {
+    let puzzle_data = match dbconn.puzzle_by_uuid_or_short_name(&puzzle).await {
+        Ok(puzzle) => puzzle,
+        Err(_) => return ModerateCommentResponse::UnknownPuzzle.into(),
+    };
+
+    let is_owner = user.has_role(puzzle_data.owner());
+    if !can_see_puzzle(&puzzle_data, is_owner) {
+        return ModerateCommentResponse::UnknownPuzzle.into();
+    }
+    if !is_owner {
+        return ModerateCommentResponse::PermissionDenied.into();
+    }
+
+    let action = match request.action {
+        CommentModeration::Hide => "comment-hide",
+        CommentModeration::Unhide => "comment-unhide",
+        CommentModeration::Delete => "comment-delete",
+    };
+    let result = match request.action {
+        CommentModeration::Hide => {
+            dbconn
+                .set_comment_hidden(puzzle_data.uuid(), &request.comment, true)
+                .await
+        }
+        CommentModeration::Unhide => {
+            dbconn
+                .set_comment_hidden(puzzle_data.uuid(), &request.comment, false)
+                .await
+        }
+        CommentModeration::Delete => {
+            dbconn
+                .delete_comment(puzzle_data.uuid(), &request.comment)
+                .await
+        }
+    };
+
+    match result {
+        Ok(()) => {
+            activity::record(
+                &mut dbconn,
+                &Actor::user(&user),
+                puzzle_data.owner(),
+                action,
+                puzzle_data.uuid(),
+                format!("comment: {}", request.comment),
+            )
+            .await;
+            ModerateCommentResponse::Success
+        }
+        Err(DatabaseError::NotFound(_)) => ModerateCommentResponse::UnknownComment,
+        Err(e) => ModerateCommentResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
 }
 
 pub fn router() -> Router {
     Router::new()
         .route("/create", post(create_puzzle))
+        .route("/check", post(check_puzzle_state))
         .route("/get/:puzzle", get(retrieve_puzzle))
+        .route("/solved/:puzzle", post(mark_solved))
+        .route("/visibility/:puzzle", post(set_visibility))
+        .route("/packs/:puzzle", get(puzzle_packs))
+        .route("/ratings/:puzzle", get(community_ratings))
+        .route("/rate/:puzzle", post(rate_puzzle))
+        .route("/comments/:puzzle", get(puzzle_comments))
+        .route("/comments/:puzzle/add", post(add_comment))
+        .route("/comments/:puzzle/moderate", post(moderate_comment))
 }
@@ -0,0 +1,269 @@
+//! Rate limiting of API requests
+//!
+//! API routes fall into groups, logins, writes, and reads, and each client has
+//! a token bucket in Redis for each group, sized according to the
+//! configuration.  Clients are logged in identities where we have one, either
+//! by their cookie or by an API token they present, and otherwise their
+//! address.  Once a bucket is empty, requests are refused
+//! with `429 Too Many Requests` and a `Retry-After` saying when to come back.
+//!
+//! If Redis is having trouble then we let requests through, since the rest
+//! of the request is likely to have the same trouble anyway.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{header::RETRY_AFTER, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tower_cookies::Cookies;
+
+use crate::{
+    auth,
+    config::{RateLimit, RateLimits},
+    dbconn::Database,
+    login,
+    state::AppState,
+};
+
+/// The groups of API routes which are limited separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RouteGroup {
+    Login,
+    Write,
+    Read,
+}
+
+impl RouteGroup {
+    /// The group a request falls into, if it is limited at all
+    ///
+    /// The path is relative to `/api`
+    fn of(method: &Method, path: &str) -> Option<RouteGroup> {
+        if path == "/health" {
+            // Monitoring must always get an answer
+            None
+        } else if path.starts_with("/login/start/")
+            || path.starts_with("/login/link/")
+            || path == "/login/continue"
+        {
+            Some(RouteGroup::Login)
+        } else if matches!(*method, Method::GET | Method::HEAD) {
+            Some(RouteGroup::Read)
+        } else {
+            Some(RouteGroup::Write)
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            RouteGroup::Login => "login",
+            RouteGroup::Write => "write",
+            RouteGroup::Read => "read",
+        }
+    }
+
+    fn limit(self, limits: &RateLimits) -> Option<RateLimit> {
+        match self {
+            RouteGroup::Login => limits.login,
+            RouteGroup::Write => limits.write,
+            RouteGroup::Read => limits.read,
+        }
+    }
+}
+
+/// The identity an API token belongs to, for the purpose of rate limiting
+///
+/// Tokens are counted as their identity so that switching between them gets
+/// no fresh allowance.  Tokens we don't know of are left to be counted by
+/// address, since anyone can make those up.
+async fn token_client(dbconn: &mut Database, token: &str) -> Option<String> {
+    match dbconn.api_token(&auth::hash_token(token)).await {
+        Ok(found) => found.map(|(identity, _)| format!("identity:{}", identity)),
+        Err(e) => {
+            tracing::error!("Unable to look up API token for rate limiting: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Who is making a request, for the purpose of rate limiting
+fn client<B>(req: &Request<B>, state: &AppState) -> Option<String> {
+    let identity = req
+        .extensions()
+        .get::<Cookies>()
+        .and_then(|cookies| login::cookie_identity(cookies, &state.login_key));
+    if let Some(identity) = identity {
+        return Some(format!("identity:{}", identity));
+    }
+    let forwarded = if state.config.rate_limits.trust_forwarded_for {
+        req.headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|addr| addr.trim().parse::<IpAddr>().ok())
+    } else {
+        None
+    };
+    forwarded
+        .or_else(|| {
+            req.extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+        .map(|addr| format!("ip:{}", addr))
+}
+
+/// How long it takes for a request to come back to a bucket
+fn refill_interval(limit: RateLimit) -> Duration {
+    Duration::from_secs(60) / limit.per_minute
+}
+
+/// The `Retry-After` for a wait, which is in whole seconds, so round up lest
+/// the client come back too soon
+fn retry_after(wait: Duration) -> u128 {
+    (wait.as_millis() + 999) / 1000
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after(wait).to_string())],
+        "Too many requests, please slow down",
+    )
+        .into_response()
+}
+
+/// Middleware for the API which limits how often each client may call it
+pub async fn rate_limit<B>(req: Request<B>, next: Next<B>) -> Response {
+    let dbconn = req.extensions().get::<Database>().cloned();
+    let state = req.extensions().get::<AppState>().cloned();
+    let (mut dbconn, state) = match (dbconn, state) {
+        (Some(dbconn), Some(state)) => (dbconn, state),
+        _ => return next.run(req).await,
+    };
+    let group = match RouteGroup::of(req.method(), req.uri().path()) {
+        Some(group) => group,
+        None => return next.run(req).await,
+    };
+    let limit = match group.limit(&state.config.rate_limits) {
+        Some(limit) if limit.per_minute > 0 => limit,
+        _ => return next.run(req).await,
+    };
+    let by_token = match auth::bearer_token(req.headers()) {
+        Some(token) => token_client(&mut dbconn, &token).await,
+        None => None,
+    };
+    let client = match by_token.or_else(|| client(&req, &state)) {
+        Some(client) => client,
+        None => return next.run(req).await,
+    };
+    let bucket = format!("{}:{}", group.name(), client);
+    let refill = refill_interval(limit);
+    match dbconn
+        .take_rate_limit_token(&bucket, limit.burst, refill)
+        .await
+    {
+        Ok(None) => next.run(req).await,
+        Ok(Some(wait)) => {
+            tracing::info!("Rate limited {} for {:?}", bucket, wait);
+            too_many_requests(wait)
+        }
+        Err(e) => {
+            tracing::error!("Unable to check rate limit for {}: {:?}", bucket, e);
+            next.run(req).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_groups() {
+        let group = |method: Method, path: &str| RouteGroup::of(&method, path);
+        assert_eq!(group(Method::GET, "/health"), None);
+        assert_eq!(group(Method::POST, "/health"), None);
+        assert_eq!(
+            group(Method::GET, "/login/start/google"),
+            Some(RouteGroup::Login)
+        );
+        assert_eq!(
+            group(Method::GET, "/login/link/github"),
+            Some(RouteGroup::Login)
+        );
+        assert_eq!(
+            group(Method::GET, "/login/continue"),
+            Some(RouteGroup::Login)
+        );
+        assert_eq!(group(Method::GET, "/login/status"), Some(RouteGroup::Read));
+        assert_eq!(group(Method::POST, "/login/clear"), Some(RouteGroup::Write));
+        assert_eq!(
+            group(Method::GET, "/puzzle/get/foo"),
+            Some(RouteGroup::Read)
+        );
+        assert_eq!(
+            group(Method::HEAD, "/puzzle/get/foo"),
+            Some(RouteGroup::Read)
+        );
+        assert_eq!(
+            group(Method::POST, "/puzzle/create"),
+            Some(RouteGroup::Write)
+        );
+        assert_eq!(group(Method::GET, "/tokens/list"), Some(RouteGroup::Read));
+        assert_eq!(
+            group(Method::POST, "/tokens/revoke"),
+            Some(RouteGroup::Write)
+        );
+        // Only the exact path is exempt
+        assert_eq!(group(Method::GET, "/healthy"), Some(RouteGroup::Read));
+    }
+
+    #[test]
+    fn group_limits() {
+        let limits = RateLimits {
+            login: Some(RateLimit {
+                burst: 1,
+                per_minute: 2,
+            }),
+            write: None,
+            ..RateLimits::default()
+        };
+        assert_eq!(RouteGroup::Login.limit(&limits).map(|l| l.burst), Some(1));
+        assert!(RouteGroup::Write.limit(&limits).is_none());
+        assert_eq!(
+            RouteGroup::Read.limit(&limits).map(|l| l.per_minute),
+            RateLimits::default().read.map(|l| l.per_minute)
+        );
+    }
+
+    #[test]
+    fn refill_intervals() {
+        let limit = |per_minute| RateLimit {
+            burst: 10,
+            per_minute,
+        };
+        assert_eq!(refill_interval(limit(1)), Duration::from_secs(60));
+        assert_eq!(refill_interval(limit(60)), Duration::from_secs(1));
+        assert_eq!(refill_interval(limit(300)), Duration::from_millis(200));
+        assert_eq!(
+            refill_interval(limit(7)),
+            Duration::from_nanos(8_571_428_571)
+        );
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        assert_eq!(retry_after(Duration::ZERO), 0);
+        assert_eq!(retry_after(Duration::from_millis(1)), 1);
+        assert_eq!(retry_after(Duration::from_millis(999)), 1);
+        assert_eq!(retry_after(Duration::from_millis(1000)), 1);
+        assert_eq!(retry_after(Duration::from_millis(1001)), 2);
+        assert_eq!(retry_after(Duration::from_secs(60)), 60);
+    }
+}
@@ -1,7 +1,16 @@
 use axum::{extract::Path, routing::get, Extension, Json, Router};
-use linkdoku_common::RoleData;
+use linkdoku_common::{
+    FeedEntry, FollowRequest, FollowResponse, FollowStatus, RoleData, SetWebhookRequest,
+    SetWebhookResponse, Visibility, WebhookSettings, WebhookStatus,
+};
 
-use crate::dbconn::{Database, DatabaseError};
+use crate::{
+    activity::{self, Actor},
+    auth::{Auth, LoggedInUser, RoleMember},
+    dbconn::{Database, DatabaseError},
+    state::AppState,
+    webhook::{self, DestinationError},
+};
 
 async fn role_by_uuid_or_short_name(
     Path(role): Path<String>,
@@ -11,13 +20,7 @@ async fn role_by_uuid_or_short_name(
     match dbconn.role_by_uuid_or_short_name(&role).await {
         Ok(role) => {
             tracing::info!("Found role: {:?}", role);
-            Json::from(Some(RoleData {
-                uuid: role.uuid().to_string(),
-                owner: role.owner().to_string(),
-                short_name: role.short_name().to_string(),
-                display_name: role.display_name().to_string(),
-                bio: role.bio().to_string(),
-            }))
+            Json::from(Some(role.as_api_role()))
         }
         Err(DatabaseError::NotFound(_)) => {
             tracing::warn!("Role {} not found", role);
@@ -30,6 +33,197 @@ async fn role_by_uuid_or_short_name(
     }
 }
 
+async fn follow_status(
+    auth: Auth,
+    Path(role): Path<String>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<FollowStatus>> {
+    let role = match dbconn.role_by_uuid_or_short_name(&role).await {
+        Ok(role) => role,
+        Err(_) => return Json::from(None),
+    };
+    match dbconn
+        .follow_status(auth.user().map(|u| u.identity().uuid()), role.uuid())
+        .await
+    {
+        Ok(status) => Json::from(Some(status)),
+        Err(e) => {
+            tracing::error!(
+                "Failure retrieving follow status of {}: {:?}",
+                role.uuid(),
+                e
+            );
+            Json::from(None)
+        }
+    }
+}
+
+async fn set_following(
+    LoggedInUser(user): LoggedInUser,
+    Path(role): Path<String>,
+    Json(request): Json<FollowRequest>,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<FollowResponse> {
+    let role = match dbconn.role_by_uuid_or_short_name(&role).await {
+        Ok(role) => role,
+        Err(_) => return FollowResponse::UnknownRole.into(),
+    };
+    let identity = user.identity().uuid();
+    if let Err(e) = dbconn
+        .set_following(identity, role.uuid(), request.follow)
+        .await
+    {
+        return FollowResponse::DatabaseFailure(e.to_string()).into();
+    }
+    match dbconn.follow_status(Some(identity), role.uuid()).await {
+        Ok(status) => FollowResponse::Success(status),
+        Err(e) => FollowResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
+/// The most entries we will put in a feed
+const FEED_LENGTH: usize = 50;
+
+async fn feed(
+    LoggedInUser(user): LoggedInUser,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<Vec<FeedEntry>>> {
+    let puzzles = match dbconn
+        .followed_puzzles(user.identity().uuid(), FEED_LENGTH)
+        .await
+    {
+        Ok(puzzles) => puzzles,
+        Err(e) => {
+            tracing::error!("Failure building feed: {:?}", e);
+            return Json::from(None);
+        }
+    };
+    let mut ret = Vec::new();
+    for (uuid, when) in puzzles {
+        let puzzle = match dbconn.puzzle_by_uuid_or_short_name(&uuid).await {
+            Ok(puzzle) => puzzle,
+            Err(_) => continue,
+        };
+        if puzzle.visibility() != Visibility::Published {
+            continue;
+        }
+        let setter_rating = puzzle
+            .as_api_puzzle(false)
+            .states
+            .last()
+            .map(|s| s.setter_rating)
+            .unwrap_or_default();
+        ret.push(FeedEntry {
+            uuid,
+            short_name: puzzle.short_name().to_string(),
+            display_name: puzzle.display_name().to_string(),
+            owner: puzzle.owner().to_string(),
+            setter_rating,
+            when,
+        });
+    }
+    Json::from(Some(ret))
+}
+
+/// How many deliveries to show from a webhook's log
+const WEBHOOK_LOG_LENGTH: usize = 20;
+
+async fn webhook_status(dbconn: &mut Database, role: &str) -> Result<WebhookStatus, DatabaseError> {
+    let webhook = dbconn.role_webhook(role).await?;
+    Ok(WebhookStatus {
+        has_secret: webhook
+            .as_ref()
+            .map(|w| w.secret.is_some())
+            .unwrap_or(false),
+        url: webhook.map(|w| w.url),
+        deliveries: dbconn.webhook_deliveries(role, WEBHOOK_LOG_LENGTH).await?,
+    })
+}
+
+async fn get_webhook(
+    RoleMember { role, .. }: RoleMember,
+    Extension(mut dbconn): Extension<Database>,
+) -> Json<Option<WebhookStatus>> {
+    match webhook_status(&mut dbconn, role.uuid()).await {
+        Ok(status) => Json::from(Some(status)),
+        Err(e) => {
+            tracing::error!("Failure retrieving webhook of {}: {:?}", role.uuid(), e);
+            Json::from(None)
+        }
+    }
+}
+
+async fn set_webhook(
+    RoleMember { user, role }: RoleMember,
+    Json(request): Json<SetWebhookRequest>,
+    Extension(mut dbconn): Extension<Database>,
+    Extension(state): Extension<AppState>,
+) -> Json<SetWebhookResponse> {
+    let old = match dbconn.role_webhook(role.uuid()).await {
+        Ok(old) => old,
+        Err(e) => return SetWebhookResponse::DatabaseFailure(e.to_string()).into(),
+    };
+    let webhook = match request.webhook {
+        Some(webhook) => {
+            let url = webhook.url.trim().to_string();
+            // Delivery checks again, since what the host resolves to may change
+            match webhook::resolve_destination(&url, state.config.allow_internal_webhooks).await {
+                Ok(_) => {}
+                Err(DestinationError::InvalidUrl) => return SetWebhookResponse::InvalidUrl.into(),
+                Err(e) => return SetWebhookResponse::ForbiddenUrl(e.to_string()).into(),
+            }
+            // The secret is never sent back out, so the owner can't send it
+            // back in, and no secret means keep the one we have
+            let secret = match webhook.secret.filter(|s| !s.is_empty()) {
+                Some(secret) => Some(secret),
+                None if request.clear_secret => None,
+                None => old.as_ref().and_then(|old| old.secret.clone()),
+            };
+            Some(WebhookSettings { url, secret })
+        }
+        None => None,
+    };
+    if let Err(e) = dbconn.set_role_webhook(role.uuid(), webhook.as_ref()).await {
+        return SetWebhookResponse::DatabaseFailure(e.to_string()).into();
+    }
+    // Secrets are secret, so we only say whether there is one
+    let summarise = |webhook: Option<&WebhookSettings>| {
+        (
+            webhook.map(|w| w.url.clone()).unwrap_or_default(),
+            match webhook.and_then(|w| w.secret.as_ref()) {
+                Some(_) => "set",
+                None => "",
+            },
+        )
+    };
+    let (old_url, old_secret) = summarise(old.as_ref());
+    let (new_url, new_secret) = summarise(webhook.as_ref());
+    activity::record(
+        &mut dbconn,
+        &Actor::user(&user),
+        role.uuid(),
+        "role-webhook",
+        role.uuid(),
+        activity::diff(&[
+            ("url", &old_url, &new_url),
+            ("secret", old_secret, new_secret),
+        ]),
+    )
+    .await;
+    match webhook_status(&mut dbconn, role.uuid()).await {
+        Ok(status) => SetWebhookResponse::Success(status),
+        Err(e) => SetWebhookResponse::DatabaseFailure(e.to_string()),
+    }
+    .into()
+}
+
 pub fn router() -> Router {
-    Router::new().route("/get/:role", get(role_by_uuid_or_short_name))
+    Router::new()
+        .route("/get/:role", get(role_by_uuid_or_short_name))
+        .route("/follow/:role", get(follow_status).post(set_following))
+        .route("/feed", get(feed))
+        .route("/webhook/:role", get(get_webhook).post(set_webhook))
+        .route("/activity/:role", get(crate::activity::role_activity))
+        .route("/feed/:role", get(crate::atom::role_feed))
 }
@@ -0,0 +1,33 @@
+-- Creating a personal API token in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   apitoken:{hash}
+--   identity:{uuid}:apitokens
+-- And the following arguments are expected, in the following order
+--   hash
+--   identity
+--   name
+--   roles (space separated UUIDs)
+--   write ("1" or "0")
+--   created
+--
+-- Tokens are stored by the SHA-256 of the token, so the token itself never
+-- reaches the database.
+
+local token, tokens = KEYS[1], KEYS[2]
+local hash, identity, name, roles, write, created =
+    ARGV[1], ARGV[2], ARGV[3], ARGV[4], ARGV[5], ARGV[6]
+
+if redis.call("EXISTS", token) == 1 then
+    return redis.error_reply("Token already exists")
+end
+
+redis.call("HSET", token,
+    "identity", identity,
+    "name", name,
+    "roles", roles,
+    "write", write,
+    "created", created)
+redis.call("SADD", tokens, hash)
+
+return 1
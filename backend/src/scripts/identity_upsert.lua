@@ -2,14 +2,19 @@
 -- Script must be invoked with the following keys:
 --   identity:UUID
 --   identity:UUID:roles
+--   identity:UUID:subjects
+--   identity:bysubject
 -- Also the following arguments are expected, in order:
 --   display_name
 --   gravatar_hash (empty string if unavailable)
--- The script will upsert the identity and return the roles
--- that the identity has (empty list on new identity)
+--   uuid
+--   subject
+-- The script will upsert the identity, record that the subject logs
+-- in as it, and return the roles that the identity has (empty list on
+-- new identity)
 
-local key_id, key_roles = KEYS[1], KEYS[2]
-local display_name, gravatar_hash = ARGV[1], ARGV[2]
+local key_id, key_roles, key_subjects, key_bysubject = KEYS[1], KEYS[2], KEYS[3], KEYS[4]
+local display_name, gravatar_hash, uuid, subject = ARGV[1], ARGV[2], ARGV[3], ARGV[4]
 
 if gravatar_hash ~= "" then
     redis.call("HSET", key_id, "display_name", display_name, "gravatar_hash", gravatar_hash)
@@ -17,4 +22,7 @@ else
     redis.call("HSET", key_id, "display_name", display_name)
 end
 
+redis.call("HSETNX", key_bysubject, subject, uuid)
+redis.call("SADD", key_subjects, subject)
+
 return redis.pcall("SMEMBERS", key_roles)
@@ -0,0 +1,33 @@
+-- Linking another login subject to an identity in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   identity:bysubject
+--   identity:{uuid}:subjects
+--   identity:{subject_uuid}
+-- And the following arguments are expected, in the following order
+--   subject
+--   uuid
+--   subject_uuid
+--
+-- Where subject_uuid is the UUID the subject would have by default.
+--
+-- Returns 1 if the subject is now linked to the identity, 0 if the subject
+-- already belongs to another identity
+
+local bysubject, subjects, subject_identity = KEYS[1], KEYS[2], KEYS[3]
+local subject, uuid, subject_uuid = ARGV[1], ARGV[2], ARGV[3]
+
+local current = redis.call("HGET", bysubject, subject)
+if current and current ~= uuid then
+    return 0
+end
+
+-- Identities from before subjects were indexed are only known by their UUID
+if not current and subject_uuid ~= uuid and redis.call("EXISTS", subject_identity) == 1 then
+    return 0
+end
+
+redis.call("HSET", bysubject, subject, uuid)
+redis.call("SADD", subjects, subject)
+
+return 1
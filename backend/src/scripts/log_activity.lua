@@ -0,0 +1,26 @@
+-- Recording a change in the Linkdoku Redis activity log
+--
+-- Script must be called with the following keys:
+--   activity
+--   role:{uuid}
+--   role:{uuid}:activity
+-- And the following arguments are expected, in the following order
+--   max_length of the site-wide log
+--   max_length of the role's log
+--   then field/value pairs for the entry
+--
+-- Every entry goes into the site-wide log, but only roles which still exist
+-- get one in their own log, so that recording the deletion of a role does not
+-- bring its log back from the dead.
+
+local activity, role_key, role_activity = KEYS[1], KEYS[2], KEYS[3]
+local max_length, role_max_length = ARGV[1], ARGV[2]
+local fields = { unpack(ARGV, 3) }
+
+redis.call("XADD", activity, "MAXLEN", "~", max_length, "*", unpack(fields))
+
+if redis.call("EXISTS", role_key) == 1 then
+    redis.call("XADD", role_activity, "MAXLEN", "~", role_max_length, "*", unpack(fields))
+end
+
+return 1
@@ -0,0 +1,26 @@
+-- Marking a puzzle as solved in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   puzzle:{uuid}:solvers
+--   puzzle:{uuid}:solvetimes
+-- And the following arguments are expected, in the following order
+--   identity
+--   current_time
+--   solve_time (empty string if not provided)
+--
+-- The first time an identity solves a puzzle is kept, and only the
+-- fastest solve time for any given identity is retained.
+
+local solvers, solvetimes = KEYS[1], KEYS[2]
+local identity, current_time, solve_time = ARGV[1], ARGV[2], ARGV[3]
+
+redis.call("ZADD", solvers, "NX", current_time, identity)
+
+if solve_time ~= "" then
+    local existing = redis.call("ZSCORE", solvetimes, identity)
+    if (not existing) or (tonumber(solve_time) < tonumber(existing)) then
+        redis.call("ZADD", solvetimes, solve_time, identity)
+    end
+end
+
+return redis.call("ZCARD", solvers)
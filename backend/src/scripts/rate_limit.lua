@@ -0,0 +1,37 @@
+-- Taking a token from a rate limiting bucket in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   ratelimit:{group}:{client}
+-- And the following arguments are expected, in the following order
+--   capacity, the most tokens the bucket can hold
+--   refill_ms, how many milliseconds it takes for one token to come back
+--   now_ms, the current time in milliseconds
+--
+-- Returns 0 if a token was taken, otherwise how many milliseconds it will be
+-- until one is available.  Buckets expire once they would have filled up
+-- again, so clients we've not heard from in a while cost nothing.
+
+local bucket = KEYS[1]
+local capacity, refill_ms, now_ms = tonumber(ARGV[1]), tonumber(ARGV[2]), tonumber(ARGV[3])
+
+local state = redis.call("HMGET", bucket, "tokens", "updated")
+local tokens = tonumber(state[1]) or capacity
+local updated = tonumber(state[2]) or now_ms
+
+-- Tokens come back over time, but the clock is not allowed to run backwards
+if now_ms > updated then
+    tokens = math.min(capacity, tokens + (now_ms - updated) / refill_ms)
+    updated = now_ms
+end
+
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * refill_ms)
+end
+
+redis.call("HSET", bucket, "tokens", tostring(tokens), "updated", tostring(updated))
+redis.call("PEXPIRE", bucket, math.ceil((capacity - tokens) * refill_ms) + 1000)
+
+return wait
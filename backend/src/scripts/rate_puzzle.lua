@@ -0,0 +1,39 @@
+-- Rating a puzzle's difficulty and quality in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   puzzle:{uuid}:difficulty
+--   puzzle:{uuid}:difficulty:counts
+--   puzzle:{uuid}:quality
+--   puzzle:{uuid}:quality:totals
+-- And the following arguments are expected, in the following order
+--   identity
+--   difficulty (empty string if not being changed)
+--   quality (empty string if not being changed)
+--
+-- Each identity has at most one difficulty and one quality rating, so
+-- replacing a rating removes the old one from the aggregates first.
+
+local difficulty_key, difficulty_counts, quality_key, quality_totals = KEYS[1], KEYS[2], KEYS[3], KEYS[4]
+local identity, difficulty, quality = ARGV[1], ARGV[2], ARGV[3]
+
+if difficulty ~= "" then
+    local old = redis.call("HGET", difficulty_key, identity)
+    if old then
+        redis.call("HINCRBY", difficulty_counts, old, -1)
+    end
+    redis.call("HSET", difficulty_key, identity, difficulty)
+    redis.call("HINCRBY", difficulty_counts, difficulty, 1)
+end
+
+if quality ~= "" then
+    local old = redis.call("HGET", quality_key, identity)
+    if old then
+        redis.call("HINCRBY", quality_totals, "total", -tonumber(old))
+    else
+        redis.call("HINCRBY", quality_totals, "count", 1)
+    end
+    redis.call("HSET", quality_key, identity, quality)
+    redis.call("HINCRBY", quality_totals, "total", quality)
+end
+
+return 1
@@ -0,0 +1,20 @@
+-- Revoking a personal API token in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   apitoken:{hash}
+--   identity:{uuid}:apitokens
+-- And the following arguments are expected, in the following order
+--   hash
+--
+-- Returns 1 if the token was revoked, 0 if the identity had no such token
+
+local token, tokens = KEYS[1], KEYS[2]
+local hash = ARGV[1]
+
+if redis.call("SREM", tokens, hash) == 0 then
+    return 0
+end
+
+redis.call("DEL", token)
+
+return 1
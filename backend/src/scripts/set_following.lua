@@ -0,0 +1,24 @@
+-- Following or unfollowing a role in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   identity:{uuid}:follows
+--   role:{uuid}:followers
+-- And the following arguments are expected, in the following order
+--   identity
+--   role
+--   "follow" or "unfollow"
+--
+-- Both directions of the relationship are kept in step with one another.
+
+local follows, followers = KEYS[1], KEYS[2]
+local identity, role, action = ARGV[1], ARGV[2], ARGV[3]
+
+if action == "follow" then
+    redis.call("SADD", follows, role)
+    redis.call("SADD", followers, identity)
+else
+    redis.call("SREM", follows, role)
+    redis.call("SREM", followers, identity)
+end
+
+return redis.call("SCARD", followers)
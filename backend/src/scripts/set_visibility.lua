@@ -0,0 +1,31 @@
+-- Changing the visibility of a puzzle in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   puzzle:{uuid}
+--   puzzles:published
+-- And the following arguments are expected, in the following order
+--   uuid
+--   visibility
+--   visibility_date
+--   states
+--   current_time
+--
+-- The published index keeps the time a puzzle was published, so publishing
+-- an already published puzzle does not bump it back to the top of feeds.
+
+local puzzle_key, published = KEYS[1], KEYS[2]
+local uuid, visibility, visibility_date, states, current_time = ARGV[1], ARGV[2], ARGV[3], ARGV[4], ARGV[5]
+
+if redis.call("EXISTS", puzzle_key) == 0 then
+    return redis.error_reply("no-such-puzzle")
+end
+
+redis.call("HSET", puzzle_key, "visibility", visibility, "visibility_date", visibility_date, "states", states)
+
+if visibility == "published" then
+    redis.call("ZADD", published, "NX", current_time, uuid)
+else
+    redis.call("ZREM", published, uuid)
+end
+
+return 1
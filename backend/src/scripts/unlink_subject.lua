@@ -0,0 +1,27 @@
+-- Unlinking a login subject from an identity in the Linkdoku Redis
+--
+-- Script must be called with the following keys:
+--   identity:bysubject
+--   identity:{uuid}:subjects
+-- And the following arguments are expected, in the following order
+--   subject
+--
+-- Returns 1 if the subject was unlinked, 0 if it was not linked to the
+-- identity, and -1 if it is the identity's only subject, since removing
+-- that would leave nobody able to log in as the identity
+
+local bysubject, subjects = KEYS[1], KEYS[2]
+local subject = ARGV[1]
+
+if redis.call("SISMEMBER", subjects, subject) == 0 then
+    return 0
+end
+
+if redis.call("SCARD", subjects) <= 1 then
+    return -1
+end
+
+redis.call("SREM", subjects, subject)
+redis.call("HDEL", bysubject, subject)
+
+return 1
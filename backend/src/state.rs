@@ -0,0 +1,70 @@
+//! Application state shared by all handlers
+//!
+//! Everything derived from the configuration at startup lives here, and is
+//! made available to handlers as an `Extension<AppState>`.  Since nothing is
+//! global, the state is fully set up before the router is built, and several
+//! instances of the application can coexist in one process.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use tokio::sync::Semaphore;
+use tower_cookies::Key;
+use url::Url;
+
+use crate::{
+    config::Configuration,
+    headers::PolicyCache,
+    login::{self, ProviderSetup},
+    puzzle::MAX_CONCURRENT_SOLVES,
+};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Configuration>,
+    /// Configured login providers, by lowercase name
+    pub providers: Arc<HashMap<String, ProviderSetup>>,
+    /// The key with which login cookies are encrypted
+    pub login_key: Key,
+    /// The public base URL of the site, without a trailing slash
+    pub base_url: String,
+    /// The HTTP client used for outgoing requests such as fetching userinfo,
+    /// webhooks have their own (see [`crate::webhook`])
+    pub http_client: reqwest::Client,
+    /// The built in content security policy for the frontend
+    pub csp_cache: Arc<PolicyCache>,
+    /// Permits for running the solver, which is limited in how much it may
+    /// run at once
+    pub solver_permits: Arc<Semaphore>,
+}
+
+impl AppState {
+    pub async fn new(config: Configuration) -> AppState {
+        let providers = login::load_providers(&config).await;
+        let login_key = Key::derive_from(config.cookie_secret.as_bytes());
+        // The site's base URL is derived from the login redirect URL, which
+        // must already be the public address of the site
+        let base_url = Url::parse(&config.redirect_url)
+            .map(|mut url| {
+                url.set_path("");
+                url.set_query(None);
+                url.to_string().trim_end_matches('/').to_string()
+            })
+            .unwrap_or_default();
+        AppState {
+            config: Arc::new(config),
+            providers: Arc::new(providers),
+            login_key,
+            base_url,
+            http_client: reqwest::Client::new(),
+            csp_cache: Arc::default(),
+            solver_permits: Arc::new(Semaphore::new(MAX_CONCURRENT_SOLVES)),
+        }
+    }
+
+    /// The frontend's `index.html`, which is served for every page of the SPA
+    pub fn index_html(&self) -> PathBuf {
+        let mut ret = self.config.resources.clone();
+        ret.push("index.html");
+        ret
+    }
+}
@@ -0,0 +1,514 @@
+//! Webhook notifications for roles
+//!
+//! A role may configure a webhook, to which we POST a [`WebhookPayload`]
+//! whenever one of its puzzles is published.  If the webhook has a secret then
+//! the payload is signed with HMAC-SHA256 so that the receiver can check it
+//! really came from us.  Deliveries are retried with backoff on network errors
+//! and server errors, and the outcome is recorded in the role's delivery log.
+//!
+//! Since anyone may set up a webhook, we must not let them use us to reach
+//! the server itself or its private network.  Webhook URLs are resolved when
+//! they are set, and again at each delivery, and refused if any address is
+//! internal.  Deliveries only connect to the addresses we checked, and don't
+//! follow redirects, so DNS and redirects can't send them anywhere else.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+use hmac::{Hmac, Mac};
+use linkdoku_common::{WebhookDelivery, WebhookPayload, WebhookSettings};
+use reqwest::{
+    header::{CONTENT_TYPE, USER_AGENT},
+    redirect::Policy,
+    Client, StatusCode,
+};
+use sha2::Sha256;
+use url::{Host, Url};
+
+use crate::{
+    dbconn::{self, Database},
+    state::AppState,
+};
+
+/// The header in which the payload signature is sent
+pub const SIGNATURE_HEADER: &str = "X-Linkdoku-Signature";
+
+/// How many times we try to deliver a payload before giving up
+const MAX_ATTEMPTS: u32 = 4;
+
+/// How long we wait before the first retry, this doubles for each retry
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long we give a webhook to respond
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sign a payload body with a webhook's secret
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Reasons we won't deliver to a webhook URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationError {
+    /// The URL is not an http or https URL
+    InvalidUrl,
+    /// The URL's host could not be resolved
+    Unresolvable(String),
+    /// The URL's host is, or resolves to, an internal address
+    Internal(IpAddr),
+    /// We couldn't make a client to deliver with
+    Client(String),
+}
+
+impl Display for DestinationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl => write!(f, "Webhook URL must be http or https"),
+            Self::Unresolvable(e) => write!(f, "Unable to resolve webhook host: {}", e),
+            Self::Internal(addr) => write!(f, "Webhook host {} is an internal address", addr),
+            Self::Client(e) => write!(f, "Unable to create webhook client: {}", e),
+        }
+    }
+}
+
+/// Whether an address is the server itself, or on a private network
+pub fn is_internal(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                // "This network"
+                || a == 0
+                // Shared address space, as used for carrier grade NAT
+                || (a == 100 && (b & 0xc0) == 64)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_internal(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // Unique local
+                || (first & 0xfe00) == 0xfc00
+                // Link local
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Resolve a webhook URL's host, refusing it if any address is internal
+/// (unless `allow_internal`, for development and testing)
+pub async fn resolve_destination(
+    url: &str,
+    allow_internal: bool,
+) -> Result<(Url, Vec<SocketAddr>), DestinationError> {
+    let parsed = Url::parse(url).map_err(|_| DestinationError::InvalidUrl)?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(DestinationError::InvalidUrl);
+    }
+    let port = parsed
+        .port_or_known_default()
+        .ok_or(DestinationError::InvalidUrl)?;
+    let addrs: Vec<SocketAddr> = match parsed.host().ok_or(DestinationError::InvalidUrl)? {
+        Host::Ipv4(addr) => vec![SocketAddr::new(addr.into(), port)],
+        Host::Ipv6(addr) => vec![SocketAddr::new(addr.into(), port)],
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| DestinationError::Unresolvable(e.to_string()))?
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(DestinationError::Unresolvable("no addresses".to_string()));
+    }
+    if !allow_internal {
+        if let Some(addr) = addrs.iter().find(|addr| is_internal(addr.ip())) {
+            return Err(DestinationError::Internal(addr.ip()));
+        }
+    }
+    Ok((parsed, addrs))
+}
+
+/// A client which can only reach the webhook at the address we checked
+async fn webhook_client(url: &str, allow_internal: bool) -> Result<Client, DestinationError> {
+    let (parsed, addrs) = resolve_destination(url, allow_internal).await?;
+    let mut builder = Client::builder().redirect(Policy::none());
+    if let Some(Host::Domain(domain)) = parsed.host() {
+        builder = builder.resolve(domain, addrs[0]);
+    }
+    builder
+        .build()
+        .map_err(|e| DestinationError::Client(e.to_string()))
+}
+
+fn delivery_now(
+    payload: &WebhookPayload,
+    attempts: u32,
+    status: Option<u16>,
+    error: Option<String>,
+) -> WebhookDelivery {
+    WebhookDelivery {
+        puzzle: payload.puzzle.uuid.clone(),
+        when: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        attempts,
+        status,
+        error,
+    }
+}
+
+/// Deliver a payload to a webhook, retrying as needed
+///
+/// Network errors, server errors, and rate limiting are retried, anything
+/// else is taken as the webhook's final answer.  Webhooks at internal
+/// addresses are refused without any attempt, unless `allow_internal`.
+pub async fn deliver(
+    webhook: &WebhookSettings,
+    payload: &WebhookPayload,
+    retry_delay: Duration,
+    allow_internal: bool,
+) -> WebhookDelivery {
+    let client = match webhook_client(&webhook.url, allow_internal).await {
+        Ok(client) => client,
+        Err(e) => return delivery_now(payload, 0, None, Some(e.to_string())),
+    };
+    let body = serde_json::to_vec(payload).expect("Odd, JSON encoding failed?");
+    let signature = webhook.secret.as_deref().map(|secret| sign(secret, &body));
+    let mut delay = retry_delay;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut request = client
+            .post(&webhook.url)
+            .timeout(TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .header(USER_AGENT, "Linkdoku")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        let (status, error, retry) = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None, false)
+            }
+            Ok(response) => {
+                let status = response.status();
+                (
+                    Some(status.as_u16()),
+                    Some(format!("Webhook responded with {}", status)),
+                    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+                )
+            }
+            Err(e) => (None, Some(e.to_string()), true),
+        };
+        if !retry || attempts >= MAX_ATTEMPTS {
+            return delivery_now(payload, attempts, status, error);
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Notify the owner's webhook, if it has one, that a puzzle was published
+///
+/// This is intended to be spawned off rather than awaited by a request handler
+/// since delivery may take some time if the webhook is having a bad day.
+pub async fn notify_published(state: AppState, mut dbconn: Database, puzzle: dbconn::Puzzle) {
+    let webhook = match dbconn.role_webhook(puzzle.owner()).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!("Unable to look up webhook for {}: {:?}", puzzle.owner(), e);
+            return;
+        }
+    };
+    let role = match dbconn.role_by_uuid_or_short_name(puzzle.owner()).await {
+        Ok(role) => role,
+        Err(e) => {
+            tracing::error!("Unable to look up role {}: {:?}", puzzle.owner(), e);
+            return;
+        }
+    };
+    let payload = WebhookPayload {
+        event: "published".to_string(),
+        role: role.as_api_role(),
+        puzzle: puzzle.as_api_puzzle(false),
+        url: format!("{}/-/puzzle/{}", state.base_url, puzzle.short_name()),
+    };
+    let delivery = deliver(
+        &webhook,
+        &payload,
+        RETRY_DELAY,
+        state.config.allow_internal_webhooks,
+    )
+    .await;
+    if let Some(error) = &delivery.error {
+        tracing::warn!(
+            "Webhook delivery for {} failed after {} attempts: {}",
+            puzzle.uuid(),
+            delivery.attempts,
+            error
+        );
+    }
+    if let Err(e) = dbconn.log_webhook_delivery(role.uuid(), &delivery).await {
+        tracing::error!(
+            "Unable to log webhook delivery for {}: {:?}",
+            role.uuid(),
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Extension, Router,
+    };
+    use linkdoku_common::{Puzzle, RoleData};
+
+    use super::*;
+
+    /// A local HTTP stub which answers with the given statuses in turn, and
+    /// records what it was sent
+    #[derive(Default)]
+    struct Stub {
+        responses: Mutex<Vec<StatusCode>>,
+        received: Mutex<Vec<(HeaderMap, Bytes)>>,
+    }
+
+    async fn receive(
+        headers: HeaderMap,
+        Extension(stub): Extension<Arc<Stub>>,
+        body: Bytes,
+    ) -> StatusCode {
+        stub.received.lock().unwrap().push((headers, body));
+        let mut responses = stub.responses.lock().unwrap();
+        if responses.is_empty() {
+            StatusCode::OK
+        } else {
+            responses.remove(0)
+        }
+    }
+
+    async fn start_stub(responses: Vec<StatusCode>) -> (String, Arc<Stub>) {
+        let stub = Arc::new(Stub {
+            responses: Mutex::new(responses),
+            ..Default::default()
+        });
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .layer(Extension(stub.clone()));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+        (url, stub)
+    }
+
+    fn payload() -> WebhookPayload {
+        WebhookPayload {
+            event: "published".to_string(),
+            role: RoleData {
+                uuid: "role-uuid".to_string(),
+                short_name: "setter".to_string(),
+                display_name: "A Setter".to_string(),
+                ..Default::default()
+            },
+            puzzle: Puzzle {
+                uuid: "puzzle-uuid".to_string(),
+                short_name: "a-puzzle".to_string(),
+                display_name: "A Puzzle".to_string(),
+                ..Default::default()
+            },
+            url: "https://example.com/-/puzzle/a-puzzle".to_string(),
+        }
+    }
+
+    #[test]
+    fn signature_matches_rfc4231() {
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn signed_delivery() {
+        let (url, stub) = start_stub(vec![]).await;
+        let webhook = WebhookSettings {
+            url,
+            secret: Some("sekrit".to_string()),
+        };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(delivery.succeeded());
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status, Some(200));
+        assert_eq!(delivery.puzzle, "puzzle-uuid");
+
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(
+            headers.get(SIGNATURE_HEADER).unwrap().to_str().unwrap(),
+            sign("sekrit", body)
+        );
+        let sent: WebhookPayload = serde_json::from_slice(body).unwrap();
+        assert_eq!(sent, payload());
+    }
+
+    #[tokio::test]
+    async fn unsigned_delivery() {
+        let (url, stub) = start_stub(vec![]).await;
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(delivery.succeeded());
+        let received = stub.received.lock().unwrap();
+        assert!(received[0].0.get(SIGNATURE_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, stub) = start_stub(vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ])
+        .await;
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(delivery.succeeded());
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(stub.received.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_eventually() {
+        let (url, stub) = start_stub(vec![StatusCode::BAD_GATEWAY; 10]).await;
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(!delivery.succeeded());
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(delivery.status, Some(502));
+        assert_eq!(stub.received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn refuses_internal_addresses() {
+        let (url, stub) = start_stub(vec![]).await;
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, false).await;
+        assert!(!delivery.succeeded());
+        assert_eq!(delivery.attempts, 0);
+        assert!(stub.received.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn does_not_follow_redirects() {
+        let (target, stub) = start_stub(vec![]).await;
+        let app = Router::new().route(
+            "/hook",
+            post(move || async move { axum::response::Redirect::temporary(&target) }),
+        );
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(!delivery.succeeded());
+        assert_eq!(delivery.status, Some(307));
+        assert!(stub.received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn internal_addresses() {
+        for addr in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(is_internal(addr.parse().unwrap()), "{}", addr);
+        }
+        for addr in ["93.184.216.34", "8.8.8.8", "2606:4700::1111", "100.128.0.1"] {
+            assert!(!is_internal(addr.parse().unwrap()), "{}", addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_destinations() {
+        for url in [
+            "http://127.0.0.1:6379/",
+            "http://localhost:6379/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "https://10.0.0.1/hook",
+        ] {
+            assert!(
+                matches!(
+                    resolve_destination(url, false).await,
+                    Err(DestinationError::Internal(_))
+                ),
+                "{}",
+                url
+            );
+        }
+        for url in ["ftp://example.com/", "not a url", "file:///etc/passwd"] {
+            assert_eq!(
+                resolve_destination(url, false).await.err(),
+                Some(DestinationError::InvalidUrl),
+                "{}",
+                url
+            );
+        }
+        let (_, addrs) = resolve_destination("http://127.0.0.1:8080/hook", true)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:8080".parse().unwrap()]);
+        let (_, addrs) = resolve_destination("https://93.184.216.34/hook", false)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["93.184.216.34:443".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, stub) = start_stub(vec![StatusCode::NOT_FOUND]).await;
+        let webhook = WebhookSettings { url, secret: None };
+        let delivery = deliver(&webhook, &payload(), Duration::ZERO, true).await;
+        assert!(!delivery.succeeded());
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status, Some(404));
+        assert_eq!(stub.received.lock().unwrap().len(), 1);
+    }
+}
@@ -23,6 +23,366 @@ pub enum LoginFlowStart {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LoginFlowResult {
     pub error: Option<String>,
+    /// Whether the flow linked another login to an already logged in user
+    #[serde(default)]
+    pub linked: bool,
+}
+
+/// A login provider which the user may choose to log in with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginProvider {
+    /// Name used in `/login/start/:provider`
+    pub name: String,
+    pub label: String,
+    /// Font Awesome classes for the provider's icon, if any
+    pub icon: Option<String>,
+}
+
+/// How a login provider is faring, as reported by `/api/health`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderHealth {
+    pub name: String,
+    /// Whether users can currently log in with the provider
+    pub live: bool,
+    /// When the provider's metadata was last discovered, for OIDC providers
+    pub last_discovered: Option<u64>,
+    /// How many discovery attempts have failed in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    /// Whether the database answered
+    pub database: bool,
+    pub providers: Vec<ProviderHealth>,
+}
+
+/// The "code" the development login page sends back, in place of a real
+/// provider's authorization code
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DevLogin {
+    pub subject: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
+/// A login subject linked to the logged in identity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedLogin {
+    /// The full subject, `provider:subject`
+    pub subject: String,
+    /// The name of the provider, as used in `/login/start/:provider`
+    pub provider: String,
+    pub label: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnlinkLoginRequest {
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnlinkLoginResponse {
+    Success,
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The subject is not linked to the user
+    NotLinked,
+    /// The subject is the only way the user can log in
+    LastLogin,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for UnlinkLoginResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnlinkLoginResponse::Success => write!(f, "Ok"),
+            UnlinkLoginResponse::NotLoggedIn => write!(f, "Not logged in"),
+            UnlinkLoginResponse::NotLinked => write!(f, "That login is not linked to you"),
+            UnlinkLoginResponse::LastLogin => {
+                write!(f, "You cannot unlink the only way you can log in")
+            }
+            UnlinkLoginResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Where an identity's avatar comes from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AvatarSource {
+    /// Gravatar, based on the email address the login provider gave us
+    #[default]
+    Gravatar,
+    /// No avatar, just initials
+    None,
+}
+
+/// The logged in identity's profile
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// The name the login provider gave us
+    pub provider_name: String,
+    /// The name the user would rather be known by, which survives logins
+    pub preferred_name: Option<String>,
+    pub avatar: AvatarSource,
+    /// Whether we know an email hash, without which gravatars won't work
+    pub has_gravatar: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub preferred_name: Option<String>,
+    pub avatar: AvatarSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpdateProfileResponse {
+    Success(Profile),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The preferred name is too long
+    InvalidName,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for UpdateProfileResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateProfileResponse::Success(_) => write!(f, "Ok"),
+            UpdateProfileResponse::NotLoggedIn => write!(f, "Not logged in"),
+            UpdateProfileResponse::InvalidName => write!(f, "That name is too long"),
+            UpdateProfileResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Everything we hold about an identity, for them to take away
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileExport {
+    pub uuid: String,
+    pub profile: Profile,
+    /// The login subjects linked to the identity
+    pub logins: Vec<String>,
+    pub roles: Vec<RoleData>,
+    pub puzzles: Vec<Puzzle>,
+    pub api_tokens: Vec<ApiTokenInfo>,
+}
+
+/// What becomes of an identity's roles, and their puzzles, when it is deleted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeletionPolicy {
+    /// Roles and puzzles stay, but nobody can manage them any more
+    Orphan,
+    /// Roles and puzzles are handed to the owner of the given role
+    Reassign(String),
+    /// Roles and puzzles are deleted too
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub policy: DeletionPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeleteAccountResponse {
+    Success,
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The role to reassign to does not exist
+    UnknownRole,
+    /// Roles cannot be reassigned to yourself
+    CannotReassignToSelf,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for DeleteAccountResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeleteAccountResponse::Success => write!(f, "Ok"),
+            DeleteAccountResponse::NotLoggedIn => write!(f, "Not logged in"),
+            DeleteAccountResponse::UnknownRole => write!(f, "Unknown role"),
+            DeleteAccountResponse::CannotReassignToSelf => {
+                write!(f, "You cannot hand your roles to yourself")
+            }
+            DeleteAccountResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An identity, as site administrators see it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminIdentity {
+    pub uuid: String,
+    pub display_name: String,
+    /// The name the login provider gave us
+    pub provider_name: String,
+    /// The login subjects linked to the identity
+    pub subjects: Vec<String>,
+    /// UUIDs of the roles the identity can act as
+    pub roles: Vec<String>,
+    /// Why the identity is suspended, if it is
+    pub suspended: Option<String>,
+    /// Whether the identity is a site administrator
+    pub admin: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestrictPuzzleRequest {
+    /// UUID or short name of the puzzle
+    pub puzzle: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuspendIdentityRequest {
+    pub identity: String,
+    /// Why the identity is suspended, or `None` to lift a suspension
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdminActionResponse {
+    Success,
+    /// The puzzle or identity acted upon does not exist
+    NotFound,
+    /// Site administrators cannot be suspended
+    CannotSuspendAdmin,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for AdminActionResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminActionResponse::Success => write!(f, "Ok"),
+            AdminActionResponse::NotFound => write!(f, "Not found"),
+            AdminActionResponse::CannotSuspendAdmin => {
+                write!(f, "Site administrators cannot be suspended")
+            }
+            AdminActionResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Something a site administrator did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminAuditEntry {
+    /// When it was done, in seconds since the epoch
+    pub when: u64,
+    /// UUID of the administrator's identity
+    pub admin: String,
+    /// What was done, such as `restrict-puzzle` or `suspend-identity`
+    pub action: String,
+    /// UUID of the puzzle or identity it was done to
+    pub target: String,
+    /// The administrator's reason, or other detail
+    pub detail: String,
+}
+
+/// Which of their roles a logged in user is acting as
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChooseRoleRequest {
+    pub role: String,
+}
+
+/// A change to a role or its puzzles, as recorded in the activity log
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityEntry {
+    /// When the change was made, in seconds since the epoch
+    pub when: u64,
+    /// UUID of the identity which made the change
+    pub actor: String,
+    /// UUID of the role the identity was acting as, if it was logged in
+    pub active_role: Option<String>,
+    /// UUID of the role whose things were changed
+    pub role: String,
+    /// What was done, such as `puzzle-create` or `puzzle-visibility`
+    pub action: String,
+    /// UUID of the role, puzzle or comment which was changed
+    pub target: String,
+    /// What changed, as `field: old → new` pairs
+    pub summary: String,
+}
+
+/// A personal API token, as listed to its identity
+///
+/// The token itself is only ever revealed once, when it is created.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTokenInfo {
+    /// Identifies the token for revocation
+    pub id: String,
+    pub name: String,
+    /// UUIDs of the roles the token may act as
+    pub roles: Vec<String>,
+    /// Whether the token may change things, or only read them
+    pub write: bool,
+    /// When the token was created, in seconds since the epoch
+    pub created: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    pub roles: Vec<String>,
+    pub write: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CreateApiTokenResponse {
+    /// The token was created, this is the only time it will be shown
+    Success { token: String, info: ApiTokenInfo },
+    /// Failure because user is not logged in, tokens cannot create tokens
+    NotLoggedIn,
+    /// The token must be given a name
+    InvalidName,
+    /// The user does not have the given role
+    UnknownRole(String),
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for CreateApiTokenResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateApiTokenResponse::Success { .. } => write!(f, "Ok"),
+            CreateApiTokenResponse::NotLoggedIn => write!(f, "Not logged in"),
+            CreateApiTokenResponse::InvalidName => write!(f, "Tokens must have a name"),
+            CreateApiTokenResponse::UnknownRole(role) => write!(f, "Unknown role {}", role),
+            CreateApiTokenResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokeApiTokenRequest {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RevokeApiTokenResponse {
+    Success,
+    /// Failure because user is not logged in, tokens cannot revoke tokens
+    NotLoggedIn,
+    /// No such token belongs to the user
+    UnknownToken,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for RevokeApiTokenResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RevokeApiTokenResponse::Success => write!(f, "Ok"),
+            RevokeApiTokenResponse::NotLoggedIn => write!(f, "Not logged in"),
+            RevokeApiTokenResponse::UnknownToken => write!(f, "Unknown token"),
+            RevokeApiTokenResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -34,6 +394,149 @@ pub struct RoleData {
     pub bio: String,
 }
 
+/// Whether the calling identity follows a role, and how many followers it has
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FollowStatus {
+    pub following: bool,
+    pub followers: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FollowRequest {
+    /// True to follow the role, false to stop following it
+    pub follow: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FollowResponse {
+    /// The follow was changed, the new status is returned
+    Success(FollowStatus),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The role does not exist
+    UnknownRole,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for FollowResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FollowResponse::Success(_) => write!(f, "Ok"),
+            FollowResponse::NotLoggedIn => write!(f, "Not logged in"),
+            FollowResponse::UnknownRole => write!(f, "Unknown role"),
+            FollowResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A published puzzle from a followed role
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeedEntry {
+    pub uuid: String,
+    pub short_name: String,
+    pub display_name: String,
+    /// UUID of the role which owns the puzzle
+    pub owner: String,
+    pub setter_rating: Rating,
+    /// When the puzzle was published, in seconds since the epoch
+    pub when: u64,
+}
+
+/// Where to notify when a role publishes a puzzle
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookSettings {
+    /// The URL to POST notifications to, must be http or https
+    pub url: String,
+    /// If set, payloads are signed with HMAC-SHA256 using this secret and the
+    /// signature is sent in the `X-Linkdoku-Signature` header as `sha256=<hex>`
+    pub secret: Option<String>,
+}
+
+/// A single attempt to deliver a notification to a webhook
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    /// UUID of the puzzle which was published
+    pub puzzle: String,
+    /// When delivery finished, in seconds since the epoch
+    pub when: u64,
+    /// How many times we tried to POST the payload
+    pub attempts: u32,
+    /// The HTTP status of the final attempt, if we got a response at all
+    pub status: Option<u16>,
+    /// What went wrong, if delivery failed
+    pub error: Option<String>,
+}
+
+impl WebhookDelivery {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The webhook configuration of a role, as shown to its owner
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookStatus {
+    pub url: Option<String>,
+    /// The secret itself is never sent back out
+    pub has_secret: bool,
+    /// Recent deliveries, newest first
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetWebhookRequest {
+    /// The new settings, or None to remove the webhook.  If the settings
+    /// have no secret then the current secret is kept.
+    pub webhook: Option<WebhookSettings>,
+    /// Remove the current secret rather than keeping it
+    #[serde(default)]
+    pub clear_secret: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetWebhookResponse {
+    Success(WebhookStatus),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The role does not exist
+    UnknownRole,
+    /// Only the role's owner may configure its webhook
+    PermissionDenied,
+    /// The webhook URL is not an http or https URL
+    InvalidUrl,
+    /// The webhook URL points somewhere we won't deliver to, such as a
+    /// private network
+    ForbiddenUrl(String),
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for SetWebhookResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetWebhookResponse::Success(_) => write!(f, "Ok"),
+            SetWebhookResponse::NotLoggedIn => write!(f, "Not logged in"),
+            SetWebhookResponse::UnknownRole => write!(f, "Unknown role"),
+            SetWebhookResponse::PermissionDenied => write!(f, "Permission denied"),
+            SetWebhookResponse::InvalidUrl => write!(f, "Webhook URL must be http or https"),
+            SetWebhookResponse::ForbiddenUrl(e) => write!(f, "Webhook URL not allowed: {}", e),
+            SetWebhookResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The body POSTed to a webhook when a role publishes a puzzle
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    /// What happened, currently always `published`
+    pub event: String,
+    pub role: RoleData,
+    pub puzzle: Puzzle,
+    /// Link to the puzzle page on the site
+    pub url: String,
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Visibility {
     #[default]
@@ -51,6 +554,66 @@ pub struct Puzzle {
     pub visibility: Visibility,
     pub visibility_changed: Option<String>,
     pub states: Vec<PuzzleState>,
+    #[serde(default)]
+    pub solves: SolveStats,
+}
+
+/// A puzzle to create, along with options for how to create it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatePuzzleRequest {
+    #[serde(flatten)]
+    pub puzzle: Puzzle,
+    /// Store the solver's solution in the grid, if the grid has a unique
+    /// solution and the setter didn't supply one.  The grid is public once
+    /// the puzzle is, so setters must opt in to this.
+    #[serde(default)]
+    pub store_solution: bool,
+}
+
+/// Solve statistics for a puzzle, as seen by the calling user
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolveStats {
+    /// How many identities have marked the puzzle as solved
+    pub count: usize,
+    /// The fastest recorded solve times, fastest first
+    pub fastest: Vec<SolveTime>,
+    /// Whether the calling user has solved the puzzle
+    pub solved: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolveTime {
+    pub name: String,
+    pub seconds: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarkSolvedRequest {
+    /// Optional solve time in seconds
+    pub solve_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarkSolvedResponse {
+    /// The solve was recorded
+    Success,
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The puzzle does not exist, or the user cannot see it
+    UnknownPuzzle,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for MarkSolvedResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarkSolvedResponse::Success => write!(f, "Ok"),
+            MarkSolvedResponse::NotLoggedIn => write!(f, "Not logged in"),
+            MarkSolvedResponse::UnknownPuzzle => write!(f, "Unknown puzzle"),
+            MarkSolvedResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -111,6 +674,182 @@ impl Rating {
     }
 }
 
+/// Aggregated community opinion of a puzzle
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommunityRatings {
+    /// How many solvers chose each difficulty rating
+    pub difficulty: Vec<(Rating, usize)>,
+    /// How many solvers have given a quality score
+    pub quality_count: usize,
+    /// The sum of all quality scores given
+    pub quality_total: u64,
+    /// The difficulty the calling user chose, if any
+    pub my_difficulty: Option<Rating>,
+    /// The quality score the calling user gave, if any
+    pub my_quality: Option<u8>,
+}
+
+/// The highest quality score a solver may give a puzzle
+pub const MAX_QUALITY: u8 = 5;
+
+impl CommunityRatings {
+    /// Number of solvers who have given a difficulty rating
+    pub fn difficulty_count(&self) -> usize {
+        self.difficulty.iter().map(|(_, n)| n).sum()
+    }
+
+    /// Mean quality score, if any have been given
+    pub fn average_quality(&self) -> Option<f64> {
+        if self.quality_count == 0 {
+            None
+        } else {
+            Some(self.quality_total as f64 / self.quality_count as f64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RatePuzzleRequest {
+    pub difficulty: Option<Rating>,
+    /// Quality score from 1 to [`MAX_QUALITY`]
+    pub quality: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RatePuzzleResponse {
+    /// The rating was stored, the updated aggregate is returned
+    Success(CommunityRatings),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The puzzle does not exist, or the user cannot see it
+    UnknownPuzzle,
+    /// The quality score was out of range
+    InvalidQuality,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for RatePuzzleResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RatePuzzleResponse::Success(_) => write!(f, "Ok"),
+            RatePuzzleResponse::NotLoggedIn => write!(f, "Not logged in"),
+            RatePuzzleResponse::UnknownPuzzle => write!(f, "Unknown puzzle"),
+            RatePuzzleResponse::InvalidQuality => write!(f, "Invalid quality score"),
+            RatePuzzleResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A comment left on a puzzle, possibly a hint or a reply to another comment
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PuzzleComment {
+    /// Identifier of the comment, unique within the puzzle
+    pub id: String,
+    /// Display name of the author
+    pub author: String,
+    /// True if the comment was left by the puzzle's setter
+    pub setter: bool,
+    /// Markdown body of the comment
+    pub body: String,
+    /// Hints are hidden until the reader asks to see them
+    pub hint: bool,
+    /// The comment this is replying to, if any
+    pub reply_to: Option<String>,
+    /// When the comment was posted, in seconds since the epoch
+    pub posted: u64,
+    /// Hidden by moderation, only ever sent to the setter
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PuzzleComments {
+    pub comments: Vec<PuzzleComment>,
+    /// True if the caller may hide or delete comments
+    pub can_moderate: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddCommentRequest {
+    pub body: String,
+    pub hint: bool,
+    pub reply_to: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddCommentResponse {
+    /// The comment was added, its ID is returned
+    Success(String),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The puzzle does not exist, or the user cannot see it
+    UnknownPuzzle,
+    /// The comment being replied to does not exist
+    UnknownComment,
+    /// The comment body was empty or too long
+    InvalidBody,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for AddCommentResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddCommentResponse::Success(id) => write!(f, "Ok({})", id),
+            AddCommentResponse::NotLoggedIn => write!(f, "Not logged in"),
+            AddCommentResponse::UnknownPuzzle => write!(f, "Unknown puzzle"),
+            AddCommentResponse::UnknownComment => write!(f, "Unknown comment"),
+            AddCommentResponse::InvalidBody => write!(f, "Comment is empty or too long"),
+            AddCommentResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The longest comment body we accept, in characters
+pub const MAX_COMMENT_LENGTH: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommentModeration {
+    Hide,
+    Unhide,
+    /// Deleting a comment also deletes any replies to it
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModerateCommentRequest {
+    pub comment: String,
+    pub action: CommentModeration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModerateCommentResponse {
+    Success,
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The puzzle does not exist, or the user cannot see it
+    UnknownPuzzle,
+    /// The comment does not exist
+    UnknownComment,
+    /// Only the puzzle's setter may moderate its comments
+    PermissionDenied,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for ModerateCommentResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModerateCommentResponse::Success => write!(f, "Ok"),
+            ModerateCommentResponse::NotLoggedIn => write!(f, "Not logged in"),
+            ModerateCommentResponse::UnknownPuzzle => write!(f, "Unknown puzzle"),
+            ModerateCommentResponse::UnknownComment => write!(f, "Unknown comment"),
+            ModerateCommentResponse::PermissionDenied => write!(f, "Permission denied"),
+            ModerateCommentResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PuzzleState {
     pub description: String,
@@ -135,10 +874,87 @@ pub struct UrlEntry {
     pub url: String,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetVisibilityRequest {
+    /// The new visibility, applied to the puzzle and all its states
+    pub visibility: Visibility,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetVisibilityResponse {
+    /// Along with how many solutions the grid has, when a grid is published
+    Success(Option<SolutionCount>),
+    /// Failure because user is not logged in
+    NotLoggedIn,
+    /// The puzzle does not exist, or the user cannot see it
+    UnknownPuzzle,
+    /// Only the puzzle's owner may change its visibility
+    PermissionDenied,
+    /// Something went wrong in the database layer
+    DatabaseFailure(String),
+}
+
+impl Display for SetVisibilityResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetVisibilityResponse::Success(_) => write!(f, "Ok"),
+            SetVisibilityResponse::NotLoggedIn => write!(f, "Not logged in"),
+            SetVisibilityResponse::UnknownPuzzle => write!(f, "Unknown puzzle"),
+            SetVisibilityResponse::PermissionDenied => write!(f, "Permission denied"),
+            SetVisibilityResponse::DatabaseFailure(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Enough about a puzzle to link to it from within a pack
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackMember {
+    pub uuid: String,
+    pub short_name: String,
+    pub display_name: String,
+}
+
+/// Where a puzzle sits within a pack which contains it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackPosition {
+    pub pack: PackMember,
+    /// Position of the puzzle within the pack, counting from 1
+    pub position: usize,
+    /// How many puzzles in the pack the caller can see
+    pub count: usize,
+    pub previous: Option<PackMember>,
+    pub next: Option<PackMember>,
+}
+
+/// The result of running the solver over a puzzle's grid
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SolutionCount {
+    /// The grid has no solutions
+    Zero,
+    /// The grid has exactly one solution
+    One,
+    /// The grid has more than one solution
+    Many,
+    /// The solver could not decide, the string says why
+    Unknown(String),
+}
+
+impl Display for SolutionCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolutionCount::Zero => write!(f, "No solutions"),
+            SolutionCount::One => write!(f, "Unique solution"),
+            SolutionCount::Many => write!(f, "Multiple solutions"),
+            SolutionCount::Unknown(why) => write!(f, "Unknown ({})", why),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreatePuzzleResponse {
-    /// Successful create, contained string is puzzle UUID
-    Success(String),
+    /// Successful create, contained string is puzzle UUID, along with the
+    /// solution count if the puzzle state contained a grid
+    Success(String, Option<SolutionCount>),
     /// Failure because user is not logged in
     NotLoggedIn,
     /// Failure because provided puzzle contained a UUID
@@ -149,6 +965,8 @@ pub enum CreatePuzzleResponse {
     InvalidStateVector,
     /// Invalid visibility data provided
     InvalidVisiblityData,
+    /// A pack member does not exist, or is not visible to the creator
+    InvalidPackMember(String),
     /// Something went wrong in the database layer
     DatabaseFailure(String),
 }
@@ -156,13 +974,40 @@ pub enum CreatePuzzleResponse {
 impl Display for CreatePuzzleResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CreatePuzzleResponse::Success(uuid) => write!(f, "Ok({})", uuid),
+            CreatePuzzleResponse::Success(uuid, _) => write!(f, "Ok({})", uuid),
             CreatePuzzleResponse::NotLoggedIn => write!(f, "Not logged in"),
             CreatePuzzleResponse::FailedUUIDSupplied => write!(f, "Unexpected UUID in input"),
             CreatePuzzleResponse::InvalidOwnerRole => write!(f, "Invalid owner role in input"),
             CreatePuzzleResponse::InvalidStateVector => write!(f, "Invalid state vector"),
             CreatePuzzleResponse::InvalidVisiblityData => write!(f, "Invalid visibility data"),
+            CreatePuzzleResponse::InvalidPackMember(member) => {
+                write!(f, "Unknown puzzle in pack: {}", member)
+            }
             CreatePuzzleResponse::DatabaseFailure(e) => write!(f, "{}", e),
         }
     }
 }
+
+/// URL schemes which links and images in user written markdown may use,
+/// wherever that markdown is rendered
+pub const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Whether a URL is relative, or uses one of the given schemes (ignoring case)
+pub fn url_scheme_allowed<S: AsRef<str>>(url: &str, schemes: &[S]) -> bool {
+    // Browsers ignore surrounding spaces and controls, and any tabs or
+    // newlines, when working out a URL's scheme
+    let url: String = url
+        .trim_matches(|c: char| c == ' ' || c.is_ascii_control())
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(pos) if url[pos..].starts_with(':') => {
+            let scheme = &url[..pos];
+            schemes
+                .iter()
+                .any(|allowed| allowed.as_ref().eq_ignore_ascii_case(scheme))
+        }
+        _ => true,
+    }
+}
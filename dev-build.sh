@@ -31,5 +31,7 @@ LINKDOKU_RESOURCES=/build/dist
 export LINKDOKU_RESOURCES
 LINKDOKU_PORT=3000
 export LINKDOKU_PORT
+LINKDOKU_OVERLAY=linkdoku-config-devonly.yaml
+export LINKDOKU_OVERLAY
 
 exec /build/target/debug/linkdoku-backend
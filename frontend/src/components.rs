@@ -1,9 +1,20 @@
 //! Components for Linkdoku
 //!
 
+pub mod activity;
+pub mod comments;
+pub mod community;
 pub mod core;
+pub mod feed;
 pub mod login;
+pub mod logins;
+pub mod pack;
+pub mod player;
+pub mod profile;
 pub mod puzzle;
 pub mod role;
+pub mod solve;
+pub mod tokens;
 pub mod user;
 pub mod utility;
+pub mod webhook;
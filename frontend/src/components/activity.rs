@@ -0,0 +1,97 @@
+//! Activity logs for roles
+//!
+
+use js_sys::Date;
+use linkdoku_common::ActivityEntry;
+use reqwest::Url;
+use wasm_bindgen::JsValue;
+use yew::prelude::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    pack::PuzzleLink,
+    role::Role,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct ActivityPanelProps {
+    /// UUID of the role
+    pub role: String,
+}
+
+/// Recent changes to a role and its puzzles, only shown to the role's members
+#[function_component(ActivityPanel)]
+pub fn activity_panel(props: &ActivityPanelProps) -> Html {
+    let entries = use_state_eq(Vec::<ActivityEntry>::new);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let activity_url = use_api_url(&format!("/role/activity/{}", props.role));
+
+    use_effect_with_deps(
+        {
+            let entries = entries.setter();
+            move |activity_url: &Url| {
+                let activity_url = activity_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Vec<ActivityEntry>, _> =
+                        make_api_call(client, activity_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => entries.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch activity: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        activity_url,
+    );
+
+    if entries.is_empty() {
+        return html! {};
+    }
+
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let when = Date::new(&JsValue::from_f64(entry.when as f64 * 1000.0));
+            let who = match &entry.active_role {
+                Some(role) => html! { <Role uuid={role.clone()} /> },
+                None => html! { <span class={"is-family-monospace"}>{entry.actor.clone()}</span> },
+            };
+            let target =
+                if entry.action.starts_with("puzzle-") || entry.action.starts_with("comment-") {
+                    html! { <PuzzleLink puzzle={entry.target.clone()} /> }
+                } else {
+                    html! { <Role uuid={entry.target.clone()} /> }
+                };
+            html! {
+                <tr>
+                    <td>{String::from(when.to_locale_string("default", &JsValue::UNDEFINED))}</td>
+                    <td>{who}</td>
+                    <td><span class={"tag"}>{entry.action.clone()}</span></td>
+                    <td>{target}</td>
+                    <td>{entry.summary.clone()}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Activity"}</p>
+            <table class={"table is-fullwidth is-narrow"}>
+                <thead>
+                    <tr>
+                        <th>{"When"}</th>
+                        <th>{"By"}</th>
+                        <th>{"Action"}</th>
+                        <th>{"Of"}</th>
+                        <th>{"Changes"}</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+        </div>
+    }
+}
@@ -0,0 +1,362 @@
+//! Comments and hint threads on puzzles
+//!
+
+use js_sys::Date;
+use linkdoku_common::{
+    AddCommentRequest, AddCommentResponse, CommentModeration, ModerateCommentRequest,
+    ModerateCommentResponse, PuzzleComment, PuzzleComments,
+};
+use reqwest::Url;
+use wasm_bindgen::JsValue;
+use web_sys::{HtmlInputElement, HtmlTextAreaElement};
+use yew::prelude::*;
+use yew_markdown::render::MarkdownRender;
+use yew_toastrack::*;
+
+use crate::components::core::{make_api_call, use_api_url, ReqwestClient, NO_BODY};
+
+fn toast_api_error(e: impl std::fmt::Display) {
+    Toaster::toast(
+        Toast::new(&format!("API Error: {}", e))
+            .with_lifetime(Some(2000))
+            .with_level(ToastLevel::Danger),
+    );
+}
+
+fn format_posted(posted: u64) -> String {
+    let date = Date::new(&JsValue::from_f64(posted as f64 * 1000.0));
+    String::from(date.to_locale_string("default", &JsValue::UNDEFINED))
+}
+
+#[derive(Properties, PartialEq)]
+struct CommentFormProps {
+    reply_to: Option<String>,
+    onsubmit: Callback<AddCommentRequest>,
+}
+
+#[function_component(CommentForm)]
+fn comment_form(props: &CommentFormProps) -> Html {
+    let body_ref = use_node_ref();
+    let hint_ref = use_node_ref();
+
+    let onclick = Callback::from({
+        let body_ref = body_ref.clone();
+        let hint_ref = hint_ref.clone();
+        let reply_to = props.reply_to.clone();
+        let onsubmit = props.onsubmit.clone();
+        move |_| {
+            let body: HtmlTextAreaElement = body_ref.cast().unwrap();
+            let hint: HtmlInputElement = hint_ref.cast().unwrap();
+            if body.value().trim().is_empty() {
+                return;
+            }
+            onsubmit.emit(AddCommentRequest {
+                body: body.value(),
+                hint: hint.checked(),
+                reply_to: reply_to.clone(),
+            });
+            body.set_value("");
+            hint.set_checked(false);
+        }
+    });
+
+    let placeholder = if props.reply_to.is_some() {
+        "Write a reply (markdown is supported)"
+    } else {
+        "Leave a comment (markdown is supported)"
+    };
+
+    html! {
+        <div class={"block"}>
+            <div class={"field"}>
+                <div class={"control"}>
+                    <textarea ref={body_ref} class={"textarea"} rows={"3"} placeholder={placeholder} />
+                </div>
+            </div>
+            <div class={"field is-grouped"}>
+                <div class={"control"}>
+                    <label class={"checkbox"}>
+                        <input ref={hint_ref} type={"checkbox"} />
+                        {" This is a hint, hide it until asked"}
+                    </label>
+                </div>
+                <div class={"control"}>
+                    <button class={"button is-primary is-small"} onclick={onclick}>{"Post"}</button>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct CommentBodyProps {
+    body: String,
+    hint: bool,
+}
+
+/// A comment body, hints are spoilered until the reader clicks to show them
+#[function_component(CommentBody)]
+fn comment_body(props: &CommentBodyProps) -> Html {
+    let revealed = use_state_eq(|| false);
+
+    if props.hint && !*revealed {
+        let onclick = {
+            let revealed = revealed.setter();
+            Callback::from(move |_| revealed.set(true))
+        };
+        html! {
+            <button class={"button is-small is-warning is-light"} onclick={onclick}>
+                <span class={"icon"}><i class={"fas fa-solid fa-eye"} /></span>
+                <span>{"Show hint"}</span>
+            </button>
+        }
+    } else {
+        html! {
+            <MarkdownRender markdown={props.body.clone()} />
+        }
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct CommentProps {
+    comment: PuzzleComment,
+    can_moderate: bool,
+    onmoderate: Callback<ModerateCommentRequest>,
+    /// Present if the user may reply to this comment
+    onreply: Option<Callback<AddCommentRequest>>,
+    #[prop_or_default]
+    children: Children,
+}
+
+#[function_component(Comment)]
+fn comment(props: &CommentProps) -> Html {
+    let replying = use_state_eq(|| false);
+    let comment = &props.comment;
+
+    let moderate = |action: CommentModeration| {
+        let id = comment.id.clone();
+        props.onmoderate.reform(move |_| ModerateCommentRequest {
+            comment: id.clone(),
+            action,
+        })
+    };
+
+    let moderation = if props.can_moderate {
+        let (label, action) = if comment.hidden {
+            ("Unhide", CommentModeration::Unhide)
+        } else {
+            ("Hide", CommentModeration::Hide)
+        };
+        html! {
+            <>
+                {" · "}
+                <a onclick={moderate(action)}>{label}</a>
+                {" · "}
+                <a class={"has-text-danger"} onclick={moderate(CommentModeration::Delete)}>{"Delete"}</a>
+            </>
+        }
+    } else {
+        html! {}
+    };
+
+    let (reply_link, reply_form) = match &props.onreply {
+        Some(onreply) => {
+            let toggle = {
+                let replying = replying.clone();
+                Callback::from(move |_| replying.set(!*replying))
+            };
+            let form = if *replying {
+                let onsubmit = {
+                    let replying = replying.setter();
+                    onreply.reform(move |req| {
+                        replying.set(false);
+                        req
+                    })
+                };
+                html! { <CommentForm reply_to={Some(comment.id.clone())} onsubmit={onsubmit} /> }
+            } else {
+                html! {}
+            };
+            (
+                html! { <>{" · "}<a onclick={toggle}>{"Reply"}</a></> },
+                form,
+            )
+        }
+        None => (html! {}, html! {}),
+    };
+
+    let setter_tag = if comment.setter {
+        html! { <span class={"tag is-primary ml-1"}>{"Setter"}</span> }
+    } else {
+        html! {}
+    };
+    let hint_tag = if comment.hint {
+        html! { <span class={"tag is-warning ml-1"}>{"Hint"}</span> }
+    } else {
+        html! {}
+    };
+    let hidden_tag = if comment.hidden {
+        html! { <span class={"tag is-dark ml-1"}>{"Hidden"}</span> }
+    } else {
+        html! {}
+    };
+
+    html! {
+        <article class={"media"}>
+            <div class={"media-content"}>
+                <p>
+                    <strong>{comment.author.clone()}</strong>
+                    {setter_tag}{hint_tag}{hidden_tag}
+                    <br />
+                    <small>{format_posted(comment.posted)}{reply_link}{moderation}</small>
+                </p>
+                <div class={"content"}>
+                    <CommentBody body={comment.body.clone()} hint={comment.hint} />
+                </div>
+                {reply_form}
+                {props.children.clone()}
+            </div>
+        </article>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CommentsPanelProps {
+    /// UUID of the puzzle
+    pub puzzle: String,
+    /// Whether the user may post comments
+    pub can_comment: bool,
+}
+
+#[function_component(CommentsPanel)]
+pub fn comments_panel(props: &CommentsPanelProps) -> Html {
+    let comments = use_state_eq(|| None);
+    // Bumped whenever we change something, so that the comments are refetched
+    let generation = use_state_eq(|| 0u32);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let comments_url = use_api_url(&format!("/puzzle/comments/{}", props.puzzle));
+    let add_url = use_api_url(&format!("/puzzle/comments/{}/add", props.puzzle));
+    let moderate_url = use_api_url(&format!("/puzzle/comments/{}/moderate", props.puzzle));
+
+    use_effect_with_deps(
+        {
+            let comments = comments.setter();
+            let client = client.clone();
+            move |(comments_url, _): &(Url, u32)| {
+                let comments_url = comments_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<PuzzleComments>, _> =
+                        make_api_call(client, comments_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => comments.set(value),
+                        Err(e) => {
+                            gloo::console::log!(format!("Unable to fetch comments: {}", e))
+                        }
+                    }
+                });
+                || ()
+            }
+        },
+        (comments_url, *generation),
+    );
+
+    let add_comment = Callback::from({
+        let client = client.clone();
+        let generation = generation.clone();
+        move |request: AddCommentRequest| {
+            let client = client.clone();
+            let add_url = add_url.clone();
+            let generation = generation.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, add_url.as_str(), None, Some(request)).await {
+                    Ok(AddCommentResponse::Success(_)) => generation.set(*generation + 1),
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to post comment: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => toast_api_error(e),
+                }
+            });
+        }
+    });
+
+    let moderate = Callback::from({
+        let generation = generation.clone();
+        move |request: ModerateCommentRequest| {
+            let client = client.clone();
+            let moderate_url = moderate_url.clone();
+            let generation = generation.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, moderate_url.as_str(), None, Some(request)).await {
+                    Ok(ModerateCommentResponse::Success) => generation.set(*generation + 1),
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to moderate comment: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => toast_api_error(e),
+                }
+            });
+        }
+    });
+
+    let comments: PuzzleComments = match &*comments {
+        Some(comments) => comments.clone(),
+        None => return html! {},
+    };
+
+    let onreply = props.can_comment.then(|| add_comment.clone());
+
+    let threads = comments
+        .comments
+        .iter()
+        .filter(|c| c.reply_to.is_none())
+        .map(|top| {
+            let replies = comments
+                .comments
+                .iter()
+                .filter(|c| c.reply_to.as_ref() == Some(&top.id))
+                .map(|reply| {
+                    html! {
+                        <Comment
+                            key={reply.id.clone()}
+                            comment={reply.clone()}
+                            can_moderate={comments.can_moderate}
+                            onmoderate={moderate.clone()}
+                            onreply={None::<Callback<AddCommentRequest>>}
+                        />
+                    }
+                })
+                .collect::<Html>();
+            html! {
+                <Comment
+                    key={top.id.clone()}
+                    comment={top.clone()}
+                    can_moderate={comments.can_moderate}
+                    onmoderate={moderate.clone()}
+                    onreply={onreply.clone()}
+                >
+                    {replies}
+                </Comment>
+            }
+        })
+        .collect::<Html>();
+
+    let form = if props.can_comment {
+        html! { <CommentForm reply_to={None::<String>} onsubmit={add_comment} /> }
+    } else {
+        html! {}
+    };
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Comments"}</p>
+            if comments.comments.is_empty() {
+                <p class={"block"}>{"Nobody has commented on this puzzle yet"}</p>
+            }
+            {threads}
+            {form}
+        </div>
+    }
+}
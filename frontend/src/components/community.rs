@@ -0,0 +1,176 @@
+//! Community opinion of puzzles, difficulty and quality ratings
+//!
+
+use linkdoku_common::{
+    CommunityRatings, RatePuzzleRequest, RatePuzzleResponse, Rating, MAX_QUALITY,
+};
+use reqwest::Url;
+use yew::prelude::*;
+use yew_toastrack::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    puzzle::PuzzleRating,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct CommunityRatingsPanelProps {
+    /// UUID of the puzzle
+    pub puzzle: String,
+    /// The rating the setter chose for the puzzle
+    pub setter_rating: Option<Rating>,
+    /// Whether the user may submit their own ratings
+    pub can_rate: bool,
+}
+
+#[function_component(CommunityRatingsPanel)]
+pub fn community_ratings_panel(props: &CommunityRatingsPanelProps) -> Html {
+    let ratings = use_state_eq(|| None);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let ratings_url = use_api_url(&format!("/puzzle/ratings/{}", props.puzzle));
+    let rate_url = use_api_url(&format!("/puzzle/rate/{}", props.puzzle));
+
+    use_effect_with_deps(
+        {
+            let ratings = ratings.setter();
+            let client = client.clone();
+            move |ratings_url: &Url| {
+                let ratings_url = ratings_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<CommunityRatings>, _> =
+                        make_api_call(client, ratings_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => ratings.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch ratings: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        ratings_url,
+    );
+
+    let submit = Callback::from({
+        let ratings = ratings.setter();
+        move |request: RatePuzzleRequest| {
+            let client = client.clone();
+            let rate_url = rate_url.clone();
+            let ratings = ratings.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, rate_url.as_str(), None, Some(request)).await {
+                    Ok(RatePuzzleResponse::Success(value)) => ratings.set(Some(value)),
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to rate puzzle: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => Toaster::toast(
+                        Toast::new(&format!("API Error: {}", e))
+                            .with_lifetime(Some(2000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                }
+            });
+        }
+    });
+
+    let ratings: CommunityRatings = match &*ratings {
+        Some(ratings) => ratings.clone(),
+        None => return html! {},
+    };
+
+    let total = ratings.difficulty_count();
+
+    let distribution = ratings
+        .difficulty
+        .iter()
+        .map(|&(rating, count)| {
+            let setter_tag = if props.setter_rating == Some(rating) {
+                html! { <span class={"tag is-primary ml-2"}>{"Setter"}</span> }
+            } else {
+                html! {}
+            };
+            let mine_tag = if ratings.my_difficulty == Some(rating) {
+                html! { <span class={"tag is-info ml-2"}>{"You"}</span> }
+            } else {
+                html! {}
+            };
+            html! {
+                <tr>
+                    <td>{rating.title()}{setter_tag}{mine_tag}</td>
+                    <td style={"width: 40%;"}>
+                        <progress class={"progress is-info"} value={count.to_string()} max={total.max(1).to_string()} />
+                    </td>
+                    <td class={"has-text-right"}>{count.to_string()}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    let quality_summary = match ratings.average_quality() {
+        Some(avg) => format!(
+            "Quality: {:.1} / {} from {} {}",
+            avg,
+            MAX_QUALITY,
+            ratings.quality_count,
+            if ratings.quality_count == 1 {
+                "rating"
+            } else {
+                "ratings"
+            }
+        ),
+        None => "Nobody has rated the quality of this puzzle yet".to_string(),
+    };
+
+    let rate_controls = if props.can_rate {
+        let difficulty_click = submit.reform(|rating| RatePuzzleRequest {
+            difficulty: Some(rating),
+            quality: None,
+        });
+        let quality_stars = (1..=MAX_QUALITY)
+            .map(|score| {
+                let onclick = submit.reform(move |_| RatePuzzleRequest {
+                    difficulty: None,
+                    quality: Some(score),
+                });
+                let class = if ratings.my_quality.map(|q| q >= score).unwrap_or(false) {
+                    "icon is-clickable has-text-warning"
+                } else {
+                    "icon is-clickable"
+                };
+                html! {
+                    <span class={class} onclick={onclick}>
+                        <i class={"fas fa-solid fa-heart"} />
+                    </span>
+                }
+            })
+            .collect::<Html>();
+        html! {
+            <div class={"columns"}>
+                <div class={"column"}>
+                    <label class={"label"}>{"Your difficulty rating"}</label>
+                    <PuzzleRating value={ratings.my_difficulty.unwrap_or_default()} onclick={difficulty_click} />
+                </div>
+                <div class={"column"}>
+                    <label class={"label"}>{"Your quality rating"}</label>
+                    <div class={"button"}>{quality_stars}</div>
+                </div>
+            </div>
+        }
+    } else {
+        html! {}
+    };
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{format!("Community difficulty ({} {})", total, if total == 1 { "vote" } else { "votes" })}</p>
+            <table class={"table is-fullwidth is-narrow"}>
+                <tbody>
+                    {distribution}
+                </tbody>
+            </table>
+            <p class={"block"}>{quality_summary}</p>
+            {rate_controls}
+        </div>
+    }
+}
@@ -177,6 +177,22 @@ impl From<reqwest::Error> for APIError {
     }
 }
 
+/// The header in which the backend expects our CSRF token
+const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// The CSRF token the backend gave us in its `csrf` cookie, which must
+/// accompany any API call which might change something
+fn csrf_token() -> Option<String> {
+    let document: web_sys::HtmlDocument = gloo::utils::document().dyn_into().ok()?;
+    document
+        .cookie()
+        .ok()?
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == "csrf")
+        .map(|(_, value)| value.to_string())
+}
+
 /// Make an async API call.
 ///
 /// You *must* have acquired the API Url already
@@ -194,7 +210,11 @@ where
 {
     let url = Url::parse_with_params(api, query_params)?;
     let request = if let Some(body) = body {
-        client.client.post(url).json(&body).build()?
+        let request = client.client.post(url).json(&body);
+        match csrf_token() {
+            Some(token) => request.header(CSRF_HEADER, token).build()?,
+            None => request.build()?,
+        }
     } else {
         client.client.get(url).build()?
     };
@@ -0,0 +1,87 @@
+//! Feed of newly published puzzles from followed roles
+//!
+
+use js_sys::Date;
+use linkdoku_common::FeedEntry;
+use wasm_bindgen::JsValue;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::{
+    components::{
+        core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+        puzzle::PuzzleRating,
+        role::Role,
+    },
+    Route,
+};
+
+#[function_component(PuzzleFeed)]
+pub fn puzzle_feed() -> Html {
+    let feed = use_state_eq(|| None);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let feed_url = use_api_url("/role/feed");
+
+    use_effect_with_deps(
+        {
+            let feed = feed.setter();
+            move |feed_url: &reqwest::Url| {
+                let feed_url = feed_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<Vec<FeedEntry>>, _> =
+                        make_api_call(client, feed_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => feed.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch feed: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        feed_url,
+    );
+
+    let entries: Vec<FeedEntry> = match &*feed {
+        Some(entries) => entries.clone(),
+        None => return html! {},
+    };
+
+    if entries.is_empty() {
+        return html! {
+            <div class={"box"}>
+                <p class={"subtitle"}>{"Your feed"}</p>
+                <p>{"Nothing here yet. Follow some setters from their role pages to see their newly published puzzles."}</p>
+            </div>
+        };
+    }
+
+    let rows = entries
+        .into_iter()
+        .map(|entry| {
+            let when = Date::new(&JsValue::from_f64(entry.when as f64 * 1000.0));
+            html! {
+                <tr key={entry.uuid.clone()}>
+                    <td>
+                        <Link<Route> to={Route::PuzzlePage { puzzle: entry.short_name.clone() }}>
+                            {entry.display_name.clone()}
+                        </Link<Route>>
+                    </td>
+                    <td><PuzzleRating value={entry.setter_rating} /></td>
+                    <td><Role uuid={entry.owner.clone()} /></td>
+                    <td>{String::from(when.to_locale_date_string("default", &JsValue::UNDEFINED))}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Your feed"}</p>
+            <table class={"table is-fullwidth is-hoverable"}>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+        </div>
+    }
+}
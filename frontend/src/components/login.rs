@@ -3,7 +3,12 @@
 use std::rc::Rc;
 
 use linkdoku_common::BackendLoginStatus;
+use linkdoku_common::DevLogin;
 use linkdoku_common::LoginFlowStart;
+use linkdoku_common::LoginProvider;
+use reqwest::Url;
+use serde::Deserialize;
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 use yew::Reducible;
 use yew_router::prelude::*;
@@ -12,6 +17,7 @@ use crate::Route;
 
 use super::core::make_api_call;
 use super::core::use_api_url;
+use super::core::use_page_url;
 use super::core::ReqwestClient;
 use super::core::NO_BODY;
 
@@ -151,19 +157,45 @@ pub fn login_user_provider(props: &UserProviderProps) -> Html {
     }
 }
 
+/// A login button for each login provider the backend offers
 #[function_component(LoginButton)]
 pub fn login_button() -> Html {
     let history = use_history().unwrap();
     let client = use_context::<ReqwestClient>().expect("No API client");
-    let start_google = use_api_url("/login/start/google");
-    let login_click = Callback::from(move |_| {
+    let providers = use_state_eq(Vec::<LoginProvider>::new);
+    let providers_url = use_api_url("/login/providers");
+    let start_url = use_api_url("/login/start/");
+
+    use_effect_with_deps(
+        {
+            let providers = providers.setter();
+            let client = client.clone();
+            move |providers_url: &Url| {
+                let providers_url = providers_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Vec<LoginProvider>, _> =
+                        make_api_call(client, providers_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => providers.set(value),
+                        Err(e) => {
+                            gloo::console::log!(format!("Unable to fetch login providers: {}", e))
+                        }
+                    }
+                });
+                || ()
+            }
+        },
+        providers_url,
+    );
+
+    let login_click = Callback::from(move |provider: String| {
         // User clicked login, so we need to redirect the user to the login flow
         // startup
         let history = history.clone();
-        let start_google = start_google.clone();
+        let start_url = start_url.join(&provider).expect("Bad provider name");
         let client = client.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            let res: LoginFlowStart = make_api_call(client, start_google.as_str(), None, NO_BODY)
+            let res: LoginFlowStart = make_api_call(client, start_url.as_str(), None, NO_BODY)
                 .await
                 .expect("Unable to start login");
             match res {
@@ -179,11 +211,27 @@ pub fn login_button() -> Html {
         });
     });
 
-    html! {
-        <button class={"button is-primary"} onclick={login_click}>
-            {"Login with Google"}
-        </button>
-    }
+    providers
+        .iter()
+        .map(|provider| {
+            let onclick = {
+                let name = provider.name.clone();
+                login_click.reform(move |_| name.clone())
+            };
+            let icon = match &provider.icon {
+                Some(icon) => html! {
+                    <span class={"icon"}><i class={classes!(icon.clone())} /></span>
+                },
+                None => html! {},
+            };
+            html! {
+                <button class={"button is-primary"} key={provider.name.clone()} onclick={onclick}>
+                    {icon}
+                    <span>{format!("Login with {}", provider.label)}</span>
+                </button>
+            }
+        })
+        .collect::<Html>()
 }
 
 #[function_component(LogoutButton)]
@@ -191,13 +239,20 @@ pub fn logout_button() -> Html {
     let login_status_dispatch =
         use_context::<LoginStatusDispatcher>().expect("Cannot get login status dispatcher");
     let history = use_history().unwrap();
+    let client = use_context::<ReqwestClient>().expect("No API client");
     let clear_login = use_api_url("/login/clear");
     let logout_click = Callback::from(move |_| {
         let history = history.clone();
         let login_status_dispatch = login_status_dispatch.clone();
+        let client = client.clone();
         let clear_login = clear_login.clone();
         wasm_bindgen_futures::spawn_local(async move {
-            reqwest::get(clear_login).await.unwrap();
+            let result: Result<(), _> =
+                make_api_call(client, clear_login.as_str(), None, Some(())).await;
+            if let Err(e) = result {
+                gloo::console::log!(format!("Unable to log out: {}", e));
+                return;
+            }
             history.push(Route::Root);
             login_status_dispatch.dispatch(LoginStatusAction::LoggedOut);
         });
@@ -208,3 +263,120 @@ pub fn logout_button() -> Html {
         </button>
     }
 }
+
+#[derive(Deserialize)]
+struct DevLoginQuery {
+    state: String,
+}
+
+/// Identities offered for quick development logins
+const DEV_PERSONAS: &[(&str, &str)] = &[
+    ("alice", "Alice Setter"),
+    ("bob", "Bob Solver"),
+    ("carol", "Carol Tester"),
+];
+
+/// The development login provider's page, where you say who you'd like to be
+///
+/// The backend only sends users here when the `dev` provider is enabled, and
+/// we send them on to complete the login as any real provider would.
+#[function_component(DevLoginPage)]
+pub fn dev_login_page() -> Html {
+    let location = use_location().expect("Not able to get router location");
+    let complete_url = use_page_url(Route::CompleteLogin);
+    let subject_ref = use_node_ref();
+    let name_ref = use_node_ref();
+    let email_ref = use_node_ref();
+
+    let state = match location.query::<DevLoginQuery>() {
+        Ok(query) => query.state,
+        Err(_) => {
+            return html! {
+                <div>{"There is no development login in progress"}</div>
+            }
+        }
+    };
+
+    let login = Callback::from(move |login: DevLogin| {
+        let mut url = complete_url.clone();
+        url.query_pairs_mut()
+            .append_pair("state", &state)
+            .append_pair(
+                "code",
+                &serde_json::to_string(&login).expect("Unable to encode login"),
+            );
+        gloo::utils::window()
+            .location()
+            .set_href(url.as_str())
+            .unwrap();
+    });
+
+    let personas = DEV_PERSONAS
+        .iter()
+        .map(|(subject, name)| {
+            let onclick = login.reform(move |_| DevLogin {
+                subject: subject.to_string(),
+                display_name: Some(name.to_string()),
+                email: None,
+            });
+            html! {
+                <button class={"button"} key={*subject} onclick={onclick}>{*name}</button>
+            }
+        })
+        .collect::<Html>();
+
+    let submit = login.reform({
+        let subject_ref = subject_ref.clone();
+        let name_ref = name_ref.clone();
+        let email_ref = email_ref.clone();
+        move |_| {
+            let value = |node: &NodeRef| {
+                let value = node.cast::<HtmlInputElement>().unwrap().value();
+                (!value.trim().is_empty()).then(|| value)
+            };
+            DevLogin {
+                subject: value(&subject_ref).unwrap_or_default(),
+                display_name: value(&name_ref),
+                email: value(&email_ref),
+            }
+        }
+    });
+
+    html! {
+        <>
+            <h1 class={"title is-1"}>{"Development login"}</h1>
+            <div class={"notification is-warning"}>
+                {"This site lets anyone log in as anyone.  It must only be used for development and testing."}
+            </div>
+            <div class={"box"}>
+                <p class={"subtitle"}>{"Log in as someone we made earlier"}</p>
+                <div class={"buttons"}>
+                    {personas}
+                </div>
+            </div>
+            <div class={"box"}>
+                <p class={"subtitle"}>{"Log in as someone else"}</p>
+                <div class={"field"}>
+                    <label class={"label"}>{"Subject"}</label>
+                    <div class={"control"}>
+                        <input ref={subject_ref} class={"input"} type={"text"} placeholder={"Anything which identifies you, e.g. dave"} />
+                    </div>
+                </div>
+                <div class={"field"}>
+                    <label class={"label"}>{"Display name"}</label>
+                    <div class={"control"}>
+                        <input ref={name_ref} class={"input"} type={"text"} />
+                    </div>
+                </div>
+                <div class={"field"}>
+                    <label class={"label"}>{"Email"}</label>
+                    <div class={"control"}>
+                        <input ref={email_ref} class={"input"} type={"email"} />
+                    </div>
+                    <p class={"help"}>{"Optional, only used for the gravatar"}</p>
+                </div>
+                <button class={"button is-primary"} onclick={submit}>{"Log in"}</button>
+            </div>
+        </>
+    }
+}
@@ -0,0 +1,197 @@
+//! Logins linked to the user's identity
+//!
+
+use linkdoku_common::{
+    LinkedLogin, LoginFlowStart, LoginProvider, UnlinkLoginRequest, UnlinkLoginResponse,
+};
+use reqwest::Url;
+use yew::prelude::*;
+use yew_hooks::use_title;
+use yew_toastrack::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    login::LoginStatus,
+};
+
+fn toast_failure(what: &str, e: impl std::fmt::Display) {
+    Toaster::toast(
+        Toast::new(&format!("{}: {}", what, e))
+            .with_lifetime(Some(5000))
+            .with_level(ToastLevel::Danger),
+    );
+}
+
+#[function_component(LinkedLoginsPage)]
+pub fn linked_logins_page() -> Html {
+    use_title("Linkdoku - Linked logins".to_string());
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let linked = use_state_eq(|| None);
+    let providers = use_state_eq(Vec::<LoginProvider>::new);
+    let generation = use_state_eq(|| 0u32);
+    let linked_url = use_api_url("/login/linked");
+    let providers_url = use_api_url("/login/providers");
+    let link_url = use_api_url("/login/link/");
+    let unlink_url = use_api_url("/login/unlink");
+
+    use_effect_with_deps(
+        {
+            let linked = linked.setter();
+            let client = client.clone();
+            move |(linked_url, _): &(Url, u32)| {
+                let linked_url = linked_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<Vec<LinkedLogin>>, _> =
+                        make_api_call(client, linked_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => linked.set(value),
+                        Err(e) => {
+                            gloo::console::log!(format!("Unable to fetch linked logins: {}", e))
+                        }
+                    }
+                });
+                || ()
+            }
+        },
+        (linked_url, *generation),
+    );
+
+    use_effect_with_deps(
+        {
+            let providers = providers.setter();
+            let client = client.clone();
+            move |providers_url: &Url| {
+                let providers_url = providers_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Vec<LoginProvider>, _> =
+                        make_api_call(client, providers_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => providers.set(value),
+                        Err(e) => {
+                            gloo::console::log!(format!("Unable to fetch login providers: {}", e))
+                        }
+                    }
+                });
+                || ()
+            }
+        },
+        providers_url,
+    );
+
+    let link = Callback::from({
+        let client = client.clone();
+        move |provider: String| {
+            let client = client.clone();
+            let link_url = link_url.join(&provider).expect("Bad provider name");
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, link_url.as_str(), None, NO_BODY).await {
+                    Ok(LoginFlowStart::Redirect(url)) => {
+                        gloo::utils::window().location().set_href(&url).unwrap();
+                    }
+                    Ok(LoginFlowStart::Idle) => {}
+                    Ok(LoginFlowStart::Error(e)) => toast_failure("Unable to link login", e),
+                    Err(e) => toast_failure("API Error", e),
+                }
+            });
+        }
+    });
+
+    let unlink = Callback::from({
+        let generation = generation.clone();
+        move |subject: String| {
+            let client = client.clone();
+            let unlink_url = unlink_url.clone();
+            let generation = generation.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(
+                    client,
+                    unlink_url.as_str(),
+                    None,
+                    Some(UnlinkLoginRequest { subject }),
+                )
+                .await
+                {
+                    Ok(UnlinkLoginResponse::Success) => generation.set(*generation + 1),
+                    Ok(res) => toast_failure("Unable to unlink login", res),
+                    Err(e) => toast_failure("API Error", e),
+                }
+            });
+        }
+    });
+
+    match login_status {
+        LoginStatus::LoggedIn { .. } => {}
+        LoginStatus::Unknown => return html! {},
+        LoginStatus::LoggedOut => {
+            return html! {
+                <div>{"You must be logged in to manage your logins"}</div>
+            }
+        }
+    }
+
+    let linked: Vec<LinkedLogin> = match &*linked {
+        Some(linked) => linked.clone(),
+        None => return html! {},
+    };
+
+    let rows = linked
+        .iter()
+        .map(|login| {
+            let onclick = {
+                let subject = login.subject.clone();
+                unlink.reform(move |_| subject.clone())
+            };
+            html! {
+                <tr key={login.subject.clone()}>
+                    <td>{login.label.clone()}</td>
+                    <td class={"has-text-right"}>
+                        <button class={"button is-small is-danger"} disabled={linked.len() < 2} onclick={onclick}>{"Unlink"}</button>
+                    </td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    let link_buttons = providers
+        .iter()
+        .map(|provider| {
+            let onclick = {
+                let name = provider.name.clone();
+                link.reform(move |_| name.clone())
+            };
+            let icon = match &provider.icon {
+                Some(icon) => html! {
+                    <span class={"icon"}><i class={classes!(icon.clone())} /></span>
+                },
+                None => html! {},
+            };
+            html! {
+                <button class={"button"} key={provider.name.clone()} onclick={onclick}>
+                    {icon}
+                    <span>{format!("Link {}", provider.label)}</span>
+                </button>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <>
+            <h1 class={"title is-1"}>{"Linked logins"}</h1>
+            <p class={"block"}>
+                {"You can log in as yourself with any of these.  You can't unlink your last login, or you'd have no way back in."}
+            </p>
+            <table class={"table is-fullwidth"}>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+            <div class={"box"}>
+                <p class={"subtitle"}>{"Link another login"}</p>
+                <div class={"buttons"}>
+                    {link_buttons}
+                </div>
+            </div>
+        </>
+    }
+}
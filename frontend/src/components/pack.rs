@@ -0,0 +1,266 @@
+//! Puzzle packs, collections of puzzles which link to one another
+//!
+
+use linkdoku_common::PackPosition;
+use reqwest::Url;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use crate::{
+    components::{
+        core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+        puzzle::PuzzleRating,
+    },
+    utils::cache::ObjectCache,
+    Route,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct PuzzleLinkProps {
+    /// UUID or short name of the puzzle to link to
+    pub puzzle: String,
+    /// If provided, used as the link text instead of the puzzle's name
+    #[prop_or_default]
+    pub children: Children,
+}
+
+/// A link to a puzzle, showing its display name and setter rating
+#[function_component(PuzzleLink)]
+pub fn puzzle_link(props: &PuzzleLinkProps) -> Html {
+    let cache = use_context::<ObjectCache>().expect("No cache?");
+    let puzzle_data = cache.cached_puzzle(&props.puzzle);
+
+    if puzzle_data.is_pending() {
+        return html! {
+            <span class={"has-text-grey"}>{"…"}</span>
+        };
+    }
+
+    let puzzle = match puzzle_data.value() {
+        Some(puzzle) => puzzle,
+        None => {
+            return html! {
+                <em class={"has-text-grey"}>{"Puzzle unavailable"}</em>
+            }
+        }
+    };
+
+    let content = if props.children.is_empty() {
+        html! {{puzzle.display_name.clone()}}
+    } else {
+        html! {{props.children.clone()}}
+    };
+
+    let rating = match puzzle.states.last() {
+        Some(state) => html! {
+            <span class={"ml-2 is-size-7"}>
+                <PuzzleRating value={state.setter_rating} />
+            </span>
+        },
+        None => html! {},
+    };
+
+    html! {
+        <span>
+            <Link<Route> to={Route::PuzzlePage { puzzle: puzzle.short_name.clone() }}>
+                {content}
+            </Link<Route>>
+            {rating}
+        </span>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct PackContentsProps {
+    pub members: Vec<String>,
+}
+
+/// The full list of puzzles in a pack
+#[function_component(PackContents)]
+pub fn pack_contents(props: &PackContentsProps) -> Html {
+    if props.members.is_empty() {
+        return html! {};
+    }
+
+    let members = props
+        .members
+        .iter()
+        .map(|member| {
+            html! {
+                <li key={member.clone()}><PuzzleLink puzzle={member.clone()} /></li>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Puzzles in this pack"}</p>
+            <div class={"content"}>
+                <ol>
+                    {members}
+                </ol>
+            </div>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct PackNavigationProps {
+    /// UUID of the puzzle being shown
+    pub puzzle: String,
+}
+
+/// Previous/next navigation for each pack a puzzle is part of
+#[function_component(PackNavigation)]
+pub fn pack_navigation(props: &PackNavigationProps) -> Html {
+    let packs = use_state_eq(Vec::new);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let packs_url = use_api_url(&format!("/puzzle/packs/{}", props.puzzle));
+
+    use_effect_with_deps(
+        {
+            let packs = packs.setter();
+            move |packs_url: &Url| {
+                let packs_url = packs_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Vec<PackPosition>, _> =
+                        make_api_call(client, packs_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => packs.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch packs: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        packs_url,
+    );
+
+    packs
+        .iter()
+        .map(|pos| {
+            let previous = match &pos.previous {
+                Some(prev) => html! {
+                    <Link<Route> to={Route::PuzzlePage { puzzle: prev.short_name.clone() }} classes={"pagination-previous"}>
+                        {format!("← {}", prev.display_name)}
+                    </Link<Route>>
+                },
+                None => html! {
+                    <a class={"pagination-previous is-disabled"}>{"← Previous"}</a>
+                },
+            };
+            let next = match &pos.next {
+                Some(next) => html! {
+                    <Link<Route> to={Route::PuzzlePage { puzzle: next.short_name.clone() }} classes={"pagination-next"}>
+                        {format!("{} →", next.display_name)}
+                    </Link<Route>>
+                },
+                None => html! {
+                    <a class={"pagination-next is-disabled"}>{"Next →"}</a>
+                },
+            };
+            html! {
+                <nav class={"pagination is-centered block"} key={pos.pack.uuid.clone()}>
+                    {previous}
+                    {next}
+                    <ul class={"pagination-list"}>
+                        <li>
+                            <Link<Route> to={Route::PuzzlePage { puzzle: pos.pack.short_name.clone() }}>
+                                {format!("{} ({} of {})", pos.pack.display_name, pos.position, pos.count)}
+                            </Link<Route>>
+                        </li>
+                    </ul>
+                </nav>
+            }
+        })
+        .collect::<Html>()
+}
+
+#[derive(Properties, PartialEq)]
+pub struct PackEditorProps {
+    pub members: Vec<String>,
+    pub onchange: Callback<Vec<String>>,
+}
+
+/// Editor for the list of puzzles in a pack
+#[function_component(PackEditor)]
+pub fn pack_editor(props: &PackEditorProps) -> Html {
+    let input_ref = use_node_ref();
+
+    let add_member = Callback::from({
+        let input_ref = input_ref.clone();
+        let members = props.members.clone();
+        let onchange = props.onchange.clone();
+        move |_| {
+            let input: HtmlInputElement = input_ref.cast().unwrap();
+            let value = input.value().trim().to_string();
+            if value.is_empty() {
+                return;
+            }
+            let mut members = members.clone();
+            members.push(value);
+            onchange.emit(members);
+            input.set_value("");
+        }
+    });
+
+    let rows = props
+        .members
+        .iter()
+        .enumerate()
+        .map(|(idx, member)| {
+            let change = |f: fn(&mut Vec<String>, usize)| {
+                let members = props.members.clone();
+                props.onchange.reform(move |_| {
+                    let mut members = members.clone();
+                    f(&mut members, idx);
+                    members
+                })
+            };
+            let move_up = change(|m, idx| m.swap(idx, idx - 1));
+            let move_down = change(|m, idx| m.swap(idx, idx + 1));
+            let remove = change(|m, idx| {
+                m.remove(idx);
+            });
+            html! {
+                <tr>
+                    <td>{(idx + 1).to_string()}</td>
+                    <td><PuzzleLink puzzle={member.clone()} /></td>
+                    <td class={"has-text-right"}>
+                        <div class={"buttons are-small is-right"}>
+                            <button class={"button"} onclick={move_up} disabled={idx == 0}>
+                                <span class={"icon"}><i class={"fas fa-solid fa-arrow-up"} /></span>
+                            </button>
+                            <button class={"button"} onclick={move_down} disabled={idx + 1 == props.members.len()}>
+                                <span class={"icon"}><i class={"fas fa-solid fa-arrow-down"} /></span>
+                            </button>
+                            <button class={"button is-danger"} onclick={remove}>
+                                <span class={"icon"}><i class={"fas fa-solid fa-trash"} /></span>
+                            </button>
+                        </div>
+                    </td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <>
+            <table class={"table is-fullwidth"}>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+            <div class={"field has-addons"}>
+                <div class={"control is-expanded"}>
+                    <input ref={input_ref} class={"input"} type={"text"} placeholder={"Puzzle short name or UUID"} />
+                </div>
+                <div class={"control"}>
+                    <button class={"button is-primary"} onclick={add_member}>{"Add puzzle"}</button>
+                </div>
+            </div>
+            <p class={"help"}>{"Refer to puzzles in the description with [puzzle-1], [puzzle-2] and so on."}</p>
+        </>
+    }
+}
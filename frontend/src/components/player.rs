@@ -0,0 +1,605 @@
+//! In-browser puzzle player for F-Puzzles style grids
+//!
+//! This is deliberately a simple player: it supports entering digits,
+//! centre pencil marks, cell colouring, undo/redo, and checking against
+//! the solution if the setter embedded one in the puzzle data.
+
+use std::{ops::RangeInclusive, rc::Rc};
+
+use js_sys::Date;
+use serde_json::Value;
+use stylist::yew::{styled_component, use_style};
+use yew::prelude::*;
+
+/// Size of a cell in SVG units
+const CELL_SIZE: usize = 60;
+
+/// Grid sizes we can play, the same as the solver supports.  Pencil marks
+/// are kept as bits so digits must stay well within a `u32`.
+const PLAYABLE_SIZES: RangeInclusive<usize> = 1..=16;
+
+/// How long after typing a `1` a second digit makes it a two digit number,
+/// in milliseconds, for grids bigger than 9x9
+const TYPING_WINDOW: f64 = 1000.0;
+
+/// Colours available in colouring mode, these are the usual pastel
+/// colours one sees in most sudoku apps
+const PALETTE: &[&str] = &[
+    "#d6d6d6", "#ffa0a0", "#ffdf61", "#b0ffb0", "#60c0ff", "#f0b0ff", "#ffb060", "#a0ffff",
+    "#c0a0ff",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerCell {
+    given: Option<u8>,
+    value: Option<u8>,
+    pencil: u32,
+    colour: Option<u8>,
+}
+
+impl PlayerCell {
+    fn digit(&self) -> Option<u8> {
+        self.given.or(self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryMode {
+    Normal,
+    Pencil,
+    Colour,
+}
+
+impl EntryMode {
+    fn title(self) -> &'static str {
+        match self {
+            EntryMode::Normal => "Normal",
+            EntryMode::Pencil => "Pencil",
+            EntryMode::Colour => "Colour",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckResult {
+    Solved,
+    Incorrect(usize),
+    Incomplete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    size: usize,
+    regions: Vec<usize>,
+    solution: Option<Vec<u8>>,
+    cells: Vec<PlayerCell>,
+    undo: Vec<Vec<PlayerCell>>,
+    redo: Vec<Vec<PlayerCell>>,
+    selected: Option<usize>,
+    mode: EntryMode,
+    checked: Option<CheckResult>,
+    started: f64,
+    /// The last digit typed, which may be the first of two: the cell, the
+    /// digit, when it was typed, and how much undo history there was before
+    typed: Option<(usize, u8, f64, usize)>,
+}
+
+pub enum PlayerAction {
+    Select(usize),
+    Move(isize, isize),
+    Enter(u8),
+    /// A digit typed on the keyboard, which may follow another to make 10 and up
+    Type(u8),
+    Clear,
+    SetMode(EntryMode),
+    Undo,
+    Redo,
+    Check,
+    Restart,
+}
+
+/// Compute the default (box) region for a cell in a grid of the given size
+///
+/// Boxes are as tall as the largest divisor of the size which does not
+/// exceed its square root, which matches what F-Puzzles does for 6x6, 8x8 etc.
+pub fn default_region(size: usize, row: usize, col: usize) -> usize {
+    let height = (1..=size)
+        .take_while(|n| n * n <= size)
+        .filter(|n| size % n == 0)
+        .last()
+        .unwrap_or(1);
+    let width = size / height;
+    (row / height) * (size / width) + (col / width)
+}
+
+fn digit_of(value: &Value) -> Option<u8> {
+    match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u8::try_from(n).ok()),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .filter(|&n| n > 0)
+}
+
+impl PlayerState {
+    /// Construct a player state from F-Puzzles data, if it is a size we can play
+    pub fn from_fpuzzles(data: &Value) -> Option<Self> {
+        let size = match data.get("size") {
+            None => 9,
+            Some(size) => size.as_u64().and_then(|n| usize::try_from(n).ok())?,
+        };
+        if !PLAYABLE_SIZES.contains(&size) {
+            return None;
+        }
+        let mut cells = vec![PlayerCell::default(); size * size];
+        let mut regions = Vec::with_capacity(size * size);
+        for row in 0..size {
+            for col in 0..size {
+                let cell = data
+                    .get("grid")
+                    .and_then(|g| g.get(row))
+                    .and_then(|r| r.get(col));
+                let region = cell
+                    .and_then(|c| c.get("region"))
+                    .and_then(Value::as_u64)
+                    .map(|n| n as usize)
+                    .unwrap_or_else(|| default_region(size, row, col));
+                regions.push(region);
+                if let Some(cell) = cell {
+                    let given = cell.get("given").and_then(Value::as_bool).unwrap_or(false);
+                    if given {
+                        cells[row * size + col].given = cell
+                            .get("value")
+                            .and_then(digit_of)
+                            .filter(|&d| usize::from(d) <= size);
+                    }
+                }
+            }
+        }
+        let solution = data
+            .get("solution")
+            .and_then(Value::as_array)
+            .filter(|s| s.len() == size * size)
+            .and_then(|s| s.iter().map(digit_of).collect::<Option<Vec<_>>>());
+        Some(Self {
+            size,
+            regions,
+            solution,
+            cells,
+            undo: Vec::new(),
+            redo: Vec::new(),
+            selected: None,
+            mode: EntryMode::Normal,
+            checked: None,
+            started: Date::now(),
+            typed: None,
+        })
+    }
+
+    pub fn has_solution(&self) -> bool {
+        self.solution.is_some()
+    }
+
+    fn check(&self) -> CheckResult {
+        let solution = match &self.solution {
+            Some(s) => s,
+            None => return CheckResult::Incomplete,
+        };
+        let mut wrong = 0;
+        let mut empty = 0;
+        for (cell, want) in self.cells.iter().zip(solution.iter()) {
+            match cell.digit() {
+                None => empty += 1,
+                Some(d) if d != *want => wrong += 1,
+                Some(_) => {}
+            }
+        }
+        match (wrong, empty) {
+            (0, 0) => CheckResult::Solved,
+            (0, _) => CheckResult::Incomplete,
+            (n, _) => CheckResult::Incorrect(n),
+        }
+    }
+
+    /// Apply a change to the selected cell, recording undo history if it changed
+    fn edit(&mut self, func: impl FnOnce(&mut PlayerCell)) {
+        if let Some(idx) = self.selected {
+            let before = self.cells.clone();
+            func(&mut self.cells[idx]);
+            if before != self.cells {
+                self.undo.push(before);
+                self.redo.clear();
+                self.checked = None;
+            }
+        }
+    }
+}
+
+impl Reducible for PlayerState {
+    type Action = PlayerAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        let mut next = (*self).clone();
+        let typed = next.typed.take();
+        match action {
+            PlayerAction::Select(idx) => next.selected = Some(idx),
+            PlayerAction::Move(drow, dcol) => {
+                let size = next.size as isize;
+                let idx = next.selected.unwrap_or(0) as isize;
+                let row = (idx / size + drow).rem_euclid(size);
+                let col = (idx % size + dcol).rem_euclid(size);
+                next.selected = Some((row * size + col) as usize);
+            }
+            PlayerAction::Enter(digit) => {
+                if digit == 0 || usize::from(digit) > next.size {
+                    return self;
+                }
+                let mode = next.mode;
+                next.edit(|cell| match mode {
+                    EntryMode::Normal if cell.given.is_none() => {
+                        cell.value = if cell.value == Some(digit) {
+                            None
+                        } else {
+                            Some(digit)
+                        };
+                    }
+                    EntryMode::Pencil if cell.digit().is_none() => cell.pencil ^= 1 << digit,
+                    EntryMode::Colour if usize::from(digit) <= PALETTE.len() => {
+                        cell.colour = if cell.colour == Some(digit) {
+                            None
+                        } else {
+                            Some(digit)
+                        };
+                    }
+                    _ => {}
+                });
+            }
+            PlayerAction::Type(digit) => {
+                let now = Date::now();
+                if let (Some((idx, first, when, undo_len)), Some(selected)) = (typed, next.selected)
+                {
+                    let combined = usize::from(first) * 10 + usize::from(digit);
+                    if idx == selected && now - when < TYPING_WINDOW && combined <= next.size {
+                        // Take back whatever the first digit did, and enter both
+                        if next.undo.len() > undo_len {
+                            next.cells = next.undo.pop().expect("Undo history vanished?");
+                        }
+                        return Rc::new(next).reduce(PlayerAction::Enter(combined as u8));
+                    }
+                }
+                let undo_len = next.undo.len();
+                let selected = next.selected;
+                let mut next = (*Rc::new(next).reduce(PlayerAction::Enter(digit))).clone();
+                if let Some(idx) = selected.filter(|_| usize::from(digit) * 10 <= next.size) {
+                    next.typed = Some((idx, digit, now, undo_len));
+                }
+                return next.into();
+            }
+            PlayerAction::Clear => {
+                let mode = next.mode;
+                next.edit(|cell| match mode {
+                    EntryMode::Colour => cell.colour = None,
+                    _ if cell.value.is_some() => cell.value = None,
+                    _ => cell.pencil = 0,
+                });
+            }
+            PlayerAction::SetMode(mode) => next.mode = mode,
+            PlayerAction::Undo => {
+                if let Some(cells) = next.undo.pop() {
+                    next.redo.push(std::mem::replace(&mut next.cells, cells));
+                    next.checked = None;
+                }
+            }
+            PlayerAction::Redo => {
+                if let Some(cells) = next.redo.pop() {
+                    next.undo.push(std::mem::replace(&mut next.cells, cells));
+                    next.checked = None;
+                }
+            }
+            PlayerAction::Check => next.checked = Some(next.check()),
+            PlayerAction::Restart => {
+                let before = next.cells.clone();
+                next.cells.iter_mut().for_each(|cell| {
+                    *cell = PlayerCell {
+                        given: cell.given,
+                        ..Default::default()
+                    }
+                });
+                if before != next.cells {
+                    next.undo.push(before);
+                    next.redo.clear();
+                }
+                next.checked = None;
+                next.started = Date::now();
+            }
+        }
+        next.into()
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct PuzzlePlayerProps {
+    pub data: Value,
+    /// Called with the elapsed time in seconds when a check finds the grid solved
+    pub onsolved: Option<Callback<u64>>,
+}
+
+/// The starting state for some puzzle data, which is only built again when
+/// the data changes, at which point the generation moves on
+struct Initial {
+    data: Value,
+    generation: usize,
+    state: Option<PlayerState>,
+}
+
+/// Play an F-Puzzles grid, or if it isn't one we can play, point the solver
+/// at the links to play it elsewhere
+#[function_component(PuzzlePlayer)]
+pub fn puzzle_player(props: &PuzzlePlayerProps) -> Html {
+    let initial = use_mut_ref(|| None::<Initial>);
+    let mut initial = initial.borrow_mut();
+    let generation = match initial.as_ref() {
+        Some(initial) if initial.data == props.data => None,
+        Some(initial) => Some(initial.generation + 1),
+        None => Some(0),
+    };
+    if let Some(generation) = generation {
+        *initial = Some(Initial {
+            data: props.data.clone(),
+            generation,
+            state: PlayerState::from_fpuzzles(&props.data),
+        });
+    }
+    let initial = initial.as_ref().expect("Initial state just built");
+
+    // A new grid must start a new player, since the player only takes its
+    // initial state when it is first created
+    match &initial.state {
+        Some(state) => html! {
+            <GridPlayer
+                key={initial.generation.to_string()}
+                initial={state.clone()}
+                onsolved={props.onsolved.clone()}
+            />
+        },
+        None => html! {
+            <div class={"notification is-info"}>
+                {"This grid can't be played here, please use one of the links to play it elsewhere."}
+            </div>
+        },
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct GridPlayerProps {
+    initial: PlayerState,
+    onsolved: Option<Callback<u64>>,
+}
+
+#[styled_component(GridPlayer)]
+fn grid_player(props: &GridPlayerProps) -> Html {
+    let state = use_reducer_eq({
+        let initial = props.initial.clone();
+        move || initial
+    });
+
+    let grid_style = use_style!(
+        r#"
+        width: 50vh;
+        height: 50vh;
+        outline: none;
+        user-select: none;
+        "#
+    );
+
+    use_effect_with_deps(
+        {
+            let onsolved = props.onsolved.clone();
+            let started = state.started;
+            move |checked: &Option<CheckResult>| {
+                if let (Some(CheckResult::Solved), Some(onsolved)) = (checked, onsolved) {
+                    onsolved.emit(((Date::now() - started) / 1000.0) as u64);
+                }
+                || ()
+            }
+        },
+        state.checked,
+    );
+
+    let size = state.size;
+    let extent = size * CELL_SIZE;
+
+    let onkeydown = {
+        let dispatcher = state.dispatcher();
+        Callback::from(move |e: KeyboardEvent| {
+            let key = e.key();
+            let action = match key.as_str() {
+                "ArrowUp" => Some(PlayerAction::Move(-1, 0)),
+                "ArrowDown" => Some(PlayerAction::Move(1, 0)),
+                "ArrowLeft" => Some(PlayerAction::Move(0, -1)),
+                "ArrowRight" => Some(PlayerAction::Move(0, 1)),
+                "Backspace" | "Delete" => Some(PlayerAction::Clear),
+                "z" if e.ctrl_key() => Some(PlayerAction::Undo),
+                "y" if e.ctrl_key() => Some(PlayerAction::Redo),
+                "n" => Some(PlayerAction::SetMode(EntryMode::Normal)),
+                "p" => Some(PlayerAction::SetMode(EntryMode::Pencil)),
+                "c" => Some(PlayerAction::SetMode(EntryMode::Colour)),
+                _ => key.parse::<u8>().ok().map(PlayerAction::Type),
+            };
+            if let Some(action) = action {
+                e.prevent_default();
+                dispatcher.dispatch(action);
+            }
+        })
+    };
+
+    let cells = state
+        .cells
+        .iter()
+        .enumerate()
+        .map(|(idx, cell)| {
+            let x = (idx % size) * CELL_SIZE;
+            let y = (idx / size) * CELL_SIZE;
+            let fill = cell
+                .colour
+                .map(|c| PALETTE[usize::from(c) - 1])
+                .unwrap_or("#ffffff");
+            let onclick = {
+                let dispatcher = state.dispatcher();
+                Callback::from(move |_| dispatcher.dispatch(PlayerAction::Select(idx)))
+            };
+            let content = if let Some(digit) = cell.digit() {
+                let colour = if cell.given.is_some() {
+                    "#000000"
+                } else {
+                    "#1d6ae5"
+                };
+                html! {
+                    <text x={(x + CELL_SIZE / 2).to_string()} y={(y + CELL_SIZE / 2).to_string()}
+                          fill={colour} font-size={"40"} text-anchor={"middle"} dominant-baseline={"central"}>
+                        {digit.to_string()}
+                    </text>
+                }
+            } else if cell.pencil != 0 {
+                let marks = (1..=size)
+                    .filter(|&d| cell.pencil & (1 << d) != 0)
+                    .map(|d| d.to_string())
+                    .collect::<String>();
+                html! {
+                    <text x={(x + CELL_SIZE / 2).to_string()} y={(y + CELL_SIZE / 2).to_string()}
+                          fill={"#1d6ae5"} font-size={"14"} text-anchor={"middle"} dominant-baseline={"central"}>
+                        {marks}
+                    </text>
+                }
+            } else {
+                html! {}
+            };
+            html! {
+                <g onclick={onclick}>
+                    <rect x={x.to_string()} y={y.to_string()} width={CELL_SIZE.to_string()} height={CELL_SIZE.to_string()}
+                          fill={fill} stroke={"#888888"} stroke-width={"1"} />
+                    {content}
+                </g>
+            }
+        })
+        .collect::<Html>();
+
+    // Thick lines wherever a cell's region differs from its neighbour's
+    let borders = (0..size * size)
+        .flat_map(|idx| {
+            let (row, col) = (idx / size, idx % size);
+            let x = col * CELL_SIZE;
+            let y = row * CELL_SIZE;
+            let mut lines = Vec::new();
+            if col + 1 < size && state.regions[idx] != state.regions[idx + 1] {
+                lines.push((x + CELL_SIZE, y, x + CELL_SIZE, y + CELL_SIZE));
+            }
+            if row + 1 < size && state.regions[idx] != state.regions[idx + size] {
+                lines.push((x, y + CELL_SIZE, x + CELL_SIZE, y + CELL_SIZE));
+            }
+            lines
+        })
+        .map(|(x1, y1, x2, y2)| {
+            html! {
+                <line x1={x1.to_string()} y1={y1.to_string()} x2={x2.to_string()} y2={y2.to_string()}
+                      stroke={"#000000"} stroke-width={"3"} stroke-linecap={"square"} />
+            }
+        })
+        .collect::<Html>();
+
+    let selection = if let Some(idx) = state.selected {
+        html! {
+            <rect x={((idx % size) * CELL_SIZE + 3).to_string()} y={((idx / size) * CELL_SIZE + 3).to_string()}
+                  width={(CELL_SIZE - 6).to_string()} height={(CELL_SIZE - 6).to_string()}
+                  fill={"none"} stroke={"#ffc107"} stroke-width={"5"} pointer-events={"none"} />
+        }
+    } else {
+        html! {}
+    };
+
+    let mode_buttons = [EntryMode::Normal, EntryMode::Pencil, EntryMode::Colour]
+        .into_iter()
+        .map(|mode| {
+            let dispatcher = state.dispatcher();
+            let onclick = Callback::from(move |_| dispatcher.dispatch(PlayerAction::SetMode(mode)));
+            let class = if state.mode == mode {
+                "button is-info is-selected"
+            } else {
+                "button"
+            };
+            html! {
+                <button class={class} onclick={onclick}>{mode.title()}</button>
+            }
+        })
+        .collect::<Html>();
+
+    let digit_buttons = (1..=size as u8)
+        .map(|digit| {
+            let dispatcher = state.dispatcher();
+            let onclick = Callback::from(move |_| dispatcher.dispatch(PlayerAction::Enter(digit)));
+            let style = match state.mode {
+                EntryMode::Colour if usize::from(digit) <= PALETTE.len() => {
+                    Some(format!("background-color: {};", PALETTE[usize::from(digit) - 1]))
+                }
+                _ => None,
+            };
+            html! {
+                <button class={"button"} style={style} onclick={onclick}>{digit.to_string()}</button>
+            }
+        })
+        .collect::<Html>();
+
+    let simple_button = |title: &'static str, action: fn() -> PlayerAction, enabled: bool| {
+        let dispatcher = state.dispatcher();
+        let onclick = Callback::from(move |_| dispatcher.dispatch(action()));
+        html! {
+            <button class={"button"} onclick={onclick} disabled={!enabled}>{title}</button>
+        }
+    };
+
+    let check_result = match state.checked {
+        None => html! {},
+        Some(CheckResult::Solved) => html! {
+            <div class={"notification is-success"}>{"Congratulations, that's correct!"}</div>
+        },
+        Some(CheckResult::Incomplete) => html! {
+            <div class={"notification is-info"}>{"No mistakes so far, but the grid isn't finished yet."}</div>
+        },
+        Some(CheckResult::Incorrect(n)) => html! {
+            <div class={"notification is-danger"}>
+                {format!("{} {} incorrect.", n, if n == 1 { "digit is" } else { "digits are" })}
+            </div>
+        },
+    };
+
+    html! {
+        <div class={"columns"}>
+            <div class={"column is-narrow"}>
+                <svg class={grid_style} viewBox={format!("-2 -2 {} {}", extent + 4, extent + 4)}
+                     tabindex={"0"} onkeydown={onkeydown}>
+                    {cells}
+                    {borders}
+                    <rect x={"0"} y={"0"} width={extent.to_string()} height={extent.to_string()}
+                          fill={"none"} stroke={"#000000"} stroke-width={"4"} pointer-events={"none"} />
+                    {selection}
+                </svg>
+            </div>
+            <div class={"column"}>
+                <div class={"buttons has-addons"}>
+                    {mode_buttons}
+                </div>
+                <div class={"buttons"}>
+                    {digit_buttons}
+                </div>
+                <div class={"buttons"}>
+                    {simple_button("Clear", || PlayerAction::Clear, state.selected.is_some())}
+                    {simple_button("Undo", || PlayerAction::Undo, !state.undo.is_empty())}
+                    {simple_button("Redo", || PlayerAction::Redo, !state.redo.is_empty())}
+                    {simple_button("Restart", || PlayerAction::Restart, true)}
+                    {simple_button("Check", || PlayerAction::Check, state.has_solution())}
+                </div>
+                {check_result}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,328 @@
+//! The user's profile, and taking their leave of us
+//!
+
+use linkdoku_common::{
+    AvatarSource, BackendLoginStatus, DeleteAccountRequest, DeleteAccountResponse, DeletionPolicy,
+    Profile, UpdateProfileRequest, UpdateProfileResponse,
+};
+use reqwest::Url;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+use yew_hooks::use_title;
+use yew_router::prelude::*;
+use yew_toastrack::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    login::{LoginStatus, LoginStatusAction, LoginStatusDispatcher},
+};
+use crate::Route;
+
+fn toast_failure(what: &str, e: impl std::fmt::Display) {
+    Toaster::toast(
+        Toast::new(&format!("{}: {}", what, e))
+            .with_lifetime(Some(5000))
+            .with_level(ToastLevel::Danger),
+    );
+}
+
+#[derive(Properties, PartialEq)]
+struct ProfileFormProps {
+    profile: Profile,
+    onsubmit: Callback<UpdateProfileRequest>,
+}
+
+#[function_component(ProfileForm)]
+fn profile_form(props: &ProfileFormProps) -> Html {
+    let name_ref = use_node_ref();
+    let avatar_ref = use_node_ref();
+
+    let onclick = Callback::from({
+        let name_ref = name_ref.clone();
+        let avatar_ref = avatar_ref.clone();
+        let onsubmit = props.onsubmit.clone();
+        move |_| {
+            let name: HtmlInputElement = name_ref.cast().unwrap();
+            let avatar: HtmlSelectElement = avatar_ref.cast().unwrap();
+            let preferred_name = name.value().trim().to_string();
+            onsubmit.emit(UpdateProfileRequest {
+                preferred_name: (!preferred_name.is_empty()).then_some(preferred_name),
+                avatar: match avatar.value().as_str() {
+                    "none" => AvatarSource::None,
+                    _ => AvatarSource::Gravatar,
+                },
+            });
+        }
+    });
+
+    let gravatar_help = if props.profile.has_gravatar {
+        html! {}
+    } else {
+        html! {
+            <p class={"help"}>{"Your login didn't tell us an email address, so you'll get initials either way"}</p>
+        }
+    };
+
+    html! {
+        <div class={"box"}>
+            <div class={"field"}>
+                <label class={"label"}>{"Preferred name"}</label>
+                <div class={"control"}>
+                    <input class={"input"} type={"text"} ref={name_ref}
+                        placeholder={props.profile.provider_name.clone()}
+                        value={props.profile.preferred_name.clone().unwrap_or_default()} />
+                </div>
+                <p class={"help"}>{"Leave this empty to use the name your login provider gives us"}</p>
+            </div>
+            <div class={"field"}>
+                <label class={"label"}>{"Avatar"}</label>
+                <div class={"control"}>
+                    <div class={"select"}>
+                        <select ref={avatar_ref}>
+                            <option value={"gravatar"} selected={props.profile.avatar == AvatarSource::Gravatar}>{"Gravatar"}</option>
+                            <option value={"none"} selected={props.profile.avatar == AvatarSource::None}>{"Initials only"}</option>
+                        </select>
+                    </div>
+                </div>
+                {gravatar_help}
+            </div>
+            <div class={"field"}>
+                <div class={"control"}>
+                    <button class={"button is-primary"} onclick={onclick}>{"Save"}</button>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct DeleteAccountFormProps {
+    onsubmit: Callback<DeletionPolicy>,
+}
+
+#[function_component(DeleteAccountForm)]
+fn delete_account_form(props: &DeleteAccountFormProps) -> Html {
+    let policy = use_state_eq(|| "orphan");
+    let confirmed = use_state_eq(|| false);
+    let role_ref = use_node_ref();
+
+    let radio = |value: &'static str, label: &'static str| {
+        let onchange = {
+            let policy = policy.clone();
+            Callback::from(move |_| policy.set(value))
+        };
+        html! {
+            <label class={"radio"}>
+                <input type={"radio"} class={"mr-2"} name={"policy"} checked={*policy == value} onchange={onchange} />
+                {label}
+            </label>
+        }
+    };
+
+    let role_input = if *policy == "reassign" {
+        html! {
+            <div class={"field"}>
+                <label class={"label"}>{"Role to hand your roles to"}</label>
+                <div class={"control"}>
+                    <input class={"input"} type={"text"} ref={role_ref.clone()} placeholder={"Role short name"} />
+                </div>
+            </div>
+        }
+    } else {
+        html! {}
+    };
+
+    let toggle_confirmed = {
+        let confirmed = confirmed.clone();
+        Callback::from(move |_| confirmed.set(!*confirmed))
+    };
+
+    let onclick = Callback::from({
+        let policy = policy.clone();
+        let onsubmit = props.onsubmit.clone();
+        move |_| {
+            let policy = match *policy {
+                "reassign" => {
+                    let role: HtmlInputElement = role_ref.cast().unwrap();
+                    let role = role.value().trim().to_string();
+                    if role.is_empty() {
+                        return;
+                    }
+                    DeletionPolicy::Reassign(role)
+                }
+                "delete" => DeletionPolicy::Delete,
+                _ => DeletionPolicy::Orphan,
+            };
+            onsubmit.emit(policy);
+        }
+    });
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Delete your account"}</p>
+            <p class={"block"}>
+                {"This removes your identity and all your logins.  Your roles and their puzzles can be left behind with nobody to manage them, handed to someone else, or deleted along with you."}
+            </p>
+            <div class={"field"}>
+                <div class={"control"}>
+                    {radio("orphan", "Leave my roles behind")}
+                    {radio("reassign", "Hand my roles to another role's owner")}
+                    {radio("delete", "Delete my roles and puzzles too")}
+                </div>
+            </div>
+            {role_input}
+            <div class={"field"}>
+                <label class={"checkbox"}>
+                    <input type={"checkbox"} class={"mr-2"} checked={*confirmed} onchange={toggle_confirmed} />
+                    {"I understand this cannot be undone"}
+                </label>
+            </div>
+            <div class={"field"}>
+                <div class={"control"}>
+                    <button class={"button is-danger"} disabled={!*confirmed} onclick={onclick}>{"Delete my account"}</button>
+                </div>
+            </div>
+        </div>
+    }
+}
+
+#[function_component(ProfilePage)]
+pub fn profile_page() -> Html {
+    use_title("Linkdoku - Profile".to_string());
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
+    let login_status_dispatch =
+        use_context::<LoginStatusDispatcher>().expect("Cannot get login status dispatcher");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let history = use_history().expect("What, no history?");
+    let profile = use_state_eq(|| None);
+    let profile_url = use_api_url("/profile/get");
+    let update_url = use_api_url("/profile/update");
+    let export_url = use_api_url("/profile/export");
+    let delete_url = use_api_url("/profile/delete");
+    let status_url = use_api_url("/login/status");
+
+    use_effect_with_deps(
+        {
+            let profile = profile.setter();
+            let client = client.clone();
+            move |profile_url: &Url| {
+                let profile_url = profile_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<Profile>, _> =
+                        make_api_call(client, profile_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => profile.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch profile: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        profile_url,
+    );
+
+    let update = Callback::from({
+        let client = client.clone();
+        let profile = profile.setter();
+        let login_status_dispatch = login_status_dispatch.clone();
+        move |request: UpdateProfileRequest| {
+            let client = client.clone();
+            let update_url = update_url.clone();
+            let status_url = status_url.clone();
+            let profile = profile.clone();
+            let login_status_dispatch = login_status_dispatch.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client.clone(), update_url.as_str(), None, Some(request)).await
+                {
+                    Ok(UpdateProfileResponse::Success(value)) => {
+                        profile.set(Some(value));
+                        Toaster::toast(
+                            Toast::new("Profile saved")
+                                .with_lifetime(Some(2000))
+                                .with_level(ToastLevel::Success),
+                        );
+                    }
+                    Ok(res) => return toast_failure("Unable to save profile", res),
+                    Err(e) => return toast_failure("API Error", e),
+                }
+                // The navbar shows our name and avatar, so pick those up afresh
+                if let Ok(BackendLoginStatus::LoggedIn {
+                    name,
+                    gravatar_hash,
+                    roles,
+                    role,
+                }) = make_api_call(client, status_url.as_str(), None, NO_BODY).await
+                {
+                    login_status_dispatch.dispatch(LoginStatusAction::LoggedIn(
+                        name,
+                        gravatar_hash,
+                        roles,
+                        role,
+                    ));
+                }
+            });
+        }
+    });
+
+    let delete = Callback::from(move |policy: DeletionPolicy| {
+        let client = client.clone();
+        let delete_url = delete_url.clone();
+        let login_status_dispatch = login_status_dispatch.clone();
+        let history = history.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match make_api_call(
+                client,
+                delete_url.as_str(),
+                None,
+                Some(DeleteAccountRequest { policy }),
+            )
+            .await
+            {
+                Ok(DeleteAccountResponse::Success) => {
+                    Toaster::toast(
+                        Toast::new("Your account has been deleted")
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Info),
+                    );
+                    login_status_dispatch.dispatch(LoginStatusAction::LoggedOut);
+                    history.push(Route::Root);
+                }
+                Ok(res) => toast_failure("Unable to delete account", res),
+                Err(e) => toast_failure("API Error", e),
+            }
+        });
+    });
+
+    match login_status {
+        LoginStatus::LoggedIn { .. } => {}
+        LoginStatus::Unknown => return html! {},
+        LoginStatus::LoggedOut => {
+            return html! {
+                <div>{"You must be logged in to manage your profile"}</div>
+            }
+        }
+    }
+
+    let profile = match &*profile {
+        Some(profile) => profile.clone(),
+        None => return html! {},
+    };
+
+    html! {
+        <>
+            <h1 class={"title is-1"}>{"Profile"}</h1>
+            <ProfileForm profile={profile} onsubmit={update} />
+            <div class={"box"}>
+                <p class={"subtitle"}>{"Your data"}</p>
+                <p class={"block"}>
+                    {"Download everything we hold about you, your roles, and their puzzles."}
+                </p>
+                <a class={"button"} href={export_url.to_string()} download={"linkdoku-export.json"}>
+                    <span class={"icon"}><i class={"fa-solid fa-download"} /></span>
+                    <span>{"Export my data"}</span>
+                </a>
+            </div>
+            <DeleteAccountForm onsubmit={delete} />
+        </>
+    }
+}
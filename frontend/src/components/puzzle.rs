@@ -1,7 +1,10 @@
 //! Puzzle related stuff
 //!
 
-use linkdoku_common::{PuzzleData, PuzzleState, Rating, UrlEntry};
+use linkdoku_common::{
+    PuzzleData, PuzzleState, Rating, SetVisibilityRequest, SetVisibilityResponse, SolutionCount,
+    UrlEntry, Visibility,
+};
 use serde_json::{json, Value};
 use stylist::{style, yew::*};
 use yew::prelude::*;
@@ -17,8 +20,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     components::{
+        comments::CommentsPanel,
+        community::CommunityRatingsPanel,
         core::{make_api_call, use_api_url, use_page_url, ReqwestClient},
         login::LoginStatus,
+        pack::{PackContents, PackEditor, PackNavigation},
+        player::PuzzlePlayer,
+        solve::{use_mark_solved, SolveStatsPanel, SolvedBadge},
         utility::{CopyButton, Tooltip, TooltipAlignment},
     },
     utils::{
@@ -34,6 +42,7 @@ pub struct CreatePuzzleState {
     pub short_name: String,
     pub display_name: String,
     pub puzzle_state: PuzzleState,
+    pub store_solution: bool,
 }
 
 #[function_component(NoPuzzleRedirect)]
@@ -116,6 +125,100 @@ pub fn puzzle_rating(props: &PuzzleRatingProps) -> Html {
     }
 }
 
+#[derive(Properties, PartialEq)]
+struct VisibilityControlProps {
+    puzzle: String,
+    short_name: String,
+    visibility: Visibility,
+}
+
+/// Lets the owner of a puzzle restrict, make public, or publish it
+#[function_component(VisibilityControl)]
+fn visibility_control(props: &VisibilityControlProps) -> Html {
+    let visibility = use_state_eq(|| props.visibility);
+    let cache = use_context::<ObjectCache>().expect("No cache?");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let visibility_url = use_api_url(&format!("/puzzle/visibility/{}", props.puzzle));
+
+    let set_visibility = Callback::from({
+        let visibility = visibility.setter();
+        let puzzle = props.puzzle.clone();
+        let short_name = props.short_name.clone();
+        move |new_visibility: Visibility| {
+            let client = client.clone();
+            let visibility_url = visibility_url.clone();
+            let visibility = visibility.clone();
+            let cache = cache.clone();
+            let puzzle = puzzle.clone();
+            let short_name = short_name.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(
+                    client,
+                    visibility_url.as_str(),
+                    None,
+                    Some(SetVisibilityRequest {
+                        visibility: new_visibility,
+                    }),
+                )
+                .await
+                {
+                    Ok(SetVisibilityResponse::Success(solutions)) => {
+                        cache.forget_puzzle(&puzzle);
+                        cache.forget_puzzle(&short_name);
+                        visibility.set(new_visibility);
+                        match solutions {
+                            None | Some(SolutionCount::One) => {}
+                            Some(count) => Toaster::toast(
+                                Toast::new(&format!("Published, but the solver found: {}", count))
+                                    .with_lifetime(Some(5000))
+                                    .with_level(ToastLevel::Warning),
+                            ),
+                        }
+                    }
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to change visibility: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => Toaster::toast(
+                        Toast::new(&format!("API Error: {}", e))
+                            .with_lifetime(Some(2000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                }
+            });
+        }
+    });
+
+    let buttons = [
+        (Visibility::Restricted, "Restricted", "fa-lock"),
+        (Visibility::Public, "Public", "fa-link"),
+        (Visibility::Published, "Published", "fa-bullhorn"),
+    ]
+    .into_iter()
+    .map(|(value, label, icon)| {
+        let class = if *visibility == value {
+            "button is-small is-selected is-info"
+        } else {
+            "button is-small"
+        };
+        let onclick = set_visibility.reform(move |_| value);
+        html! {
+            <button class={class} onclick={onclick}>
+                <span class={"icon"}><i class={classes!("fas", "fa-solid", icon)} /></span>
+                <span>{label}</span>
+            </button>
+        }
+    })
+    .collect::<Html>();
+
+    html! {
+        <div class={"buttons has-addons"}>
+            {buttons}
+        </div>
+    }
+}
+
 #[derive(Properties, PartialEq, Eq, Debug)]
 pub struct PuzzlePageProps {
     pub puzzle: String,
@@ -216,6 +319,73 @@ pub fn puzzle_page(props: &PuzzlePageProps) -> Html {
         })
     });
 
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
+    let (mark_solved, just_solved) = use_mark_solved(&puzzle_data.uuid);
+    let solved = puzzle_data.solves.solved || just_solved;
+    let onmark = if login_status.is_logged_in() && !solved {
+        Some(mark_solved)
+    } else {
+        None
+    };
+
+    let visibility_control = if login_status.roles().contains(&puzzle_data.owner) {
+        html! {
+            <VisibilityControl
+                puzzle={puzzle_data.uuid.clone()}
+                short_name={puzzle_data.short_name.clone()}
+                visibility={puzzle_data.visibility}
+            />
+        }
+    } else {
+        html! {}
+    };
+
+    let player = match current_state.map(|s| &s.data) {
+        Some(PuzzleData::FPuzzles(data)) => {
+            let onsolved = onmark.clone().map(|onmark| onmark.reform(Some));
+            html! {
+                <div class={"block"}>
+                    <PuzzlePlayer data={data.clone()} onsolved={onsolved} />
+                </div>
+            }
+        }
+        Some(PuzzleData::Pack(members)) => html! {
+            <div class={"block"}>
+                <PackContents members={members.clone()} />
+            </div>
+        },
+        _ => html! {},
+    };
+
+    let solve_stats = {
+        let mut stats = puzzle_data.solves.clone();
+        stats.solved = solved;
+        html! {
+            <div class={"block"}>
+                <SolveStatsPanel stats={stats} onmark={onmark} />
+            </div>
+        }
+    };
+
+    let community = html! {
+        <div class={"block"}>
+            <CommunityRatingsPanel
+                puzzle={puzzle_data.uuid.clone()}
+                setter_rating={current_state.map(|s| s.setter_rating)}
+                can_rate={login_status.is_logged_in()}
+            />
+        </div>
+    };
+
+    let comments = html! {
+        <div class={"block"}>
+            <CommentsPanel
+                puzzle={puzzle_data.uuid.clone()}
+                can_comment={login_status.is_logged_in()}
+            />
+        </div>
+    };
+
     html! {
         <>
             <div class={"block"}>
@@ -231,8 +401,15 @@ pub fn puzzle_page(props: &PuzzlePageProps) -> Html {
                     </Tooltip>
                 </span>
                 {rating}
+                <SolvedBadge solved={solved} />
+                {visibility_control}
                 <MarkdownRender markdown={description.to_string()} transformer={transformer}/>
             </div>
+            <PackNavigation puzzle={puzzle_data.uuid.clone()} />
+            {player}
+            {solve_stats}
+            {community}
+            {comments}
         </>
     }
 }
@@ -511,6 +688,37 @@ fn puzzle_state_editor(props: &PuzzleStateEditorProps) -> Html {
             }
         });
 
+        let solution_check = use_state_eq(|| None);
+        let check_url = use_api_url("/puzzle/check");
+        let client = use_context::<ReqwestClient>().expect("No API client");
+
+        let check_callback = Callback::from({
+            let fpuzzles_data = fpuzzles_data.clone();
+            let setter = solution_check.setter();
+            move |_| {
+                let state = PuzzleState {
+                    data: match &*fpuzzles_data {
+                        Some(data) => PuzzleData::FPuzzles(data.clone()),
+                        None => return,
+                    },
+                    ..Default::default()
+                };
+                let client = client.clone();
+                let check_url = check_url.clone();
+                let setter = setter.clone();
+                setter.set(Some("Checking…".to_string()));
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<SolutionCount>, _> =
+                        make_api_call(client, check_url.as_str(), None, Some(state)).await;
+                    setter.set(Some(match result {
+                        Ok(Some(count)) => count.to_string(),
+                        Ok(None) => "No grid to check".to_string(),
+                        Err(e) => format!("API Error: {}", e),
+                    }));
+                });
+            }
+        });
+
         html! {
             <>
                 <div class={"field"}>
@@ -532,10 +740,30 @@ fn puzzle_state_editor(props: &PuzzleStateEditorProps) -> Html {
                         {render_puzzle_data(fpuzzles_data.as_ref())}
                     </div>
                 </div>
+                <div class={"field is-grouped"}>
+                    <div class={"control"}>
+                        <button class={"button"} onclick={check_callback} disabled={fpuzzles_data.is_none()}>
+                            {"Check solutions"}
+                        </button>
+                    </div>
+                    <div class={"control"}>
+                        <span class={"tag is-medium"}>{(*solution_check).clone().unwrap_or_default()}</span>
+                    </div>
+                </div>
             </>
         }
     };
 
+    let pack_tab_content = {
+        let onchange = Callback::from({
+            let pack_data = pack_data.setter();
+            move |members| pack_data.set(members)
+        });
+        html! {
+            <PackEditor members={(*pack_data).clone()} onchange={onchange} />
+        }
+    };
+
     let puzzle_data_control = {
         let tabchanged = Callback::from({
             let editor_kind = editor_kind.clone();
@@ -557,6 +785,9 @@ fn puzzle_state_editor(props: &PuzzleStateEditorProps) -> Html {
                         <TabContent title={EditorKind::FPuzzles.title()}>
                             {fpuzzles_tab_content}
                         </TabContent>
+                        <TabContent title={EditorKind::Pack.title()}>
+                            {pack_tab_content}
+                        </TabContent>
                     </Tabbed>
                 </div>
             </div>
@@ -727,6 +958,26 @@ pub fn create_puzzle() -> Html {
         }
     };
 
+    let store_solution_control = {
+        let toggled = Callback::from({
+            let state = state.clone();
+            move |_| {
+                let mut new_state = (*state).clone();
+                new_state.store_solution = !new_state.store_solution;
+                state.set(new_state);
+            }
+        });
+        let disabled = !matches!(state.puzzle_state.data, PuzzleData::FPuzzles(_));
+        html! {
+            <div class={"field"}>
+                <label class={"checkbox"}>
+                    <input type={"checkbox"} class={"mr-2"} checked={state.store_solution} disabled={disabled} onchange={toggled} />
+                    {"Store the solution in the grid if it is unique (anyone who can see the puzzle can see the solution)"}
+                </label>
+            </div>
+        }
+    };
+
     let create_puzzle_button = {
         let button_ref = use_node_ref();
 
@@ -741,17 +992,21 @@ pub fn create_puzzle() -> Html {
             let state = state.clone();
             let history = history.clone();
             move |_| {
-                use linkdoku_common::{CreatePuzzleResponse, Puzzle, Visibility};
+                use linkdoku_common::{CreatePuzzleRequest, CreatePuzzleResponse, Puzzle};
                 let button: HtmlButtonElement = button_ref.cast().unwrap();
                 button.set_class_name(pending_classes);
-                let puzzle = Puzzle {
-                    uuid: String::new(),
-                    owner: state.owner.clone(),
-                    short_name: state.short_name.clone(),
-                    display_name: state.display_name.clone(),
-                    visibility: Visibility::Restricted,
-                    visibility_changed: None,
-                    states: vec![state.puzzle_state.clone()],
+                let puzzle = CreatePuzzleRequest {
+                    puzzle: Puzzle {
+                        uuid: String::new(),
+                        owner: state.owner.clone(),
+                        short_name: state.short_name.clone(),
+                        display_name: state.display_name.clone(),
+                        visibility: Visibility::Restricted,
+                        visibility_changed: None,
+                        states: vec![state.puzzle_state.clone()],
+                        solves: Default::default(),
+                    },
+                    store_solution: state.store_solution,
                 };
                 let client = client.clone();
                 let create_puzzle_url = create_puzzle_url.clone();
@@ -772,13 +1027,22 @@ pub fn create_puzzle() -> Html {
                                 return;
                             }
                         };
-                    if let CreatePuzzleResponse::Success(uuid) = &result {
+                    if let CreatePuzzleResponse::Success(uuid, solutions) = &result {
                         // Success, so redirect to this puzzle
                         Toaster::toast(
                             Toast::new("Created successfully")
                                 .with_lifetime(Some(1000))
                                 .with_level(ToastLevel::Success),
                         );
+                        if let Some(solutions) = solutions {
+                            if *solutions != SolutionCount::One {
+                                Toaster::toast(
+                                    Toast::new(&format!("Warning, puzzle grid has: {}", solutions))
+                                        .with_lifetime(Some(5000))
+                                        .with_level(ToastLevel::Warning),
+                                );
+                            }
+                        }
                         history.push(Route::PuzzlePage {
                             puzzle: uuid.clone(),
                         });
@@ -817,6 +1081,7 @@ pub fn create_puzzle() -> Html {
             {short_name_control}
             {display_name_control}
             {puzzle_data_control}
+            {store_solution_control}
             {create_puzzle_button}
         </>
     }
@@ -1,3 +1,5 @@
+use linkdoku_common::{FollowRequest, FollowResponse, FollowStatus};
+use reqwest::Url;
 use stylist::yew::{styled_component, use_style};
 use yew::prelude::*;
 use yew_hooks::use_title;
@@ -6,7 +8,12 @@ use yew_router::prelude::{use_history, History, Location};
 use yew_toastrack::{Toast, ToastLevel, Toaster};
 
 use crate::{
-    components::puzzle::CreatePuzzleState,
+    components::{
+        activity::ActivityPanel,
+        core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+        puzzle::CreatePuzzleState,
+        webhook::WebhookPanel,
+    },
     utils::cache::{CacheEntry, ObjectCache},
     Route,
 };
@@ -103,6 +110,103 @@ pub fn render_role(props: &RoleProps) -> Html {
     }
 }
 
+#[derive(Properties, PartialEq)]
+pub struct FollowButtonProps {
+    /// UUID of the role to follow
+    pub role: String,
+}
+
+#[function_component(FollowButton)]
+pub fn follow_button(props: &FollowButtonProps) -> Html {
+    let status = use_state_eq(|| None);
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let follow_url = use_api_url(&format!("/role/follow/{}", props.role));
+
+    use_effect_with_deps(
+        {
+            let status = status.setter();
+            let client = client.clone();
+            move |follow_url: &Url| {
+                let follow_url = follow_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<FollowStatus>, _> =
+                        make_api_call(client, follow_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => status.set(value),
+                        Err(e) => {
+                            gloo::console::log!(format!("Unable to fetch follow status: {}", e))
+                        }
+                    }
+                });
+                || ()
+            }
+        },
+        follow_url.clone(),
+    );
+
+    let current: FollowStatus = match &*status {
+        Some(current) => current.clone(),
+        None => return html! {},
+    };
+
+    let followers = match current.followers {
+        1 => "1 follower".to_string(),
+        n => format!("{} followers", n),
+    };
+
+    if !login_status.is_logged_in() {
+        return html! {
+            <span class={"tag is-medium"}>{followers}</span>
+        };
+    }
+
+    let onclick = Callback::from({
+        let status = status.setter();
+        let follow = !current.following;
+        move |_| {
+            let client = client.clone();
+            let follow_url = follow_url.clone();
+            let status = status.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(
+                    client,
+                    follow_url.as_str(),
+                    None,
+                    Some(FollowRequest { follow }),
+                )
+                .await
+                {
+                    Ok(FollowResponse::Success(value)) => status.set(Some(value)),
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to change follow: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => Toaster::toast(
+                        Toast::new(&format!("API Error: {}", e))
+                            .with_lifetime(Some(2000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                }
+            });
+        }
+    });
+
+    let (label, class) = if current.following {
+        ("Unfollow", "button")
+    } else {
+        ("Follow", "button is-info")
+    };
+
+    html! {
+        <div class={"buttons has-addons"}>
+            <button class={class} onclick={onclick}>{label}</button>
+            <button class={"button is-static"}>{followers}</button>
+        </div>
+    }
+}
+
 #[function_component(DefaultRoleRedirect)]
 pub fn default_role_redirect() -> Html {
     let login_stats = use_context::<LoginStatus>().expect("No login status?");
@@ -139,6 +243,7 @@ pub fn role_page(props: &RolePageProps) -> Html {
     let cache = use_context::<ObjectCache>().expect("No cache?");
     let role_data = cache.cached_role(&props.role);
     let history = use_history().expect("No history?");
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
 
     gloo::console::log!(format!("Role Page: role={:?}", &*role_data));
 
@@ -193,6 +298,24 @@ pub fn role_page(props: &RolePageProps) -> Html {
 
     use_title(format!("Linkdoku - Role - {}", role_data.display_name));
 
+    let feed_url = use_api_url(&format!("/role/feed/{}", role_data.short_name));
+
+    let follow_button = html! {
+        <FollowButton role={role_data.uuid.clone()} />
+    };
+
+    // Only members of the role may see or change its webhook, or see its activity
+    let webhook_panel = if login_status.roles().contains(&role_data.uuid) {
+        html! {
+            <>
+                <WebhookPanel role={role_data.uuid.clone()} />
+                <ActivityPanel role={role_data.uuid.clone()} />
+            </>
+        }
+    } else {
+        html! {}
+    };
+
     let create_puzzle_click = Callback::from(move |_| {
         history
             .push_with_state(
@@ -208,10 +331,26 @@ pub fn role_page(props: &RolePageProps) -> Html {
     html! {
         <>
             <h1 class={"title is-1"}>{role_data.display_name.clone()}</h1>
+            <div class={"level is-mobile"}>
+                <div class={"level-left"}>
+                    <div class={"level-item"}>
+                        {follow_button}
+                    </div>
+                </div>
+                <div class={"level-right"}>
+                    <div class={"level-item"}>
+                        <a class={"button is-small"} href={feed_url.to_string()}>
+                            <span class={"icon"}><i class={"fas fa-solid fa-rss"} /></span>
+                            <span>{"Atom feed"}</span>
+                        </a>
+                    </div>
+                </div>
+            </div>
             <MarkdownRender markdown={role_data.bio} />
             <hr />
             <h2 class={"title is-2"}>{"No puzzle list renderer yet"}</h2>
             <hr />
+            {webhook_panel}
             <div class={"level is-mobile"}>
                 <div class={"level-left"} />
                 <div class={"level-right"}>
@@ -0,0 +1,205 @@
+//! Solve tracking for puzzles
+//!
+
+use linkdoku_common::{MarkSolvedRequest, MarkSolvedResponse, SolveStats};
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_toastrack::*;
+
+use crate::{
+    components::core::{make_api_call, use_api_url, ReqwestClient},
+    utils::cache::ObjectCache,
+};
+
+/// Render a number of seconds as `h:mm:ss` or `m:ss`
+pub fn format_solve_time(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds / 60) % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Parse a solve time typed by a user, either `h:mm:ss`, `m:ss` or plain minutes
+fn parse_solve_time(input: &str) -> Option<u64> {
+    input
+        .trim()
+        .split(':')
+        .map(|bit| bit.trim().parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|bits| match bits.as_slice() {
+            [minutes] => Some(minutes * 60),
+            [minutes, seconds] => Some(minutes * 60 + seconds),
+            [hours, minutes, seconds] => Some(hours * 3600 + minutes * 60 + seconds),
+            _ => None,
+        })
+}
+
+/// Acquire a callback which marks the given puzzle as solved, and a flag
+/// which says if the puzzle has been marked solved during this page view
+pub fn use_mark_solved(puzzle: &str) -> (Callback<Option<u64>>, bool) {
+    let marked = use_state_eq(|| false);
+    let cache = use_context::<ObjectCache>().expect("No cache?");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let mark_url = use_api_url(&format!("/puzzle/solved/{}", puzzle));
+
+    let callback = Callback::from({
+        let marked = marked.setter();
+        let puzzle = puzzle.to_string();
+        move |solve_time| {
+            let client = client.clone();
+            let mark_url = mark_url.clone();
+            let marked = marked.clone();
+            let cache = cache.clone();
+            let puzzle = puzzle.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let result: MarkSolvedResponse = match make_api_call(
+                    client,
+                    mark_url.as_str(),
+                    None,
+                    Some(MarkSolvedRequest { solve_time }),
+                )
+                .await
+                {
+                    Ok(res) => res,
+                    Err(e) => {
+                        Toaster::toast(
+                            Toast::new(&format!("API Error: {}", e))
+                                .with_lifetime(Some(2000))
+                                .with_level(ToastLevel::Danger),
+                        );
+                        return;
+                    }
+                };
+                if result == MarkSolvedResponse::Success {
+                    Toaster::toast(
+                        Toast::new("Solve recorded")
+                            .with_lifetime(Some(2000))
+                            .with_level(ToastLevel::Success),
+                    );
+                    cache.forget_puzzle(&puzzle);
+                    marked.set(true);
+                } else {
+                    Toaster::toast(
+                        Toast::new(&format!("Unable to record solve: {}", result))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    );
+                }
+            });
+        }
+    });
+
+    (callback, *marked)
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SolvedBadgeProps {
+    pub solved: bool,
+}
+
+#[function_component(SolvedBadge)]
+pub fn solved_badge(props: &SolvedBadgeProps) -> Html {
+    if props.solved {
+        html! {
+            <span class={"tag is-success is-medium"}>
+                <span class={"icon"}><i class={"fas fa-solid fa-check"} /></span>
+                <span>{"Solved"}</span>
+            </span>
+        }
+    } else {
+        html! {}
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SolveStatsPanelProps {
+    pub stats: SolveStats,
+    /// Present if the user is able to mark the puzzle as solved
+    pub onmark: Option<Callback<Option<u64>>>,
+}
+
+#[function_component(SolveStatsPanel)]
+pub fn solve_stats_panel(props: &SolveStatsPanelProps) -> Html {
+    let time_input = use_node_ref();
+
+    let mark_control = match &props.onmark {
+        Some(onmark) if !props.stats.solved => {
+            let onclick = Callback::from({
+                let onmark = onmark.clone();
+                let time_input = time_input.clone();
+                move |_| {
+                    let input: HtmlInputElement = time_input.cast().unwrap();
+                    let value = input.value();
+                    if value.trim().is_empty() {
+                        onmark.emit(None);
+                    } else if let Some(seconds) = parse_solve_time(&value) {
+                        onmark.emit(Some(seconds));
+                    } else {
+                        Toaster::toast(
+                            Toast::new("Solve time should look like 12:34")
+                                .with_lifetime(Some(5000))
+                                .with_level(ToastLevel::Warning),
+                        );
+                    }
+                }
+            });
+            html! {
+                <div class={"field has-addons"}>
+                    <div class={"control"}>
+                        <input ref={time_input} class={"input"} type={"text"} placeholder={"Solve time (optional), e.g. 12:34"} />
+                    </div>
+                    <div class={"control"}>
+                        <button class={"button is-primary"} onclick={onclick}>{"Mark as solved"}</button>
+                    </div>
+                </div>
+            }
+        }
+        _ => html! {},
+    };
+
+    let fastest = if props.stats.fastest.is_empty() {
+        html! {}
+    } else {
+        let rows = props
+            .stats
+            .fastest
+            .iter()
+            .enumerate()
+            .map(|(n, entry)| {
+                html! {
+                    <tr>
+                        <td>{(n + 1).to_string()}</td>
+                        <td>{entry.name.clone()}</td>
+                        <td class={"has-text-right"}>{format_solve_time(entry.seconds)}</td>
+                    </tr>
+                }
+            })
+            .collect::<Html>();
+        html! {
+            <table class={"table is-narrow"}>
+                <thead>
+                    <tr><th>{"#"}</th><th>{"Solver"}</th><th>{"Time"}</th></tr>
+                </thead>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+        }
+    };
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>
+                {match props.stats.count {
+                    0 => "Nobody has solved this puzzle yet".to_string(),
+                    1 => "Solved once".to_string(),
+                    n => format!("Solved {} times", n),
+                }}
+            </p>
+            {fastest}
+            {mark_control}
+        </div>
+    }
+}
@@ -0,0 +1,271 @@
+//! Personal API tokens, for scripts which talk to the API
+//!
+
+use js_sys::Date;
+use linkdoku_common::{
+    ApiTokenInfo, CreateApiTokenRequest, CreateApiTokenResponse, RevokeApiTokenRequest,
+    RevokeApiTokenResponse,
+};
+use reqwest::Url;
+use wasm_bindgen::JsValue;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_hooks::use_title;
+use yew_toastrack::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    login::LoginStatus,
+    role::Role,
+};
+
+fn toast_failure(what: &str, e: impl std::fmt::Display) {
+    Toaster::toast(
+        Toast::new(&format!("{}: {}", what, e))
+            .with_lifetime(Some(5000))
+            .with_level(ToastLevel::Danger),
+    );
+}
+
+#[derive(Properties, PartialEq)]
+struct TokenFormProps {
+    roles: Vec<String>,
+    onsubmit: Callback<CreateApiTokenRequest>,
+}
+
+#[function_component(TokenForm)]
+fn token_form(props: &TokenFormProps) -> Html {
+    let name_ref = use_node_ref();
+    let write_ref = use_node_ref();
+    let chosen = use_state_eq(Vec::<String>::new);
+
+    let role_toggles = props
+        .roles
+        .iter()
+        .map(|role| {
+            let checked = chosen.contains(role);
+            let onchange = {
+                let chosen = chosen.clone();
+                let role = role.clone();
+                Callback::from(move |_| {
+                    let mut value = (*chosen).clone();
+                    if value.contains(&role) {
+                        value.retain(|r| r != &role);
+                    } else {
+                        value.push(role.clone());
+                    }
+                    chosen.set(value);
+                })
+            };
+            html! {
+                <label class={"checkbox is-flex is-align-items-center"} key={role.clone()}>
+                    <input type={"checkbox"} class={"mr-2"} checked={checked} onchange={onchange} />
+                    <Role uuid={role.clone()} />
+                </label>
+            }
+        })
+        .collect::<Html>();
+
+    let onclick = Callback::from({
+        let name_ref = name_ref.clone();
+        let write_ref = write_ref.clone();
+        let chosen = chosen.clone();
+        let onsubmit = props.onsubmit.clone();
+        move |_| {
+            let name: HtmlInputElement = name_ref.cast().unwrap();
+            let write: HtmlInputElement = write_ref.cast().unwrap();
+            if name.value().trim().is_empty() {
+                return;
+            }
+            onsubmit.emit(CreateApiTokenRequest {
+                name: name.value(),
+                roles: (*chosen).clone(),
+                write: write.checked(),
+            });
+            name.set_value("");
+            write.set_checked(false);
+            chosen.set(Vec::new());
+        }
+    });
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Create a token"}</p>
+            <div class={"field"}>
+                <label class={"label"}>{"Name"}</label>
+                <div class={"control"}>
+                    <input ref={name_ref} class={"input"} type={"text"} placeholder={"What is this token for?"} />
+                </div>
+            </div>
+            <div class={"field"}>
+                <label class={"label"}>{"Roles the token may act as"}</label>
+                {role_toggles}
+            </div>
+            <div class={"field"}>
+                <label class={"checkbox"}>
+                    <input ref={write_ref} type={"checkbox"} />
+                    {" Allow this token to make changes, rather than only read"}
+                </label>
+            </div>
+            <button class={"button is-primary"} onclick={onclick}>{"Create token"}</button>
+        </div>
+    }
+}
+
+#[function_component(ApiTokensPage)]
+pub fn api_tokens_page() -> Html {
+    use_title("Linkdoku - API tokens".to_string());
+    let login_status = use_context::<LoginStatus>().expect("No login status?");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let tokens = use_state_eq(|| None);
+    // The most recently created token, which we can only show once
+    let fresh_token = use_state_eq(|| None::<String>);
+    let generation = use_state_eq(|| 0u32);
+    let list_url = use_api_url("/tokens/list");
+    let create_url = use_api_url("/tokens/create");
+    let revoke_url = use_api_url("/tokens/revoke");
+
+    use_effect_with_deps(
+        {
+            let tokens = tokens.setter();
+            let client = client.clone();
+            move |(list_url, _): &(Url, u32)| {
+                let list_url = list_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<Vec<ApiTokenInfo>>, _> =
+                        make_api_call(client, list_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => tokens.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch tokens: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        (list_url, *generation),
+    );
+
+    let create = Callback::from({
+        let client = client.clone();
+        let generation = generation.clone();
+        let fresh_token = fresh_token.setter();
+        move |request: CreateApiTokenRequest| {
+            let client = client.clone();
+            let create_url = create_url.clone();
+            let generation = generation.clone();
+            let fresh_token = fresh_token.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, create_url.as_str(), None, Some(request)).await {
+                    Ok(CreateApiTokenResponse::Success { token, .. }) => {
+                        fresh_token.set(Some(token));
+                        generation.set(*generation + 1);
+                    }
+                    Ok(res) => toast_failure("Unable to create token", res),
+                    Err(e) => toast_failure("API Error", e),
+                }
+            });
+        }
+    });
+
+    let revoke = Callback::from({
+        let generation = generation.clone();
+        move |id: String| {
+            let client = client.clone();
+            let revoke_url = revoke_url.clone();
+            let generation = generation.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(
+                    client,
+                    revoke_url.as_str(),
+                    None,
+                    Some(RevokeApiTokenRequest { id }),
+                )
+                .await
+                {
+                    Ok(RevokeApiTokenResponse::Success) => generation.set(*generation + 1),
+                    Ok(res) => toast_failure("Unable to revoke token", res),
+                    Err(e) => toast_failure("API Error", e),
+                }
+            });
+        }
+    });
+
+    let roles = match login_status {
+        LoginStatus::LoggedIn { roles, .. } => roles,
+        LoginStatus::Unknown => return html! {},
+        LoginStatus::LoggedOut => {
+            return html! {
+                <div>{"You must be logged in to manage API tokens"}</div>
+            }
+        }
+    };
+
+    let fresh = match &*fresh_token {
+        Some(token) => html! {
+            <div class={"notification is-success"}>
+                <p class={"block"}>{"Your new token is below. Copy it now, it will not be shown again."}</p>
+                <pre>{token.clone()}</pre>
+            </div>
+        },
+        None => html! {},
+    };
+
+    let rows = (*tokens)
+        .as_ref()
+        .map(|tokens: &Vec<ApiTokenInfo>| {
+            tokens
+                .iter()
+                .map(|token| {
+                    let created = Date::new(&JsValue::from_f64(token.created as f64 * 1000.0));
+                    let roles = token
+                        .roles
+                        .iter()
+                        .map(|role| html! { <Role uuid={role.clone()} /> })
+                        .collect::<Html>();
+                    let onclick = {
+                        let id = token.id.clone();
+                        revoke.reform(move |_| id.clone())
+                    };
+                    html! {
+                        <tr key={token.id.clone()}>
+                            <td>{token.name.clone()}</td>
+                            <td>{roles}</td>
+                            <td>{if token.write { "Read and write" } else { "Read only" }}</td>
+                            <td>{String::from(created.to_locale_string("default", &JsValue::UNDEFINED))}</td>
+                            <td class={"has-text-right"}>
+                                <button class={"button is-small is-danger"} onclick={onclick}>{"Revoke"}</button>
+                            </td>
+                        </tr>
+                    }
+                })
+                .collect::<Html>()
+        })
+        .unwrap_or_default();
+
+    html! {
+        <>
+            <h1 class={"title is-1"}>{"API tokens"}</h1>
+            <p class={"block"}>
+                {"Scripts may use these tokens to call the API on your behalf, by sending "}
+                <code>{"Authorization: Bearer <token>"}</code>
+                {" with each request."}
+            </p>
+            {fresh}
+            <table class={"table is-fullwidth"}>
+                <thead>
+                    <tr>
+                        <th>{"Name"}</th>
+                        <th>{"Roles"}</th>
+                        <th>{"Access"}</th>
+                        <th>{"Created"}</th>
+                        <th />
+                    </tr>
+                </thead>
+                <tbody>
+                    {rows}
+                </tbody>
+            </table>
+            <TokenForm roles={roles} onsubmit={create} />
+        </>
+    }
+}
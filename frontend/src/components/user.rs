@@ -1,9 +1,13 @@
 //! Components related to users
 
+use linkdoku_common::ChooseRoleRequest;
 use yew::prelude::*;
+use yew_router::prelude::*;
 
+use crate::components::core::{make_api_call, use_api_url, ReqwestClient};
 use crate::components::login::{LoginButton, LoginStatus, LogoutButton};
 use crate::components::role::Role;
+use crate::Route;
 
 use super::login::{LoginStatusAction, LoginStatusDispatcher};
 
@@ -51,6 +55,8 @@ pub fn user_avatar(props: &AvatarProps) -> Html {
 pub fn user_menu_button() -> Html {
     let login_status_dispatch =
         use_context::<LoginStatusDispatcher>().expect("Cannot get login status dispatcher");
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let choose_role_url = use_api_url("/login/role");
     match use_context::<LoginStatus>().expect("Unable to retrieve login status") {
         LoginStatus::Unknown => html! {},
         LoginStatus::LoggedOut => html! {
@@ -72,7 +78,27 @@ pub fn user_menu_button() -> Html {
                 .map(|this_role| {
                     let emitter = login_status_dispatch.clone();
                     let role_uuid = this_role.clone();
-                    let onclick = Callback::from(move |_| emitter.dispatch(LoginStatusAction::ChosenRole(role_uuid.clone())));
+                    let client = client.clone();
+                    let choose_role_url = choose_role_url.clone();
+                    // The backend records which role made any changes, so it needs telling too
+                    let onclick = Callback::from(move |_| {
+                        emitter.dispatch(LoginStatusAction::ChosenRole(role_uuid.clone()));
+                        let client = client.clone();
+                        let choose_role_url = choose_role_url.clone();
+                        let role = role_uuid.clone();
+                        wasm_bindgen_futures::spawn_local(async move {
+                            let result: Result<bool, _> = make_api_call(
+                                client,
+                                choose_role_url.as_str(),
+                                None,
+                                Some(ChooseRoleRequest { role }),
+                            )
+                            .await;
+                            if let Err(e) = result {
+                                gloo::console::log!(format!("Unable to choose role: {}", e));
+                            }
+                        });
+                    });
                     html! {
                         <div class={"navbar-item"}>
                             <Role active={role == this_role} uuid={this_role.clone()} onclick={onclick} />
@@ -90,6 +116,16 @@ pub fn user_menu_button() -> Html {
                     <div class={"navbar-dropdown is-right"}>
                         {roles}
                         <hr class={"navbar-divider"} />
+                        <Link<Route> to={Route::Profile} classes={"navbar-item"}>
+                            {"Profile"}
+                        </Link<Route>>
+                        <Link<Route> to={Route::LinkedLogins} classes={"navbar-item"}>
+                            {"Linked logins"}
+                        </Link<Route>>
+                        <Link<Route> to={Route::ApiTokens} classes={"navbar-item"}>
+                            {"API tokens"}
+                        </Link<Route>>
+                        <hr class={"navbar-divider"} />
                         <div class={"navbar-item"}>
                             <div class={"buttons"}>
                                 <LogoutButton />
@@ -0,0 +1,193 @@
+//! Webhook configuration for roles
+//!
+
+use js_sys::Date;
+use linkdoku_common::{SetWebhookRequest, SetWebhookResponse, WebhookSettings, WebhookStatus};
+use reqwest::Url;
+use wasm_bindgen::JsValue;
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_toastrack::*;
+
+use crate::components::{
+    core::{make_api_call, use_api_url, ReqwestClient, NO_BODY},
+    pack::PuzzleLink,
+};
+
+#[derive(Properties, PartialEq)]
+pub struct WebhookPanelProps {
+    /// UUID of the role
+    pub role: String,
+}
+
+/// Webhook settings and delivery log, only shown to the role's owner
+#[function_component(WebhookPanel)]
+pub fn webhook_panel(props: &WebhookPanelProps) -> Html {
+    let status = use_state_eq(|| None);
+    let client = use_context::<ReqwestClient>().expect("No API client");
+    let webhook_url = use_api_url(&format!("/role/webhook/{}", props.role));
+    let url_ref = use_node_ref();
+    let secret_ref = use_node_ref();
+
+    use_effect_with_deps(
+        {
+            let status = status.setter();
+            let client = client.clone();
+            move |webhook_url: &Url| {
+                let webhook_url = webhook_url.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let result: Result<Option<WebhookStatus>, _> =
+                        make_api_call(client, webhook_url.as_str(), None, NO_BODY).await;
+                    match result {
+                        Ok(value) => status.set(value),
+                        Err(e) => gloo::console::log!(format!("Unable to fetch webhook: {}", e)),
+                    }
+                });
+                || ()
+            }
+        },
+        webhook_url.clone(),
+    );
+
+    let submit = Callback::from({
+        let status = status.setter();
+        move |request: SetWebhookRequest| {
+            let client = client.clone();
+            let webhook_url = webhook_url.clone();
+            let status = status.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match make_api_call(client, webhook_url.as_str(), None, Some(request)).await {
+                    Ok(SetWebhookResponse::Success(value)) => {
+                        Toaster::toast(
+                            Toast::new("Webhook saved")
+                                .with_lifetime(Some(2000))
+                                .with_level(ToastLevel::Success),
+                        );
+                        status.set(Some(value));
+                    }
+                    Ok(res) => Toaster::toast(
+                        Toast::new(&format!("Unable to save webhook: {}", res))
+                            .with_lifetime(Some(5000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                    Err(e) => Toaster::toast(
+                        Toast::new(&format!("API Error: {}", e))
+                            .with_lifetime(Some(2000))
+                            .with_level(ToastLevel::Danger),
+                    ),
+                }
+            });
+        }
+    });
+
+    let current: WebhookStatus = match &*status {
+        Some(current) => current.clone(),
+        None => return html! {},
+    };
+
+    let save = submit.reform({
+        let url_ref = url_ref.clone();
+        let secret_ref = secret_ref.clone();
+        move |_| {
+            let url: HtmlInputElement = url_ref.cast().unwrap();
+            let secret: HtmlInputElement = secret_ref.cast().unwrap();
+            let secret = secret.value();
+            SetWebhookRequest {
+                webhook: Some(WebhookSettings {
+                    url: url.value(),
+                    secret: (!secret.is_empty()).then(|| secret),
+                }),
+                clear_secret: false,
+            }
+        }
+    });
+    let clear_secret = submit.reform({
+        let url_ref = url_ref.clone();
+        move |_| {
+            let url: HtmlInputElement = url_ref.cast().unwrap();
+            SetWebhookRequest {
+                webhook: Some(WebhookSettings {
+                    url: url.value(),
+                    secret: None,
+                }),
+                clear_secret: true,
+            }
+        }
+    });
+    let remove = submit.reform(|_| SetWebhookRequest {
+        webhook: None,
+        clear_secret: false,
+    });
+
+    let secret_help = if current.has_secret {
+        "A secret is set, leave this blank to keep it"
+    } else {
+        "Optional, used to sign payloads with HMAC-SHA256 in the X-Linkdoku-Signature header"
+    };
+
+    let deliveries = current
+        .deliveries
+        .iter()
+        .map(|delivery| {
+            let when = Date::new(&JsValue::from_f64(delivery.when as f64 * 1000.0));
+            let outcome = match &delivery.error {
+                None => html! { <span class={"tag is-success"}>{"Delivered"}</span> },
+                Some(error) => html! { <span class={"tag is-danger"} title={error.clone()}>{"Failed"}</span> },
+            };
+            html! {
+                <tr>
+                    <td>{String::from(when.to_locale_string("default", &JsValue::UNDEFINED))}</td>
+                    <td><PuzzleLink puzzle={delivery.puzzle.clone()} /></td>
+                    <td>{outcome}</td>
+                    <td>{delivery.status.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string())}</td>
+                    <td class={"has-text-right"}>{delivery.attempts.to_string()}</td>
+                </tr>
+            }
+        })
+        .collect::<Html>();
+
+    html! {
+        <div class={"box"}>
+            <p class={"subtitle"}>{"Webhook"}</p>
+            <p class={"block"}>{"When you publish a puzzle, we will POST its details as JSON to this URL."}</p>
+            <div class={"field"}>
+                <label class={"label"}>{"URL"}</label>
+                <div class={"control"}>
+                    <input ref={url_ref} class={"input"} type={"url"} placeholder={"https://example.com/webhook"} value={current.url.clone().unwrap_or_default()} />
+                </div>
+            </div>
+            <div class={"field"}>
+                <label class={"label"}>{"Secret"}</label>
+                <div class={"control"}>
+                    <input ref={secret_ref} class={"input"} type={"password"} />
+                </div>
+                <p class={"help"}>{secret_help}</p>
+            </div>
+            <div class={"buttons"}>
+                <button class={"button is-primary"} onclick={save}>{"Save"}</button>
+                if current.has_secret {
+                    <button class={"button is-warning"} onclick={clear_secret}>{"Clear secret"}</button>
+                }
+                if current.url.is_some() {
+                    <button class={"button is-danger"} onclick={remove}>{"Remove"}</button>
+                }
+            </div>
+            if !current.deliveries.is_empty() {
+                <table class={"table is-fullwidth is-narrow"}>
+                    <thead>
+                        <tr>
+                            <th>{"When"}</th>
+                            <th>{"Puzzle"}</th>
+                            <th>{"Outcome"}</th>
+                            <th>{"Status"}</th>
+                            <th class={"has-text-right"}>{"Attempts"}</th>
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {deliveries}
+                    </tbody>
+                </table>
+            }
+        </div>
+    }
+}
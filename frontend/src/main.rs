@@ -16,7 +16,11 @@ mod utils;
 use utils::cache::ObjectCacheProvider;
 
 use components::core::{BaseURIProvider, Footer, Navbar};
-use components::login::{LoginStatus, UserProvider};
+use components::feed::PuzzleFeed;
+use components::login::{DevLoginPage, LoginStatus, UserProvider};
+use components::logins::LinkedLoginsPage;
+use components::profile::ProfilePage;
+use components::tokens::ApiTokensPage;
 
 use crate::components::core::use_api_url;
 use crate::components::login::{LoginStatusAction, LoginStatusDispatcher};
@@ -24,14 +28,14 @@ use crate::components::puzzle::*;
 use crate::components::role::*;
 use crate::utils::urlbits::extract_fpuzzles_data;
 
-use yew_markdown::editor::MarkdownEditor;
-
 #[derive(Routable, PartialEq, Clone)]
 enum Route {
     #[at("/-/")]
     Root,
     #[at("/-/complete-login")]
     CompleteLogin,
+    #[at("/-/dev-login")]
+    DevLogin,
     #[at("/-/role")]
     DefaultRoleRedirect,
     #[at("/-/role/:role")]
@@ -44,6 +48,12 @@ enum Route {
     PuzzlePage { puzzle: String },
     #[at("/-/utils/lz")]
     LZPage,
+    #[at("/-/tokens")]
+    ApiTokens,
+    #[at("/-/logins")]
+    LinkedLogins,
+    #[at("/-/profile")]
+    Profile,
     #[not_found]
     #[at("/-/404")]
     NotFound,
@@ -90,27 +100,18 @@ fn login_flow() -> Html {
         };
     }
 
-    if query.code.is_none() || query.error.is_some() {
-        // We had an error, so we should clear things and return to root
-        let clear_url = use_api_url("/login/clear");
-        use_effect(move || {
-            wasm_bindgen_futures::spawn_local(async move {
-                reqwest::get(clear_url).await.expect("Should be fine!");
-                history.push(Route::Root);
-            });
-            || ()
-        });
-        return html! {
-            "We had a problem, please hold..."
-        };
+    // Errors from the provider are passed on too, so that the backend can clean
+    // up the flow in progress
+    let mut continuation_params = vec![("state", query.state.clone())];
+    if let Some(code) = &query.code {
+        continuation_params.push(("code", code.clone()));
+    }
+    if let Some(error) = &query.error {
+        continuation_params.push(("error", error.clone()));
     }
-
     let continuation_url = Url::parse_with_params(
         use_api_url("/login/continue").as_str(),
-        &[
-            ("state", query.state.clone()),
-            ("code", query.code.as_ref().unwrap().to_string()),
-        ],
+        &continuation_params,
     )
     .expect("Unable to construct continuation URL");
 
@@ -129,7 +130,32 @@ fn login_flow() -> Html {
                         .json()
                         .await
                         .expect("Unable to unpack json");
-                    if let Some(_error) = result.error {
+                    if result.linked {
+                        // The user was already logged in and was linking another login,
+                        // so their login status is unchanged
+                        match result.error {
+                            Some(error) => Toaster::toast(
+                                Toast::new(&format!("Unable to link login: {}", error))
+                                    .with_lifetime(Some(5000))
+                                    .with_level(ToastLevel::Danger),
+                            ),
+                            None => Toaster::toast(
+                                Toast::new("Login linked")
+                                    .with_lifetime(Some(2000))
+                                    .with_level(ToastLevel::Success),
+                            ),
+                        }
+                        history.push(Route::LinkedLogins);
+                        return;
+                    }
+                    if let Some(error) = result.error {
+                        if error == "suspended" {
+                            Toaster::toast(
+                                Toast::new("Your account has been suspended")
+                                    .with_lifetime(Some(5000))
+                                    .with_level(ToastLevel::Danger),
+                            );
+                        }
                         // Error while trying to retrieve login results, so say we're logged out
                         dispatcher.dispatch(LoginStatusAction::LoggedOut);
                     } else {
@@ -189,8 +215,12 @@ fn switch(route: &Route) -> Html {
     let page_html = match route {
         Route::Root => html! { <LoginStateShow /> },
         Route::CompleteLogin => html! { <HandleLoginFlow /> },
+        Route::DevLogin => html! { <DevLoginPage /> },
         Route::NotFound => html! { <ShowNotFound /> },
         Route::LZPage => html! { <LZPage /> },
+        Route::ApiTokens => html! { <ApiTokensPage /> },
+        Route::LinkedLogins => html! { <LinkedLoginsPage /> },
+        Route::Profile => html! { <ProfilePage /> },
         Route::DefaultRoleRedirect => html! { <DefaultRoleRedirect /> },
         Route::RolePage { role } => html! { <RolePage role={role.clone()} /> },
         Route::NoPuzzleRedirect => html! { <NoPuzzleRedirect /> },
@@ -209,19 +239,6 @@ fn switch(route: &Route) -> Html {
 fn show_login_state() -> Html {
     let login_status = use_context::<LoginStatus>().expect("Cannot get login status");
 
-    let counter = use_state(|| 0usize);
-
-    let toasty = Callback::from({
-        move |_| {
-            Toaster::toast(
-                Toast::new(&format!("Hello world ({})", *counter))
-                    .with_lifetime(Some(5000))
-                    .with_level(ToastLevel::Success),
-            );
-            counter.set(*counter + 1);
-        }
-    });
-
     let utility = Callback::from({
         let history = use_history().expect("What, no history?");
         move |_| {
@@ -229,24 +246,6 @@ fn show_login_state() -> Html {
         }
     });
 
-    const MARKDOWN: &str = r#"
-# Hello World
-
-This is a paragraph of text as a markdown object.
-It will be interesting to see what the everything looks like.
-
-| What | Are | You | Up | To |
-| :---- | :--- | ---: | :--: | :-- |
-| I'm  | Not | Sure | Truly | Sorry |
-
-Are you interested in [this link](https://example.com/)?
-Perhaps you want to try a [reference link][rl] instead?
-
-
-[rl]: https://www.digital-scurf.org/
-
-    "#;
-
     match login_status {
         LoginStatus::Unknown => html! {},
         LoginStatus::LoggedOut => {
@@ -262,11 +261,8 @@ Perhaps you want to try a [reference link][rl] instead?
                     <br />
                     <Role uuid={role.clone()} />
                     <br />
-                    <button class={"button is-danger"} onclick={toasty}>{"Say hello"}</button>
-                    <br />
+                    <PuzzleFeed />
                     <button class={"button is-primary"} onclick={utility}>{"LZ Utility"}</button>
-                    <br />
-                    <MarkdownEditor initial={MARKDOWN} />
                 </div>
             }
         }
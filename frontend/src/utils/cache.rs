@@ -117,6 +117,11 @@ impl ObjectCache {
         }
     }
 
+    /// Drop any cached copy of the given puzzle, so it is re-fetched next time
+    pub fn forget_puzzle(&self, uuid_or_short_name: &str) {
+        LocalStorage::delete(format!("puzzle:{}", uuid_or_short_name));
+    }
+
     pub fn use_cached_value<F, T, E>(
         &self,
         key: &str,
@@ -6,6 +6,8 @@ use yew::virtual_dom::VNode;
 use yew_markdown::render::MarkdownRender;
 use yew_markdown::xform::{TransformRequest, TransformResponse};
 
+use crate::components::pack::PuzzleLink;
+
 pub fn extract_fpuzzles_data(input: &str) -> Option<Value> {
     fn maybe_decode_fpuzzles(input: &str) -> Option<Value> {
         //gloo::console::log!(format!("Attempting to decode lzstr: {}", input));
@@ -149,18 +151,17 @@ pub fn transform_markdown(grid: &PuzzleState, req: TransformRequest) -> Transfor
             } else if let Some(maybe_idx) = url.strip_prefix("puzzle-") {
                 match maybe_idx.parse::<usize>() {
                     Ok(num) if num > 0 => {
-                        if let PuzzleData::Pack(urls) = &grid.data {
-                            if let Some(ue) = urls.get(num - 1) {
-                                let content = if trivially_text(&content, &url) {
-                                    html! {
-                                        <em>{"TODO: Magic puzzle link content"}</em>
-                                    }
+                        if let PuzzleData::Pack(members) = &grid.data {
+                            if let Some(member) = members.get(num - 1) {
+                                if trivially_text(&content, &url) {
+                                    Some(html! {
+                                        <PuzzleLink puzzle={member.clone()} />
+                                    })
                                 } else {
-                                    content
-                                };
-                                Some(html! {
-                                    <span>{"This would be a puzzle link to "} {ue}{". "} {content}</span>
-                                })
+                                    Some(html! {
+                                        <PuzzleLink puzzle={member.clone()}>{content}</PuzzleLink>
+                                    })
+                                }
                             } else {
                                 error(format!("Puzzle index out of range: {}", num))
                             }
@@ -0,0 +1,617 @@
+//! Turning F-Puzzles JSON into a [`Grid`]
+
+use serde_json::Value;
+
+use crate::{
+    search::{Arrow, Cage},
+    Grid, SolverError, SolverResult,
+};
+
+/// Constraints which we know how to ignore because they carry no logical
+/// meaning (they are purely decorative)
+const COSMETIC_KEYS: &[&str] = &[
+    "size",
+    "title",
+    "author",
+    "ruleset",
+    "solution",
+    "grid",
+    "text",
+    "line",
+    "rectangle",
+    "circle",
+    "cage",
+    "highlightConflicts",
+    "disabledlogic",
+    "truecandidatesoptions",
+];
+
+/// Constraints which we understand
+const SUPPORTED_KEYS: &[&str] = &[
+    "diagonal+",
+    "diagonal-",
+    "antiknight",
+    "antiking",
+    "disjointgroups",
+    "nonconsecutive",
+    "killercage",
+    "thermometer",
+    "arrow",
+    "extraregion",
+    "odd",
+    "even",
+];
+
+/// Box height for a grid of the given size, matching what F-Puzzles uses
+fn box_height(size: usize) -> usize {
+    (1..=size)
+        .take_while(|n| n * n <= size)
+        .filter(|n| size % n == 0)
+        .last()
+        .unwrap_or(1)
+}
+
+/// Parse an `R1C1` style cell reference into a cell index
+fn parse_cell(size: usize, cell: &Value) -> SolverResult<usize> {
+    let text = cell
+        .as_str()
+        .ok_or_else(|| SolverError::Invalid(format!("Bad cell reference: {}", cell)))?;
+    let bad = || SolverError::Invalid(format!("Bad cell reference: {}", text));
+    let (row, col) = text
+        .strip_prefix('R')
+        .and_then(|s| s.split_once('C'))
+        .ok_or_else(bad)?;
+    let row: usize = row.parse().map_err(|_| bad())?;
+    let col: usize = col.parse().map_err(|_| bad())?;
+    if row == 0 || col == 0 || row > size || col > size {
+        return Err(bad());
+    }
+    Ok((row - 1) * size + (col - 1))
+}
+
+fn parse_cells(size: usize, cells: Option<&Value>) -> SolverResult<Vec<usize>> {
+    cells
+        .and_then(Value::as_array)
+        .map(|cells| cells.iter().map(|c| parse_cell(size, c)).collect())
+        .unwrap_or_else(|| Ok(Vec::new()))
+}
+
+fn entries<'a>(data: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    data.get(key)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+fn flag(data: &Value, key: &str) -> bool {
+    data.get(key).and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn digit(value: &Value) -> Option<u8> {
+    match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u8::try_from(n).ok()),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+pub(crate) fn grid_from_fpuzzles(data: &Value) -> SolverResult<Grid> {
+    let data_obj = data
+        .as_object()
+        .ok_or_else(|| SolverError::Invalid("Puzzle data is not an object".to_string()))?;
+
+    // Refuse to answer for puzzles with constraints we don't understand, since
+    // any answer we gave would be misleading
+    for (key, value) in data_obj {
+        if COSMETIC_KEYS.contains(&key.as_str()) || SUPPORTED_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let in_use = match value {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Array(a) => !a.is_empty(),
+            _ => true,
+        };
+        if in_use {
+            return Err(SolverError::Unsupported(key.clone()));
+        }
+    }
+
+    let size = data
+        .get("size")
+        .and_then(Value::as_u64)
+        .map(|n| n as usize)
+        .unwrap_or(9);
+    if !(1..=16).contains(&size) {
+        return Err(SolverError::Invalid(format!(
+            "Unsupported grid size {}",
+            size
+        )));
+    }
+    let height = box_height(size);
+    let width = size / height;
+
+    let mut grid = Grid::new(size);
+
+    // Givens and regions come from the cell grid
+    let mut regions: Vec<Vec<usize>> = vec![Vec::new(); size];
+    for row in 0..size {
+        for col in 0..size {
+            let idx = row * size + col;
+            let cell = data
+                .get("grid")
+                .and_then(|g| g.get(row))
+                .and_then(|r| r.get(col));
+            let region = match cell.and_then(|c| c.get("region")) {
+                Some(Value::Null) => None,
+                Some(v) => v.as_u64().map(|n| n as usize),
+                None => Some((row / height) * height + (col / width)),
+            };
+            if let Some(region) = region {
+                regions
+                    .get_mut(region)
+                    .ok_or_else(|| SolverError::Invalid(format!("Bad region {}", region)))?
+                    .push(idx);
+            }
+            if let Some(cell) = cell {
+                if flag(cell, "given") {
+                    let value = cell.get("value").and_then(digit).unwrap_or(0);
+                    if value == 0 || usize::from(value) > size {
+                        return Err(SolverError::Invalid(format!(
+                            "Bad given at R{}C{}",
+                            row + 1,
+                            col + 1
+                        )));
+                    }
+                    grid.givens[idx] = value;
+                }
+            }
+        }
+    }
+
+    for i in 0..size {
+        grid.add_distinct(&(0..size).map(|c| i * size + c).collect::<Vec<_>>());
+        grid.add_distinct(&(0..size).map(|r| r * size + i).collect::<Vec<_>>());
+    }
+    for region in &regions {
+        grid.add_distinct(region);
+    }
+
+    if flag(data, "diagonal+") {
+        grid.add_distinct(
+            &(0..size)
+                .map(|i| (size - 1 - i) * size + i)
+                .collect::<Vec<_>>(),
+        );
+    }
+    if flag(data, "diagonal-") {
+        grid.add_distinct(&(0..size).map(|i| i * size + i).collect::<Vec<_>>());
+    }
+
+    if flag(data, "disjointgroups") {
+        for pos in 0..size {
+            let cells = (0..size * size)
+                .filter(|&idx| ((idx / size) % height) * width + (idx % size) % width == pos)
+                .collect::<Vec<_>>();
+            grid.add_distinct(&cells);
+        }
+    }
+
+    let offsets = |pairs: &[(isize, isize)], func: &mut dyn FnMut(usize, usize)| {
+        for row in 0..size as isize {
+            for col in 0..size as isize {
+                for &(dr, dc) in pairs {
+                    let (r2, c2) = (row + dr, col + dc);
+                    if r2 >= 0 && c2 >= 0 && r2 < size as isize && c2 < size as isize {
+                        func(
+                            (row as usize) * size + col as usize,
+                            (r2 as usize) * size + c2 as usize,
+                        );
+                    }
+                }
+            }
+        }
+    };
+
+    if flag(data, "antiknight") {
+        offsets(&[(1, 2), (2, 1), (2, -1), (1, -2)], &mut |a, b| {
+            grid.add_distinct_pair(a, b)
+        });
+    }
+    if flag(data, "antiking") {
+        offsets(&[(1, 1), (1, -1)], &mut |a, b| grid.add_distinct_pair(a, b));
+    }
+    if flag(data, "nonconsecutive") {
+        offsets(&[(0, 1), (1, 0)], &mut |a, b| {
+            grid.add_nonconsecutive_pair(a, b)
+        });
+    }
+
+    for region in entries(data, "extraregion") {
+        let cells = parse_cells(size, region.get("cells"))?;
+        grid.add_distinct(&cells);
+    }
+
+    for cage in entries(data, "killercage") {
+        let cells = parse_cells(size, cage.get("cells"))?;
+        let sum = cage.get("value").and_then(|v| match v {
+            Value::Number(n) => n.as_u64().map(|n| n as u32),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        });
+        grid.add_distinct(&cells);
+        grid.cages.push(Cage { cells, sum });
+    }
+
+    for thermo in entries(data, "thermometer") {
+        for line in entries(thermo, "lines") {
+            let cells = parse_cells(size, Some(line))?;
+            // Each position on the thermometer bounds the digits it can hold
+            for (pos, &cell) in cells.iter().enumerate() {
+                let above = cells.len() - 1 - pos;
+                for d in 1..=size {
+                    if d <= pos || d + above > size {
+                        grid.candidates[cell] &= !(1 << d);
+                    }
+                }
+            }
+            grid.add_distinct(&cells);
+            grid.thermos.push(cells);
+        }
+    }
+
+    for arrow in entries(data, "arrow") {
+        let bulb = parse_cells(size, arrow.get("cells"))?;
+        for line in entries(arrow, "lines") {
+            // Lines start in the bulb, so skip any cells which are part of it
+            let shaft = parse_cells(size, Some(line))?
+                .into_iter()
+                .filter(|c| !bulb.contains(c))
+                .collect();
+            grid.arrows.push(Arrow {
+                bulb: bulb.clone(),
+                shaft,
+            });
+        }
+    }
+
+    for (key, mask) in [("odd", 0xAAAA_AAAAu32), ("even", 0x5555_5554u32)] {
+        for entry in entries(data, key) {
+            let cell = parse_cell(size, entry.get("cell").unwrap_or(&Value::Null))?;
+            // Bit n set means n is permitted, so odd keeps bits 1,3,5...
+            grid.candidates[cell] &= mask;
+        }
+    }
+
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{solve_fpuzzles, tests::classic, Solutions};
+
+    /// A 4x4 solution, from which rows 3 and 4 are given below
+    const SOLUTION: [u8; 16] = [1, 2, 3, 4, 3, 4, 1, 2, 2, 1, 4, 3, 4, 3, 2, 1];
+    const BOTTOM_HALF: &str = "........21434321";
+
+    fn with(mut data: Value, key: &str, value: Value) -> Value {
+        data.as_object_mut().unwrap().insert(key.to_string(), value);
+        data
+    }
+
+    /// Every valid 4x4 grid with 2x2 boxes, by brute force
+    fn all_4x4() -> Vec<Vec<u8>> {
+        fn fill(grid: &mut Vec<u8>, idx: usize, out: &mut Vec<Vec<u8>>) {
+            if idx == 16 {
+                out.push(grid.clone());
+                return;
+            }
+            let (row, col) = (idx / 4, idx % 4);
+            for d in 1..=4 {
+                let clash = (0..idx).any(|other| {
+                    let (r, c) = (other / 4, other % 4);
+                    grid[other] == d
+                        && (r == row || c == col || (r / 2 == row / 2 && c / 2 == col / 2))
+                });
+                if !clash {
+                    grid[idx] = d;
+                    fill(grid, idx + 1, out);
+                }
+            }
+            grid[idx] = 0;
+        }
+        let mut out = Vec::new();
+        fill(&mut vec![0; 16], 0, &mut out);
+        out
+    }
+
+    /// What the solver should say, given which grids satisfy the constraint
+    fn expected(givens: &str, ok: impl Fn(&[u8]) -> bool) -> Solutions {
+        let matching: Vec<_> = all_4x4()
+            .into_iter()
+            .filter(|grid| {
+                givens
+                    .chars()
+                    .zip(grid.iter())
+                    .all(|(g, &d)| g.to_digit(10).map(|g| g as u8 == d).unwrap_or(true))
+            })
+            .filter(|grid| ok(grid))
+            .collect();
+        match matching.len() {
+            0 => Solutions::None,
+            1 => Solutions::Unique(matching[0].clone()),
+            _ => Solutions::Multiple,
+        }
+    }
+
+    /// All pairs of cells a given offset apart
+    fn pairs(offsets: &[(isize, isize)]) -> Vec<(usize, usize)> {
+        let mut ret = Vec::new();
+        for idx in 0..16isize {
+            for &(dr, dc) in offsets {
+                let (r, c) = (idx / 4 + dr, idx % 4 + dc);
+                if (0..4).contains(&r) && (0..4).contains(&c) {
+                    ret.push((idx as usize, (r * 4 + c) as usize));
+                }
+            }
+        }
+        ret
+    }
+
+    fn distinct(grid: &[u8], cells: &[usize]) -> bool {
+        cells
+            .iter()
+            .enumerate()
+            .all(|(i, &a)| cells[i + 1..].iter().all(|&b| grid[a] != grid[b]))
+    }
+
+    #[test]
+    fn flags_match_brute_force() {
+        let knight = pairs(&[(1, 2), (2, 1), (2, -1), (1, -2)]);
+        let king = pairs(&[(1, 1), (1, -1)]);
+        let orthogonal = pairs(&[(0, 1), (1, 0)]);
+        type Check = Box<dyn Fn(&[u8]) -> bool>;
+        let flags: Vec<(&str, Check)> = vec![
+            (
+                "diagonal+",
+                Box::new(|g: &[u8]| distinct(g, &[12, 9, 6, 3])),
+            ),
+            (
+                "diagonal-",
+                Box::new(|g: &[u8]| distinct(g, &[0, 5, 10, 15])),
+            ),
+            (
+                "antiknight",
+                Box::new(move |g: &[u8]| knight.iter().all(|&(a, b)| g[a] != g[b])),
+            ),
+            (
+                "antiking",
+                Box::new(move |g: &[u8]| king.iter().all(|&(a, b)| g[a] != g[b])),
+            ),
+            (
+                "nonconsecutive",
+                Box::new(move |g: &[u8]| orthogonal.iter().all(|&(a, b)| g[a].abs_diff(g[b]) != 1)),
+            ),
+            (
+                "disjointgroups",
+                Box::new(|g: &[u8]| {
+                    (0..4).all(|pos| {
+                        let (r, c) = (pos / 2, pos % 2);
+                        distinct(
+                            g,
+                            &[r * 4 + c, r * 4 + c + 2, r * 4 + c + 8, r * 4 + c + 10],
+                        )
+                    })
+                }),
+            ),
+        ];
+        let givens = [
+            "................",
+            BOTTOM_HALF,
+            "1...............",
+            "..3..........2..",
+        ];
+        for (flag, ok) in &flags {
+            for givens in givens {
+                let data = with(classic(4, givens), flag, json!(true));
+                assert_eq!(
+                    solve_fpuzzles(&data),
+                    Ok(expected(givens, ok)),
+                    "{} with {}",
+                    flag,
+                    givens
+                );
+                // Turning a flag off is the same as not having it
+                let data = with(classic(4, givens), flag, json!(false));
+                assert_eq!(solve_fpuzzles(&data), Ok(expected(givens, |_| true)));
+            }
+        }
+    }
+
+    #[test]
+    fn bottom_half_is_ambiguous() {
+        assert_eq!(
+            solve_fpuzzles(&classic(4, BOTTOM_HALF)),
+            Ok(Solutions::Multiple)
+        );
+    }
+
+    #[test]
+    fn killer_cages() {
+        let cage = |sum: Value| json!([{ "cells": ["R1C1", "R1C2"], "value": sum }]);
+        let data = with(classic(4, BOTTOM_HALF), "killercage", cage(json!(3)));
+        assert_eq!(
+            solve_fpuzzles(&data),
+            Ok(Solutions::Unique(SOLUTION.to_vec()))
+        );
+        // F-Puzzles sometimes gives the sum as a string
+        let data = with(classic(4, BOTTOM_HALF), "killercage", cage(json!("3")));
+        assert_eq!(
+            solve_fpuzzles(&data),
+            Ok(Solutions::Unique(SOLUTION.to_vec()))
+        );
+        let data = with(classic(4, BOTTOM_HALF), "killercage", cage(json!(4)));
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+        // A cage with no sum only keeps its digits distinct
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "killercage",
+            json!([{ "cells": ["R1C1", "R2C2"] }]),
+        );
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::Multiple));
+    }
+
+    #[test]
+    fn thermometers() {
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "thermometer",
+            json!([{ "lines": [["R1C1", "R1C2", "R1C3"]] }]),
+        );
+        assert_eq!(
+            solve_fpuzzles(&data),
+            Ok(Solutions::Unique(SOLUTION.to_vec()))
+        );
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "thermometer",
+            json!([{ "lines": [["R1C2", "R1C1", "R1C3"]] }]),
+        );
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn arrows() {
+        // R1C4 is the sum of R1C1 and R2C3, which always hold the same digit
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "arrow",
+            json!([{ "cells": ["R1C4"], "lines": [["R1C4", "R1C1", "R2C3"]] }]),
+        );
+        assert_eq!(
+            solve_fpuzzles(&data),
+            Ok(Solutions::Unique(vec![
+                1, 4, 3, 2, 3, 2, 1, 4, 2, 1, 4, 3, 4, 3, 2, 1
+            ]))
+        );
+        // R1C1 and R1C3 always differ
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "arrow",
+            json!([{ "cells": ["R1C3"], "lines": [["R1C3", "R1C1"]] }]),
+        );
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn odd_and_even() {
+        // The bottom half forces R1C1 to be odd
+        let data = with(classic(4, BOTTOM_HALF), "even", json!([{ "cell": "R1C1" }]));
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+        let data = with(classic(4, BOTTOM_HALF), "odd", json!([{ "cell": "R1C1" }]));
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::Multiple));
+        // Only two digits are even, so three even cells can't share a row
+        let data = with(
+            classic(4, &".".repeat(16)),
+            "even",
+            json!([{ "cell": "R1C1" }, { "cell": "R1C2" }, { "cell": "R1C3" }]),
+        );
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+        let data = with(
+            classic(4, &".".repeat(16)),
+            "even",
+            json!([{ "cell": "R1C1" }, { "cell": "R1C2" }]),
+        );
+        let data = with(data, "odd", json!([{ "cell": "R1C3" }]));
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::Multiple));
+    }
+
+    #[test]
+    fn extra_regions() {
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "extraregion",
+            json!([{ "cells": ["R1C1", "R2C3"] }]),
+        );
+        // R1C1 and R2C3 always hold the same digit
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn unsupported_keys() {
+        let data = with(
+            classic(4, BOTTOM_HALF),
+            "littlekillersum",
+            json!([{ "cells": ["R1C1"], "value": 3 }]),
+        );
+        assert_eq!(
+            solve_fpuzzles(&data),
+            Err(SolverError::Unsupported("littlekillersum".to_string()))
+        );
+        let data = with(classic(4, BOTTOM_HALF), "palindrome", json!(true));
+        assert!(matches!(
+            solve_fpuzzles(&data),
+            Err(SolverError::Unsupported(_))
+        ));
+        // Unsupported constraints which aren't in use are fine
+        let data = with(classic(4, BOTTOM_HALF), "littlekillersum", json!([]));
+        let data = with(data, "palindrome", json!(false));
+        let data = with(data, "ratio", Value::Null);
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::Multiple));
+        // As are cosmetic ones
+        let data = with(classic(4, BOTTOM_HALF), "title", json!("A puzzle"));
+        let data = with(data, "line", json!([{ "lines": [["R1C1", "R1C2"]] }]));
+        assert_eq!(solve_fpuzzles(&data), Ok(Solutions::Multiple));
+    }
+
+    #[test]
+    fn bad_sizes() {
+        for size in [0, 17, 100] {
+            let data = json!({ "size": size });
+            assert!(
+                matches!(solve_fpuzzles(&data), Err(SolverError::Invalid(_))),
+                "size {}",
+                size
+            );
+        }
+        assert!(matches!(
+            solve_fpuzzles(&json!([])),
+            Err(SolverError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn bad_givens_and_cells() {
+        let data = json!({ "size": 4, "grid": [[{ "value": 5, "given": true }]] });
+        assert!(matches!(
+            solve_fpuzzles(&data),
+            Err(SolverError::Invalid(_))
+        ));
+        for cell in ["R0C1", "R5C1", "R1C5", "X1C1", "R1", "RaCb"] {
+            let data = with(
+                classic(4, BOTTOM_HALF),
+                "killercage",
+                json!([{ "cells": [cell], "value": 1 }]),
+            );
+            assert!(
+                matches!(solve_fpuzzles(&data), Err(SolverError::Invalid(_))),
+                "{}",
+                cell
+            );
+        }
+    }
+
+    #[test]
+    fn box_heights() {
+        assert_eq!(box_height(4), 2);
+        assert_eq!(box_height(6), 2);
+        assert_eq!(box_height(8), 2);
+        assert_eq!(box_height(9), 3);
+        assert_eq!(box_height(12), 3);
+        assert_eq!(box_height(16), 4);
+        assert_eq!(box_height(7), 1);
+    }
+}
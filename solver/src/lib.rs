@@ -0,0 +1,179 @@
+//! Sudoku solver for Linkdoku
+//!
+//! This is a straightforward backtracking solver which understands classic
+//! sudoku rules along with the more common F-Puzzles constraints.  Its main
+//! purpose is to answer the question "is it unique?" for setters, so it stops
+//! as soon as it has found a second solution.
+
+use std::{error::Error, fmt::Display, time::Instant};
+
+use serde_json::Value;
+
+mod fpuzzles;
+mod search;
+
+pub use search::Grid;
+
+/// The maximum number of search nodes we are willing to visit before giving up
+pub const DEFAULT_NODE_LIMIT: usize = 2_000_000;
+
+/// The outcome of trying to solve a puzzle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Solutions {
+    /// The puzzle has no solution at all
+    None,
+    /// The puzzle has exactly one solution, given here in row-major order
+    Unique(Vec<u8>),
+    /// The puzzle has more than one solution
+    Multiple,
+}
+
+/// Reasons the solver might not be able to give an answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SolverError {
+    /// The puzzle data could not be understood
+    Invalid(String),
+    /// The puzzle uses a constraint the solver does not understand
+    Unsupported(String),
+    /// The solver gave up after visiting too many nodes
+    TooComplex,
+    /// The solver gave up because it ran out of time
+    TimedOut,
+}
+
+impl Display for SolverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(s) => write!(f, "Invalid puzzle data: {}", s),
+            Self::Unsupported(s) => write!(f, "Unsupported constraint: {}", s),
+            Self::TooComplex => write!(f, "Puzzle too complex for the solver"),
+            Self::TimedOut => write!(f, "Solver ran out of time"),
+        }
+    }
+}
+
+impl Error for SolverError {}
+
+pub type SolverResult<T> = Result<T, SolverError>;
+
+/// Solve an F-Puzzles style puzzle, stopping once a second solution is found
+pub fn solve_fpuzzles(data: &Value) -> SolverResult<Solutions> {
+    solve_fpuzzles_with_limit(data, DEFAULT_NODE_LIMIT)
+}
+
+/// As [`solve_fpuzzles`] but with a custom limit on the number of search nodes
+pub fn solve_fpuzzles_with_limit(data: &Value, node_limit: usize) -> SolverResult<Solutions> {
+    let grid = fpuzzles::grid_from_fpuzzles(data)?;
+    grid.solve(node_limit)
+}
+
+/// As [`solve_fpuzzles_with_limit`] but also giving up at the deadline, so
+/// that the caller need not wait on, or leave running, a solver it has lost
+/// interest in
+pub fn solve_fpuzzles_until(
+    data: &Value,
+    node_limit: usize,
+    deadline: Instant,
+) -> SolverResult<Solutions> {
+    let grid = fpuzzles::grid_from_fpuzzles(data)?;
+    grid.solve_until(node_limit, Some(deadline))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// F-Puzzles data for a classic grid given as a string of digits, with
+    /// anything other than a digit being an empty cell
+    pub(crate) fn classic(size: usize, givens: &str) -> Value {
+        let digits: Vec<Option<u8>> = givens
+            .chars()
+            .map(|c| c.to_digit(10).map(|d| d as u8))
+            .collect();
+        assert_eq!(digits.len(), size * size);
+        let grid: Vec<Value> = digits
+            .chunks(size)
+            .map(|row| {
+                row.iter()
+                    .map(|d| match d {
+                        Some(d) => json!({ "value": d, "given": true }),
+                        None => json!({}),
+                    })
+                    .collect()
+            })
+            .collect();
+        json!({ "size": size, "grid": grid })
+    }
+
+    fn digits(s: &str) -> Vec<u8> {
+        s.chars().map(|c| c.to_digit(10).unwrap() as u8).collect()
+    }
+
+    const PUZZLE: &str =
+        "53..7....6..195....98....6.8...6...34..8.3..17...2...6.6....28....419..5....8..79";
+    const SOLUTION: &str =
+        "534678912672195348198342567859761423426853791713924856961537284287419635345286179";
+
+    #[test]
+    fn classic_unique() {
+        assert_eq!(
+            solve_fpuzzles(&classic(9, PUZZLE)),
+            Ok(Solutions::Unique(digits(SOLUTION)))
+        );
+    }
+
+    #[test]
+    fn classic_no_solution() {
+        // R1C9 can only be a 9, which is already given in its column
+        let mut givens = format!("12345678.........9{}", ".".repeat(63));
+        assert_eq!(solve_fpuzzles(&classic(9, &givens)), Ok(Solutions::None));
+        // Fine until the solver tries to fill the grid
+        givens = PUZZLE.replacen("53", "35", 1);
+        assert_eq!(solve_fpuzzles(&classic(9, &givens)), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn classic_many_solutions() {
+        assert_eq!(
+            solve_fpuzzles(&classic(9, &".".repeat(81))),
+            Ok(Solutions::Multiple)
+        );
+        // Only the first three rows of givens leaves the puzzle ambiguous
+        let givens = format!("{}{}", &PUZZLE[..27], ".".repeat(54));
+        assert_eq!(
+            solve_fpuzzles(&classic(9, &givens)),
+            Ok(Solutions::Multiple)
+        );
+    }
+
+    #[test]
+    fn node_limit() {
+        assert_eq!(
+            solve_fpuzzles_with_limit(&classic(9, &".".repeat(81)), 10),
+            Err(SolverError::TooComplex)
+        );
+        assert_eq!(
+            solve_fpuzzles_with_limit(&classic(9, PUZZLE), 10_000),
+            Ok(Solutions::Unique(digits(SOLUTION)))
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        assert_eq!(
+            solve_fpuzzles_until(
+                &classic(9, &".".repeat(81)),
+                DEFAULT_NODE_LIMIT,
+                Instant::now()
+            ),
+            Err(SolverError::TimedOut)
+        );
+        let later = Instant::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            solve_fpuzzles_until(&classic(9, PUZZLE), DEFAULT_NODE_LIMIT, later),
+            Ok(Solutions::Unique(digits(SOLUTION)))
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! The backtracking search itself
+
+use std::time::Instant;
+
+use crate::{Solutions, SolverError, SolverResult};
+
+/// How many search nodes we visit between looking at the clock
+const CLOCK_INTERVAL: usize = 256;
+
+/// A killer cage, the cells within it are also distinct
+#[derive(Debug, Clone)]
+pub(crate) struct Cage {
+    pub(crate) cells: Vec<usize>,
+    pub(crate) sum: Option<u32>,
+}
+
+/// An arrow, the digits on the shaft sum to the number in the bulb
+#[derive(Debug, Clone)]
+pub(crate) struct Arrow {
+    pub(crate) bulb: Vec<usize>,
+    pub(crate) shaft: Vec<usize>,
+}
+
+/// A puzzle grid ready to be solved
+///
+/// Digits are `1..=size` and cells are numbered in row-major order.
+/// Candidates are kept as bitmasks where bit `n` means digit `n` is possible.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub(crate) size: usize,
+    pub(crate) givens: Vec<u8>,
+    pub(crate) candidates: Vec<u32>,
+    pub(crate) peers: Vec<Vec<usize>>,
+    pub(crate) neighbours: Vec<Vec<usize>>,
+    pub(crate) cages: Vec<Cage>,
+    pub(crate) thermos: Vec<Vec<usize>>,
+    pub(crate) arrows: Vec<Arrow>,
+}
+
+struct SearchState {
+    values: Vec<u8>,
+    candidates: Vec<u32>,
+}
+
+struct Search<'a> {
+    grid: &'a Grid,
+    nodes: usize,
+    node_limit: usize,
+    deadline: Option<Instant>,
+    found: usize,
+    first: Option<Vec<u8>>,
+}
+
+impl Grid {
+    /// Create an empty grid of the given size with no constraints at all
+    pub(crate) fn new(size: usize) -> Self {
+        let all = ((1u32 << (size + 1)) - 1) & !1;
+        Self {
+            size,
+            givens: vec![0; size * size],
+            candidates: vec![all; size * size],
+            peers: vec![Vec::new(); size * size],
+            neighbours: vec![Vec::new(); size * size],
+            cages: Vec::new(),
+            thermos: Vec::new(),
+            arrows: Vec::new(),
+        }
+    }
+
+    /// Require that all the given cells contain different digits
+    pub(crate) fn add_distinct(&mut self, cells: &[usize]) {
+        for &a in cells {
+            for &b in cells {
+                if a != b && !self.peers[a].contains(&b) {
+                    self.peers[a].push(b);
+                }
+            }
+        }
+    }
+
+    /// Require that two cells contain different digits
+    pub(crate) fn add_distinct_pair(&mut self, a: usize, b: usize) {
+        self.add_distinct(&[a, b]);
+    }
+
+    /// Require that two cells do not contain consecutive digits
+    pub(crate) fn add_nonconsecutive_pair(&mut self, a: usize, b: usize) {
+        if !self.neighbours[a].contains(&b) {
+            self.neighbours[a].push(b);
+            self.neighbours[b].push(a);
+        }
+    }
+
+    /// Solve the grid, giving up after visiting `node_limit` search nodes
+    pub fn solve(&self, node_limit: usize) -> SolverResult<Solutions> {
+        self.solve_until(node_limit, None)
+    }
+
+    /// As [`Grid::solve`], but also giving up once the deadline, if any, passes
+    pub fn solve_until(
+        &self,
+        node_limit: usize,
+        deadline: Option<Instant>,
+    ) -> SolverResult<Solutions> {
+        let mut state = SearchState {
+            values: vec![0; self.size * self.size],
+            candidates: self.candidates.clone(),
+        };
+        let mut search = Search {
+            grid: self,
+            nodes: 0,
+            node_limit,
+            deadline,
+            found: 0,
+            first: None,
+        };
+        let consistent = self
+            .givens
+            .iter()
+            .enumerate()
+            .filter(|(_, &digit)| digit != 0)
+            .all(|(idx, &digit)| self.assign(&mut state, idx, digit));
+        if consistent {
+            search.run(state)?;
+        }
+        Ok(match search.found {
+            0 => Solutions::None,
+            1 => Solutions::Unique(search.first.expect("Solution was recorded")),
+            _ => Solutions::Multiple,
+        })
+    }
+
+    /// Place a digit, pruning candidates from related cells.  Returns false if
+    /// this immediately results in a contradiction
+    fn assign(&self, state: &mut SearchState, idx: usize, digit: u8) -> bool {
+        let bit = 1u32 << digit;
+        if state.candidates[idx] & bit == 0 {
+            return false;
+        }
+        state.values[idx] = digit;
+        state.candidates[idx] = bit;
+        for &peer in &self.peers[idx] {
+            if state.values[peer] == digit {
+                return false;
+            }
+            state.candidates[peer] &= !bit;
+            if state.values[peer] == 0 && state.candidates[peer] == 0 {
+                return false;
+            }
+        }
+        let adjacent = (bit << 1) | (bit >> 1);
+        for &neighbour in &self.neighbours[idx] {
+            if state.values[neighbour] != 0 {
+                if state.values[neighbour].abs_diff(digit) == 1 {
+                    return false;
+                }
+                continue;
+            }
+            state.candidates[neighbour] &= !adjacent;
+            if state.candidates[neighbour] == 0 {
+                return false;
+            }
+        }
+        self.cages_ok(state) && self.thermos_ok(state) && self.arrows_ok(state)
+    }
+
+    fn cages_ok(&self, state: &SearchState) -> bool {
+        self.cages.iter().all(|cage| {
+            let sum = match cage.sum {
+                Some(sum) => sum,
+                None => return true,
+            };
+            let placed: u32 = cage.cells.iter().map(|&c| state.values[c] as u32).sum();
+            let empty = cage.cells.iter().filter(|&&c| state.values[c] == 0).count() as u32;
+            if empty == 0 {
+                placed == sum
+            } else {
+                placed + empty <= sum && placed + empty * self.size as u32 >= sum
+            }
+        })
+    }
+
+    fn thermos_ok(&self, state: &SearchState) -> bool {
+        self.thermos.iter().all(|thermo| {
+            let placed: Vec<(usize, u8)> = thermo
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| state.values[c] != 0)
+                .map(|(pos, &c)| (pos, state.values[c]))
+                .collect();
+            placed
+                .windows(2)
+                .all(|w| usize::from(w[1].1).saturating_sub(usize::from(w[0].1)) >= w[1].0 - w[0].0)
+        })
+    }
+
+    fn arrows_ok(&self, state: &SearchState) -> bool {
+        self.arrows.iter().all(|arrow| {
+            let placed: u32 = arrow.shaft.iter().map(|&c| state.values[c] as u32).sum();
+            let empty = arrow
+                .shaft
+                .iter()
+                .filter(|&&c| state.values[c] == 0)
+                .count() as u32;
+            let bulb_full = arrow.bulb.iter().all(|&c| state.values[c] != 0);
+            if bulb_full {
+                let bulb = arrow
+                    .bulb
+                    .iter()
+                    .fold(0u32, |acc, &c| acc * 10 + state.values[c] as u32);
+                if empty == 0 {
+                    placed == bulb
+                } else {
+                    placed + empty <= bulb && placed + empty * self.size as u32 >= bulb
+                }
+            } else {
+                let max_bulb = arrow
+                    .bulb
+                    .iter()
+                    .fold(0u32, |acc, _| acc * 10 + self.size as u32);
+                placed + empty <= max_bulb
+            }
+        })
+    }
+}
+
+impl<'a> Search<'a> {
+    fn run(&mut self, state: SearchState) -> SolverResult<()> {
+        self.nodes += 1;
+        if self.nodes > self.node_limit {
+            return Err(SolverError::TooComplex);
+        }
+        if let Some(deadline) = self.deadline {
+            if (self.nodes - 1) % CLOCK_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(SolverError::TimedOut);
+            }
+        }
+        // Pick the empty cell with the fewest candidates
+        let next = state
+            .values
+            .iter()
+            .enumerate()
+            .filter(|(_, &v)| v == 0)
+            .min_by_key(|(idx, _)| state.candidates[*idx].count_ones())
+            .map(|(idx, _)| idx);
+        let idx = match next {
+            Some(idx) => idx,
+            None => {
+                self.found += 1;
+                if self.first.is_none() {
+                    self.first = Some(state.values);
+                }
+                return Ok(());
+            }
+        };
+        let mut remaining = state.candidates[idx];
+        while remaining != 0 && self.found < 2 {
+            let digit = remaining.trailing_zeros() as u8;
+            remaining &= remaining - 1;
+            let mut next = SearchState {
+                values: state.values.clone(),
+                candidates: state.candidates.clone(),
+            };
+            if self.grid.assign(&mut next, idx, digit) {
+                self.run(next)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 latin square, which has two solutions: 12/21 and 21/12
+    fn latin() -> Grid {
+        let mut grid = Grid::new(2);
+        grid.add_distinct(&[0, 1]);
+        grid.add_distinct(&[2, 3]);
+        grid.add_distinct(&[0, 2]);
+        grid.add_distinct(&[1, 3]);
+        grid
+    }
+
+    #[test]
+    fn empty_grid_candidates() {
+        let grid = Grid::new(4);
+        assert!(grid.candidates.iter().all(|&c| c == 0b11110));
+    }
+
+    #[test]
+    fn latin_square() {
+        assert_eq!(latin().solve(100), Ok(Solutions::Multiple));
+        let mut grid = latin();
+        grid.givens[0] = 1;
+        assert_eq!(grid.solve(100), Ok(Solutions::Unique(vec![1, 2, 2, 1])));
+        grid.givens[1] = 1;
+        assert_eq!(grid.solve(100), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn cages() {
+        let mut grid = latin();
+        grid.cages.push(Cage {
+            cells: vec![0, 3],
+            sum: Some(2),
+        });
+        assert_eq!(grid.solve(100), Ok(Solutions::Unique(vec![1, 2, 2, 1])));
+        grid.cages[0].sum = Some(5);
+        assert_eq!(grid.solve(100), Ok(Solutions::None));
+        grid.cages[0].sum = None;
+        assert_eq!(grid.solve(100), Ok(Solutions::Multiple));
+    }
+
+    #[test]
+    fn thermos() {
+        let mut grid = latin();
+        grid.thermos.push(vec![1, 0]);
+        assert_eq!(grid.solve(100), Ok(Solutions::Unique(vec![2, 1, 1, 2])));
+        grid.thermos.push(vec![0, 1]);
+        assert_eq!(grid.solve(100), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn arrows() {
+        let mut grid = latin();
+        grid.arrows.push(Arrow {
+            bulb: vec![0],
+            shaft: vec![3],
+        });
+        assert_eq!(grid.solve(100), Ok(Solutions::Multiple));
+        grid.arrows.push(Arrow {
+            bulb: vec![0],
+            shaft: vec![1],
+        });
+        assert_eq!(grid.solve(100), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn nonconsecutive() {
+        let mut grid = latin();
+        grid.add_nonconsecutive_pair(0, 1);
+        assert_eq!(grid.solve(100), Ok(Solutions::None));
+    }
+
+    #[test]
+    fn node_limit() {
+        assert_eq!(latin().solve(1), Err(SolverError::TooComplex));
+        assert_eq!(latin().solve(100), Ok(Solutions::Multiple));
+    }
+
+    #[test]
+    fn deadline() {
+        let past = Instant::now();
+        assert_eq!(
+            latin().solve_until(100, Some(past)),
+            Err(SolverError::TimedOut)
+        );
+        let future = Instant::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            latin().solve_until(100, Some(future)),
+            Ok(Solutions::Multiple)
+        );
+    }
+}
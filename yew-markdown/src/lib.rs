@@ -4,4 +4,5 @@
 
 pub mod editor;
 pub mod render;
+pub mod sanitise;
 pub mod xform;
@@ -5,26 +5,36 @@ use pulldown_cmark::{
 };
 use yew::prelude::*;
 
-use crate::xform::{TransformRequest, Transformer};
+use crate::{
+    sanitise::{sanitise, MarkdownPolicy},
+    xform::{TransformRequest, Transformer},
+};
 
 #[derive(Properties, PartialEq)]
 pub struct MarkdownRenderProps {
     pub markdown: String,
     pub transformer: Option<Transformer>,
+    /// What raw HTML and which URLs we let through, see [`crate::sanitise`]
+    #[prop_or_default]
+    pub policy: MarkdownPolicy,
 }
 
-fn bl_cb(link: BrokenLink<'_>) -> Option<(CowStr<'_>, CowStr<'_>)> {
-    Some((link.reference.clone(), link.reference.clone()))
-}
-
-#[function_component(MarkdownRender)]
-pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
+/// The markdown extensions we support
+pub(crate) fn markdown_options() -> Options {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_SMART_PUNCTUATION);
     options.insert(Options::ENABLE_STRIKETHROUGH);
     options.insert(Options::ENABLE_TABLES);
     options.insert(Options::ENABLE_TASKLISTS);
+    options
+}
+
+fn bl_cb(link: BrokenLink<'_>) -> Option<(CowStr<'_>, CowStr<'_>)> {
+    Some((link.reference.clone(), link.reference.clone()))
+}
 
+#[function_component(MarkdownRender)]
+pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
     let mut stack_depth = 0usize;
     let mut inlines: Vec<Vec<Html>> = vec![Vec::new()];
 
@@ -34,14 +44,17 @@ pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
 
     let mut bl_cb = &bl_cb;
 
-    let events = Parser::new_with_broken_link_callback(
-        &props.markdown,
-        options,
-        if props.transformer.is_some() {
-            Some(&mut bl_cb)
-        } else {
-            None
-        },
+    let events = sanitise(
+        Parser::new_with_broken_link_callback(
+            &props.markdown,
+            markdown_options(),
+            if props.transformer.is_some() {
+                Some(&mut bl_cb)
+            } else {
+                None
+            },
+        ),
+        &props.policy,
     );
 
     for event in events {
@@ -224,8 +237,12 @@ pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
                             None
                         };
                         replacement.unwrap_or_else(|| {
-                            html! {
-                                <a href={url} title={title}>{content}</a>
+                            if props.policy.url_allowed(&url) {
+                                html! {
+                                    <a href={url} title={title}>{content}</a>
+                                }
+                            } else {
+                                content
                             }
                         })
                     }
@@ -253,8 +270,12 @@ pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
                             None
                         };
                         replacement.unwrap_or_else(|| {
-                            html! {
-                                <img src={url} title={title} />
+                            if props.policy.url_allowed(&url) {
+                                html! {
+                                    <img src={url} title={title} />
+                                }
+                            } else {
+                                content
                             }
                         })
                     }
@@ -280,15 +301,9 @@ pub fn render_markdown_block(props: &MarkdownRenderProps) -> Html {
                     <code>{content.into_string()}</code>
                 });
             }
-            Event::Html(content) => {
-                // Process plain HTML content.
-                // Since we're not particularly interested in people messing with us, we won't allow this
-                // so instead we'll render it as preformatted text
-                inlines[stack_depth].push(html! {
-                    <pre><code>
-                        {content.into_string()}
-                    </code></pre>
-                });
+            Event::Html(_) => {
+                // Raw HTML never gets past sanitise(), which shows it as text
+                // or drops it according to the policy
             }
             Event::FootnoteReference(_noteref) => {
                 // We do not enable footnotes, so this can't happen
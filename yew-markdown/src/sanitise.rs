@@ -0,0 +1,258 @@
+//! Sanitising of markdown from untrusted users
+//!
+//! Markdown we render comes from arbitrary users, so before rendering we pass
+//! the parsed events through [`sanitise`] according to a [`MarkdownPolicy`]:
+//!
+//! * Raw HTML is never interpreted.  It is either shown as text, block HTML
+//!   as a code block, or dropped entirely.
+//! * Links and images are only kept if their URL is relative or uses one of
+//!   the allowed schemes.  A dropped link leaves its text behind, and a
+//!   dropped image leaves its alt text.
+//!
+//! Links which are left for the [`Transformer`](crate::xform::Transformer)
+//! to resolve are passed through, and the renderer checks their URL if the
+//! transformer declines them.
+
+use linkdoku_common::{url_scheme_allowed, SAFE_URL_SCHEMES};
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Tag};
+
+/// What to do with raw HTML in markdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RawHtml {
+    /// Show the HTML as text, so that the author can see it did nothing
+    #[default]
+    Escape,
+    /// Drop the HTML entirely
+    Strip,
+}
+
+/// How much of what users write in markdown we let through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkdownPolicy {
+    pub raw_html: RawHtml,
+    /// URL schemes allowed in links and images, compared ignoring case.
+    /// Relative URLs are always allowed.
+    pub url_schemes: Vec<String>,
+}
+
+impl Default for MarkdownPolicy {
+    fn default() -> Self {
+        Self {
+            raw_html: RawHtml::default(),
+            url_schemes: SAFE_URL_SCHEMES.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl MarkdownPolicy {
+    /// Whether a link or image may point at the given URL
+    pub fn url_allowed(&self, url: &str) -> bool {
+        url_scheme_allowed(url, &self.url_schemes)
+    }
+
+    fn tag_allowed(&self, tag: &Tag) -> bool {
+        match tag {
+            Tag::Link(LinkType::Email, url, _) => self.url_allowed(&format!("mailto:{}", url)),
+            Tag::Link(
+                LinkType::ReferenceUnknown | LinkType::CollapsedUnknown | LinkType::ShortcutUnknown,
+                _,
+                _,
+            )
+            | Tag::Image(
+                LinkType::ReferenceUnknown | LinkType::CollapsedUnknown | LinkType::ShortcutUnknown,
+                _,
+                _,
+            ) => true,
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => self.url_allowed(url),
+            _ => true,
+        }
+    }
+}
+
+fn push_code_block<'a>(events: &mut Vec<Event<'a>>, content: String) {
+    events.push(Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)));
+    events.push(Event::Text(content.into()));
+    events.push(Event::End(Tag::CodeBlock(CodeBlockKind::Indented)));
+}
+
+/// Apply a policy to parsed markdown
+pub fn sanitise<'a>(
+    events: impl IntoIterator<Item = Event<'a>>,
+    policy: &MarkdownPolicy,
+) -> Vec<Event<'a>> {
+    let mut ret = Vec::new();
+    let mut depth = 0usize;
+    // Block HTML arrives a line at a time, so we gather it up
+    let mut raw_block: Option<String> = None;
+
+    for event in events {
+        if let Event::Html(html) = &event {
+            if depth == 0 {
+                if policy.raw_html == RawHtml::Escape {
+                    raw_block.get_or_insert_with(String::new).push_str(html);
+                }
+                continue;
+            }
+        }
+        if let Some(block) = raw_block.take() {
+            push_code_block(&mut ret, block);
+        }
+        match event {
+            Event::Start(tag) => {
+                depth += 1;
+                if policy.tag_allowed(&tag) {
+                    ret.push(Event::Start(tag));
+                }
+            }
+            Event::End(tag) => {
+                depth = depth.saturating_sub(1);
+                if policy.tag_allowed(&tag) {
+                    ret.push(Event::End(tag));
+                }
+            }
+            Event::Html(html) => {
+                if policy.raw_html == RawHtml::Escape {
+                    ret.push(Event::Text(html));
+                }
+            }
+            event => ret.push(event),
+        }
+    }
+    if let Some(block) = raw_block.take() {
+        push_code_block(&mut ret, block);
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{html::push_html, Parser};
+
+    use super::*;
+    use crate::render::markdown_options;
+
+    fn render_with(markdown: &str, policy: &MarkdownPolicy) -> String {
+        let events = sanitise(Parser::new_ext(markdown, markdown_options()), policy);
+        let mut ret = String::new();
+        push_html(&mut ret, events.into_iter());
+        ret
+    }
+
+    fn render(markdown: &str) -> String {
+        render_with(markdown, &MarkdownPolicy::default())
+    }
+
+    const HOSTILE: &[&str] = &[
+        "<script>alert(1)</script>",
+        "<div>\n<script>alert(1)</script>\n</div>",
+        "Hello <script>alert(1)</script> world",
+        "Hello <img src=x onerror=alert(1)> world",
+        "<iframe src=\"https://example.com\"></iframe>",
+        "[click](javascript:alert(1))",
+        "[click](JaVaScRiPt:alert(1))",
+        "[click](&#106;avascript:alert(1))",
+        "[click](<java&#9;script:alert(1)>)",
+        "[click][evil]\n\n[evil]: javascript:alert(1)",
+        "<javascript:alert(1)>",
+        "![pic](javascript:alert(1))",
+        "![pic](data:image/svg+xml;base64,PHN2Zz48L3N2Zz4=)",
+        "[click](data:text/html,<script>alert(1)</script>)",
+        "[click](vbscript:msgbox)",
+    ];
+
+    #[test]
+    fn hostile_markdown_cannot_inject_script() {
+        for markdown in HOSTILE {
+            let html = render(markdown);
+            assert!(!html.contains("<script"), "{:?} gave {}", markdown, html);
+            assert!(!html.contains("<img"), "{:?} gave {}", markdown, html);
+            assert!(!html.contains("<iframe"), "{:?} gave {}", markdown, html);
+            assert!(!html.contains("href="), "{:?} gave {}", markdown, html);
+        }
+    }
+
+    #[test]
+    fn stripped_html_leaves_nothing_behind() {
+        let policy = MarkdownPolicy {
+            raw_html: RawHtml::Strip,
+            ..MarkdownPolicy::default()
+        };
+        for markdown in HOSTILE {
+            let html = render_with(markdown, &policy);
+            assert!(!html.contains("script>"), "{:?} gave {}", markdown, html);
+            assert!(!html.contains("onerror"), "{:?} gave {}", markdown, html);
+        }
+    }
+
+    #[test]
+    fn raw_html_is_shown_as_text() {
+        let html = render("<script>alert(1)</script>");
+        assert!(html.starts_with("<pre><code>"), "{}", html);
+        assert!(
+            html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"),
+            "{}",
+            html
+        );
+        let html = render("Hello <b>world</b>");
+        assert!(html.starts_with("<p>"), "{}", html);
+        assert!(html.contains("Hello &lt;b&gt;world&lt;/b&gt;"), "{}", html);
+    }
+
+    #[test]
+    fn dropped_links_and_images_keep_their_text() {
+        assert_eq!(render("[click](javascript:alert(1))"), "<p>click</p>\n");
+        assert_eq!(
+            render("![a picture](data:image/png;base64,AAAA)"),
+            "<p>a picture</p>\n"
+        );
+    }
+
+    #[test]
+    fn no_unexpected_attributes() {
+        let html = render("# Heading {#x onclick=alert(1)}");
+        assert!(html.starts_with("<h1>"), "{}", html);
+        let html = render("[click](https://example.com \"a\\\" onclick=\\\"alert(1)\")");
+        assert!(!html.contains("\" onclick"), "{}", html);
+    }
+
+    #[test]
+    fn safe_urls_are_kept() {
+        assert_eq!(
+            render("[site](https://example.com/puzzle)"),
+            "<p><a href=\"https://example.com/puzzle\">site</a></p>\n"
+        );
+        assert_eq!(
+            render("[puzzle](/-/puzzle/foo)"),
+            "<p><a href=\"/-/puzzle/foo\">puzzle</a></p>\n"
+        );
+        assert_eq!(
+            render("[section](#rules)"),
+            "<p><a href=\"#rules\">section</a></p>\n"
+        );
+        assert_eq!(
+            render("<someone@example.com>"),
+            "<p><a href=\"mailto:someone@example.com\">someone@example.com</a></p>\n"
+        );
+        assert!(render("![grid](https://example.com/grid.png)")
+            .contains("<img src=\"https://example.com/grid.png\" alt=\"grid\""));
+    }
+
+    #[test]
+    fn schemes_are_configurable() {
+        let policy = MarkdownPolicy {
+            url_schemes: vec!["https".to_string()],
+            ..MarkdownPolicy::default()
+        };
+        assert!(policy.url_allowed("https://example.com"));
+        assert!(policy.url_allowed("HTTPS://example.com"));
+        assert!(!policy.url_allowed("http://example.com"));
+        assert!(!policy.url_allowed("mailto:someone@example.com"));
+        assert!(policy.url_allowed("relative/path:with-colon"));
+        assert!(!policy.url_allowed(" \tjava\nscript:alert(1)"));
+        assert_eq!(
+            render_with("[site](http://example.com)", &policy),
+            "<p>site</p>\n"
+        );
+    }
+}